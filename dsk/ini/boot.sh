@@ -1,5 +1,7 @@
 shell /ini/palettes/gruvbox-dark.sh
 read /ini/fonts/zap-light-8x16.psf => /dev/vga/font
+net firewall --load
+net config --load
 read /ini/banner.txt
 user login
 shell
@@ -0,0 +1,9 @@
+(load "/lib/lisp/core.lsp")
+
+# The request is given in the args: method, path, query, and headers
+
+(print "Content-Type: text/html")
+(print "")
+(print "<h1>MOROS</h1>")
+(print (string "<p>Uptime: " (trunc (uptime)) " seconds</p>"))
+(print (string "<p>Request: " (first args) " " (second args) "</p>"))
@@ -135,7 +135,15 @@ pub fn create_device(path: &str, name: &str) -> Option<usize> {
 }
 
 fn device_buffer(name: &str) -> Result<Vec<u8>, ()> {
-    let arg = if name.starts_with("ata-") { "ata" } else { name };
+    let arg = if name.starts_with("ata-") {
+        "ata"
+    } else if name.starts_with("ahci-") {
+        "ahci"
+    } else if name.starts_with("virtio-") {
+        "virtio"
+    } else {
+        name
+    };
     let dev = device_type(arg)?;
     let mut buf = dev.buf();
     if name.starts_with("ata-") {
@@ -147,6 +155,15 @@ fn device_buffer(name: &str) -> Result<Vec<u8>, ()> {
             _ => return Err(()),
         }
     }
+    if let Some(port) = name.strip_prefix("ahci-") {
+        match port.parse::<u8>() {
+            Ok(port) if port < 32 => buf[1] = port,
+            _ => return Err(()),
+        }
+    }
+    if let Some(id) = name.strip_prefix("virtio-") {
+        buf[1] = id.parse::<u8>().or(Err(()))?;
+    }
     Ok(buf)
 }
 
@@ -161,8 +178,11 @@ fn device_type(name: &str) -> Result<DeviceType, ()> {
         "rtc"      => Ok(DeviceType::RTC),
         "tcp"      => Ok(DeviceType::TcpSocket),
         "udp"      => Ok(DeviceType::UdpSocket),
+        "icmp"     => Ok(DeviceType::IcmpSocket),
         "font"     => Ok(DeviceType::VgaFont),
         "ata"      => Ok(DeviceType::Drive),
+        "ahci"     => Ok(DeviceType::AhciDrive),
+        "virtio"   => Ok(DeviceType::VirtioDrive),
         _          => Err(()),
     }
 }
@@ -0,0 +1,507 @@
+use crate::api::syscall;
+use crate::api::tls;
+use crate::sys;
+use crate::sys::fs::OpenFlag;
+use crate::sys::net::socket::{SocketError, SocketOption};
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::str;
+use smoltcp::wire::IpAddress;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Connect,
+    Read,
+    Write,
+    Timeout,
+    Interrupted,
+    InvalidResponse,
+    Tls(tls::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct URL {
+    pub is_tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl URL {
+    pub fn parse(url: &str) -> Option<Self> {
+        let (is_tls, url) = if let Some(url) = url.strip_prefix("http://") {
+            (false, url)
+        } else if let Some(url) = url.strip_prefix("https://") {
+            (true, url)
+        } else {
+            return None;
+        };
+        let default_port = if is_tls { 443 } else { 80 };
+        let (server, path) = match url.find('/') {
+            Some(i) => url.split_at(i),
+            None => (url, "/"),
+        };
+        // IPv6 addresses must be enclosed in brackets: `[::1]:80`
+        let i = server.rfind(']').unwrap_or(0);
+        let (host, port) = match server[i..].find(':') {
+            Some(j) => server.split_at(i + j),
+            None => (server, ":"),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = &port[1..];
+        Some(Self {
+            is_tls,
+            host: host.into(),
+            port: port.parse().unwrap_or(default_port),
+            path: path.into(),
+        })
+    }
+
+    // Resolve the location of a redirect relative to this URL
+    pub fn join(&self, location: &str) -> Option<Self> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            Self::parse(location)
+        } else if location.starts_with('/') {
+            let mut url = self.clone();
+            url.path = location.to_string();
+            Some(url)
+        } else {
+            let mut url = self.clone();
+            let i = url.path.rfind('/').map_or(0, |i| i + 1);
+            url.path = format!("{}{}", &url.path[..i], location);
+            Some(url)
+        }
+    }
+
+    // Value of the `Host` header
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host) // IPv6 address
+        } else {
+            self.host.to_string()
+        };
+        if self.port == if self.is_tls { 443 } else { 80 } {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub version: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &str, url: &URL) -> Self {
+        let mut headers = BTreeMap::new();
+        headers.insert(
+            "User-Agent".to_string(),
+            format!("MOROS/{}", env!("CARGO_PKG_VERSION")),
+        );
+        headers.insert("Connection".to_string(), "close".to_string());
+        let mut req = Self {
+            method: method.to_string(),
+            path: String::new(),
+            query: String::new(),
+            version: "HTTP/1.1".to_string(),
+            headers,
+            body: Vec::new(),
+        };
+        req.set_url(url);
+        req
+    }
+
+    // Change the target of the request and its `Host` header
+    pub fn set_url(&mut self, url: &URL) {
+        let (path, query) = url.path.split_once('?').
+            unwrap_or((url.path.as_str(), ""));
+        self.path = path.to_string();
+        self.query = query.to_string();
+        self.headers.insert("Host".to_string(), url.authority());
+    }
+
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        Self::parse_len(buf).map(|(req, _)| req)
+    }
+
+    // Parse the request at the beginning of the buffer and return it with
+    // its length, or nothing until its head and the `Content-Length` bytes
+    // of its body have been received
+    pub fn parse_len(buf: &[u8]) -> Option<(Self, usize)> {
+        let (lines, i) = parse_head(buf)?;
+        if !buf[..i].ends_with(b"\n\n") && !buf[..i].ends_with(b"\n\r\n") {
+            return None;
+        }
+        let headers = parse_headers(&lines[1..]);
+        let len = match find_header(&headers, "Content-Length") {
+            Some(len) => len.parse().ok()?,
+            None => 0,
+        };
+        let end = i.checked_add(len).filter(|&end| end <= buf.len())?;
+        let fields: Vec<_> = lines[0].split(' ').collect();
+        let target = fields.get(1).unwrap_or(&"");
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let req = Self {
+            method: fields[0].to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            version: fields.get(2).unwrap_or(&"").to_string(),
+            headers,
+            body: buf[i..end].to_vec(),
+        };
+        Some((req, end))
+    }
+
+    pub fn header(&self, key: &str) -> Option<&String> {
+        find_header(&self.headers, key)
+    }
+
+    // Request line and headers separated by CRLF
+    pub fn head(&self) -> String {
+        let mut target = self.path.clone();
+        if !self.query.is_empty() {
+            target = format!("{}?{}", target, self.query);
+        }
+        let version = &self.version;
+        let mut head = format!("{} {} {}\r\n", self.method, target, version);
+        for (key, val) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", key, val));
+        }
+        let has_body = !self.body.is_empty() || self.method == "POST";
+        if has_body && self.header("Content-Length").is_none() {
+            let len = self.body.len();
+            head.push_str(&format!("Content-Length: {}\r\n", len));
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = self.head().into_bytes();
+        buf.extend_from_slice(&self.body);
+        buf
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub code: usize,
+    pub version: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(code: usize) -> Self {
+        Self {
+            code,
+            version: "HTTP/1.1".to_string(),
+            headers: BTreeMap::new(),
+            body: Vec::new(),
+        }
+    }
+
+    // Parse a complete response and decode its body if it was sent in chunks
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let (lines, i) = parse_head(buf)?;
+        let mut fields = lines[0].split(' ');
+        let version = fields.next()?.to_string();
+        let code = fields.next()?.parse().ok()?;
+        let mut res = Self {
+            code,
+            version,
+            headers: parse_headers(&lines[1..]),
+            body: buf[i..].to_vec(),
+        };
+        if let Some(encoding) = res.header("Transfer-Encoding") {
+            if encoding.eq_ignore_ascii_case("chunked") {
+                res.body = decode_chunked(&res.body)?;
+                res.headers.retain(|k, _|
+                    !k.eq_ignore_ascii_case("Transfer-Encoding")
+                );
+            }
+        } else if let Some(len) = res.header("Content-Length") {
+            let len = len.parse().ok()?;
+            if len < res.body.len() {
+                res.body.truncate(len);
+            }
+        }
+        Some(res)
+    }
+
+    pub fn header(&self, key: &str) -> Option<&String> {
+        find_header(&self.headers, key)
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        matches!(self.code, 301 | 302 | 303 | 307 | 308)
+    }
+
+    // Status line and headers separated by CRLF
+    pub fn head(&self) -> String {
+        let status = status_text(self.code);
+        let mut head = format!("{} {} {}\r\n", self.version, self.code, status);
+        for (key, val) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", key, val));
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = self.head().into_bytes();
+        buf.extend_from_slice(&self.body);
+        buf
+    }
+}
+
+pub fn status_text(code: usize) -> &'static str {
+    match code {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        416 => "Range Not Satisfiable",
+        500 => "Internal Server Error",
+        _ => "Unknown Error",
+    }
+}
+
+// Split the lines of the head of a message until an empty line, and return
+// them with the position of the body
+fn parse_head(buf: &[u8]) -> Option<(Vec<String>, usize)> {
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < buf.len() {
+        let j = buf[i..].iter().position(|&b| b == b'\n').
+            map_or(buf.len(), |j| i + j);
+        let line = String::from_utf8_lossy(&buf[i..j]);
+        let line = line.trim_end_matches('\r');
+        i = (j + 1).min(buf.len());
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line.to_string());
+    }
+    if lines.is_empty() {
+        None
+    } else {
+        Some((lines, i))
+    }
+}
+
+fn parse_headers(lines: &[String]) -> BTreeMap<String, String> {
+    lines.iter().filter_map(|line| {
+        let (key, val) = line.split_once(':')?;
+        Some((key.trim().to_string(), val.trim().to_string()))
+    }).collect()
+}
+
+fn find_header<'a>(
+    headers: &'a BTreeMap<String, String>,
+    key: &str
+) -> Option<&'a String> {
+    headers.iter().find_map(|(k, v)|
+        if k.eq_ignore_ascii_case(key) { Some(v) } else { None }
+    )
+}
+
+// Each chunk is prefixed by its size in hexadecimal followed by optional
+// extensions, and the last chunk has a size of zero
+pub fn decode_chunked(buf: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut i = 0;
+    loop {
+        let j = i + buf[i..].iter().position(|&b| b == b'\n')?;
+        let line = str::from_utf8(&buf[i..j]).ok()?;
+        let size = line.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        i = j + 1;
+        if size == 0 {
+            return Some(body);
+        }
+        let end = i.checked_add(size).filter(|&end| end <= buf.len())?;
+        body.extend_from_slice(&buf[i..end]);
+        i = end;
+        if buf[i..].starts_with(b"\r\n") {
+            i += 2;
+        } else if buf[i..].starts_with(b"\n") {
+            i += 1;
+        }
+    }
+}
+
+pub fn encode_chunked(buf: &[u8], chunk_size: usize) -> Vec<u8> {
+    let mut res = Vec::new();
+    for chunk in buf.chunks(chunk_size) {
+        res.extend_from_slice(format!("{:X}\r\n", chunk.len()).as_bytes());
+        res.extend_from_slice(chunk);
+        res.extend_from_slice(b"\r\n");
+    }
+    res.extend_from_slice(b"0\r\n\r\n");
+    res
+}
+
+// Send a request to a server and wait until it closes the connection to
+// return the response
+pub fn send(
+    addr: IpAddress,
+    url: &URL,
+    req: &Request,
+    timeout: f64
+) -> Result<Response, Error> {
+    let socket_path = "/dev/net/tcp";
+    let buf_len = match syscall::info(socket_path) {
+        Some(info) => info.size() as usize,
+        None => return Err(Error::Connect),
+    };
+    let flags = OpenFlag::Device as usize;
+    let handle = syscall::open(socket_path, flags).ok_or(Error::Connect)?;
+    let res = exchange(handle, addr, url, req, timeout, buf_len);
+    syscall::close(handle);
+    let buf = res?;
+    Response::parse(&buf).ok_or(Error::InvalidResponse)
+}
+
+fn exchange(
+    handle: usize,
+    addr: IpAddress,
+    url: &URL,
+    req: &Request,
+    timeout: f64,
+    buf_len: usize
+) -> Result<Vec<u8>, Error> {
+    syscall::sockopt(handle, SocketOption::ConnectTimeout, timeout).ok();
+    syscall::sockopt(handle, SocketOption::ReadTimeout, timeout).ok();
+    syscall::sockopt(handle, SocketOption::WriteTimeout, timeout).ok();
+    match syscall::connect(handle, addr, url.port) {
+        Ok(()) => {}
+        Err(SocketError::Timeout) => return Err(Error::Timeout),
+        Err(_) => return Err(Error::Connect),
+    }
+    if url.is_tls {
+        return match tls::exchange(handle, &url.host, &req.to_bytes()) {
+            Ok(buf) => Ok(buf),
+            Err(tls::Error::Timeout) => Err(Error::Timeout),
+            Err(tls::Error::Interrupted) => Err(Error::Interrupted),
+            Err(e) => Err(Error::Tls(e)),
+        };
+    }
+    send_all(handle, &req.to_bytes())?;
+    let mut buf = Vec::new();
+    let mut data = vec![0; buf_len];
+    loop {
+        if sys::console::end_of_text() || sys::console::end_of_transmission() {
+            return Err(Error::Interrupted);
+        }
+        match syscall::recv(handle, &mut data) {
+            Ok(0) => return Ok(buf),
+            Ok(n) => buf.extend_from_slice(&data[0..n]),
+            Err(SocketError::Timeout) => return Err(Error::Timeout),
+            Err(_) => return Err(Error::Read),
+        }
+    }
+}
+
+// Send the whole buffer, which can take more than one write when it's larger
+// than the send buffer of the socket
+fn send_all(handle: usize, buf: &[u8]) -> Result<(), Error> {
+    let mut i = 0;
+    while i < buf.len() {
+        if sys::console::end_of_text() || sys::console::end_of_transmission() {
+            return Err(Error::Interrupted);
+        }
+        match syscall::send(handle, &buf[i..]) {
+            Ok(0) => return Err(Error::Write),
+            Ok(n) => i += n,
+            Err(SocketError::Timeout) => return Err(Error::Timeout),
+            Err(_) => return Err(Error::Write),
+        }
+    }
+    Ok(())
+}
+
+#[test_case]
+fn test_url() {
+    let url = URL::parse("http://10.0.2.2:8080/a/b.html").unwrap();
+    assert_eq!(url.host, "10.0.2.2");
+    assert_eq!(url.port, 8080);
+    assert_eq!(url.authority(), "10.0.2.2:8080");
+    assert_eq!(url.join("c.html").unwrap().path, "/a/c.html");
+    assert_eq!(url.join("/d").unwrap().path, "/d");
+    assert_eq!(url.join("http://moros.cc").unwrap().host, "moros.cc");
+    assert_eq!(URL::parse("http://[::1]/").unwrap().authority(), "[::1]");
+    let url = URL::parse("https://moros.cc/").unwrap();
+    assert!(url.is_tls);
+    assert_eq!(url.port, 443);
+    assert_eq!(url.authority(), "moros.cc");
+    assert!(!url.join("http://moros.cc/").unwrap().is_tls);
+}
+
+#[test_case]
+fn test_request() {
+    let url = URL::parse("http://moros.cc/index.html?lang=en").unwrap();
+    let mut req = Request::new("PUT", &url);
+    req.body = b"Hello".to_vec();
+    let req = Request::parse(&req.to_bytes()).unwrap();
+    assert_eq!(req.method, "PUT");
+    assert_eq!(req.path, "/index.html");
+    assert_eq!(req.query, "lang=en");
+    assert_eq!(req.version, "HTTP/1.1");
+    assert_eq!(req.header("host"), Some(&"moros.cc".to_string()));
+    assert_eq!(req.header("content-length"), Some(&"5".to_string()));
+    assert_eq!(req.body, b"Hello".to_vec());
+
+    let buf = req.to_bytes();
+    let n = buf.len();
+    assert!(Request::parse(&buf[..(n - 1)]).is_none());
+    let buf = [buf.clone(), buf].concat();
+    assert_eq!(Request::parse_len(&buf).map(|(_, len)| len), Some(n));
+}
+
+#[test_case]
+fn test_response() {
+    let buf = b"HTTP/1.1 302 Found\r\n\
+        Location: /index.html\r\n\
+        transfer-encoding: chunked\r\n\
+        \r\n\
+        2\r\nOK\r\n0\r\n\r\n";
+    let res = Response::parse(buf).unwrap();
+    assert_eq!(res.code, 302);
+    assert!(res.is_redirect());
+    assert_eq!(res.header("location"), Some(&"/index.html".to_string()));
+    assert_eq!(res.body, b"OK".to_vec());
+    assert!(res.head().starts_with("HTTP/1.1 302 Found\r\n"));
+}
+
+#[test_case]
+fn test_chunked() {
+    let buf = b"5\r\nHello\r\n7;ext=1\r\n, World\r\n0\r\n\r\n";
+    assert_eq!(decode_chunked(buf), Some(b"Hello, World".to_vec()));
+    assert_eq!(decode_chunked(b"5\r\nHel"), None);
+    assert_eq!(decode_chunked(b"FFFFFFFFFFFFFFFF\r\nHello"), None);
+    let buf = encode_chunked(b"Hello, World", 5);
+    assert_eq!(decode_chunked(&buf), Some(b"Hello, World".to_vec()));
+}
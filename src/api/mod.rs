@@ -87,6 +87,7 @@ pub mod clock;
 pub mod console;
 pub mod font;
 pub mod fs;
+pub mod http;
 pub mod io;
 pub mod process;
 pub mod prompt;
@@ -94,6 +95,7 @@ pub mod rng;
 pub mod regex;
 pub mod syscall;
 pub mod time;
+pub mod tls;
 pub mod unit;
 pub mod vga;
 // TODO: add mod wildcard
@@ -1,12 +1,13 @@
 use crate::api::fs::IO;
 use crate::api::process::ExitCode;
 use crate::sys::fs::{FileInfo, FileType};
+use crate::sys::net::socket::{SocketError, SocketOption};
 use crate::sys::syscall::number::*;
 use crate::syscall;
 
 use core::convert::TryFrom;
-use smoltcp::wire::IpAddress;
-use smoltcp::wire::Ipv4Address;
+use smoltcp::wire::{IpAddress, IpEndpoint};
+use smoltcp::wire::{Ipv4Address, Ipv6Address};
 
 pub fn exit(code: ExitCode) {
     unsafe { syscall!(EXIT, code as usize) };
@@ -91,6 +92,30 @@ pub fn write(handle: usize, buf: &[u8]) -> Option<usize> {
     }
 }
 
+// Read from a socket with a distinct error when it has timed out or would
+// block in non-blocking mode
+pub fn recv(handle: usize, buf: &mut [u8]) -> Result<usize, SocketError> {
+    let ptr = buf.as_ptr() as usize;
+    let len = buf.len();
+    let res = unsafe { syscall!(READ, handle, ptr, len) } as isize;
+    if res >= 0 {
+        Ok(res as usize)
+    } else {
+        Err(SocketError::from(res))
+    }
+}
+
+pub fn send(handle: usize, buf: &[u8]) -> Result<usize, SocketError> {
+    let ptr = buf.as_ptr() as usize;
+    let len = buf.len();
+    let res = unsafe { syscall!(WRITE, handle, ptr, len) } as isize;
+    if res >= 0 {
+        Ok(res as usize)
+    } else {
+        Err(SocketError::from(res))
+    }
+}
+
 pub fn close(handle: usize) {
     unsafe { syscall!(CLOSE, handle) };
 }
@@ -133,7 +158,11 @@ pub fn poll(list: &[(usize, IO)]) -> Option<(usize, IO)> {
     }
 }
 
-pub fn connect(handle: usize, addr: IpAddress, port: u16) -> Result<(), ()> {
+pub fn connect(
+    handle: usize,
+    addr: IpAddress,
+    port: u16
+) -> Result<(), SocketError> {
     let buf = addr.as_bytes();
     let ptr = buf.as_ptr() as usize;
     let len = buf.len();
@@ -141,7 +170,7 @@ pub fn connect(handle: usize, addr: IpAddress, port: u16) -> Result<(), ()> {
     if res >= 0 {
         Ok(())
     } else {
-        Err(())
+        Err(SocketError::from(res))
     }
 }
 
@@ -154,14 +183,39 @@ pub fn listen(handle: usize, port: u16) -> Result<(), ()> {
     }
 }
 
-pub fn accept(handle: usize) -> Result<IpAddress, ()> {
-    let addr = IpAddress::v4(0, 0, 0, 0);
-    let buf = addr.as_bytes();
+pub fn accept(handle: usize) -> Result<IpAddress, SocketError> {
+    accept_endpoint(handle).map(|endpoint| endpoint.addr)
+}
+
+// Accept a connection, or a datagram that will become the remote endpoint of
+// a UDP socket, and return the address and the port of the peer
+pub fn accept_endpoint(handle: usize) -> Result<IpEndpoint, SocketError> {
+    let buf = [0; 18]; // Large enough for an IPv6 address and a port
     let ptr = buf.as_ptr() as usize;
     let len = buf.len();
     let res = unsafe { syscall!(ACCEPT, handle, ptr, len) } as isize;
+    let addr = match res {
+        4 => IpAddress::from(Ipv4Address::from_bytes(&buf[0..4])),
+        16 => IpAddress::from(Ipv6Address::from_bytes(&buf[0..16])),
+        _ => return Err(SocketError::from(res)),
+    };
+    let n = res as usize;
+    let port = u16::from_be_bytes([buf[n], buf[n + 1]]);
+    Ok(IpEndpoint::new(addr, port))
+}
+
+// Timeouts are given in seconds, and any other value than 0 will enable the
+// non-blocking mode
+pub fn sockopt(
+    handle: usize,
+    option: SocketOption,
+    value: f64
+) -> Result<(), ()> {
+    let option = option as usize;
+    let value = value.to_bits();
+    let res = unsafe { syscall!(SOCKOPT, handle, option, value) } as isize;
     if res >= 0 {
-        Ok(IpAddress::from(Ipv4Address::from_bytes(buf)))
+        Ok(())
     } else {
         Err(())
     }
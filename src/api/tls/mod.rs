@@ -0,0 +1,186 @@
+use crate::api::clock;
+use crate::api::fs;
+use crate::api::syscall;
+use crate::sys;
+use crate::sys::net::socket::SocketError;
+
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::time::Duration;
+use rustls::client::UnbufferedClientConnection;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::time_provider::TimeProvider;
+use rustls::unbuffered::{AppDataRecord, ConnectionState, UnbufferedStatus};
+use rustls::{ClientConfig, RootCertStore};
+
+mod provider;
+
+pub const CA_PATH: &str = "/ini/ca.pem";
+
+const BUF_LEN: usize = 16 << 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Certificates,
+    Handshake,
+    Read,
+    Write,
+    Timeout,
+    Interrupted,
+}
+
+#[derive(Debug)]
+struct Clock;
+
+impl TimeProvider for Clock {
+    fn current_time(&self) -> Option<UnixTime> {
+        let secs = clock::realtime() as u64;
+        Some(UnixTime::since_unix_epoch(Duration::from_secs(secs)))
+    }
+}
+
+// Load the certificates of the trusted authorities from a PEM bundle
+pub fn root_certificates(path: &str) -> Result<RootCertStore, Error> {
+    let buf = fs::read_to_bytes(path).map_err(|_| Error::Certificates)?;
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(&buf) {
+        let cert = cert.map_err(|_| Error::Certificates)?;
+        roots.add(cert).map_err(|_| Error::Certificates)?;
+    }
+    if roots.is_empty() {
+        return Err(Error::Certificates);
+    }
+    Ok(roots)
+}
+
+fn config() -> Result<ClientConfig, Error> {
+    let roots = root_certificates(CA_PATH)?;
+    let provider = Arc::new(provider::provider());
+    let config = ClientConfig::builder_with_details(provider, Arc::new(Clock)).
+        with_safe_default_protocol_versions().
+        map_err(|_| Error::Handshake)?.
+        with_root_certificates(roots).
+        with_no_client_auth();
+    Ok(config)
+}
+
+// Send data to a server through a TLS session established on a connected
+// TCP socket, and return all the data received until the session is closed
+pub fn exchange(
+    handle: usize,
+    host: &str,
+    data: &[u8]
+) -> Result<Vec<u8>, Error> {
+    let name = ServerName::try_from(host.to_string()).
+        map_err(|_| Error::Handshake)?;
+    let config = Arc::new(config()?);
+    let mut conn = UnbufferedClientConnection::new(config, name).
+        map_err(|_| Error::Handshake)?;
+
+    let mut incoming = vec![0; BUF_LEN];
+    let mut outgoing = vec![0; BUF_LEN];
+    let mut incoming_used = 0;
+    let mut outgoing_used = 0;
+    let mut is_sent = false;
+    let mut res = Vec::new();
+    loop {
+        let UnbufferedStatus { mut discard, state } =
+            conn.process_tls_records(&mut incoming[..incoming_used]);
+        match state.map_err(|_| Error::Handshake)? {
+            ConnectionState::ReadTraffic(mut state) => {
+                while let Some(record) = state.next_record() {
+                    let AppDataRecord { discard: n, payload } =
+                        record.map_err(|_| Error::Read)?;
+                    res.extend_from_slice(payload);
+                    discard += n;
+                }
+            }
+            ConnectionState::EncodeTlsData(mut state) => {
+                outgoing_used += state.encode(&mut outgoing[outgoing_used..]).
+                    map_err(|_| Error::Handshake)?;
+            }
+            ConnectionState::TransmitTlsData(state) => {
+                send(handle, &outgoing[..outgoing_used])?;
+                outgoing_used = 0;
+                state.done();
+            }
+            ConnectionState::BlockedHandshake { .. } => {
+                if recv(handle, &mut incoming, &mut incoming_used)? == 0 {
+                    return Err(Error::Handshake);
+                }
+            }
+            ConnectionState::WriteTraffic(mut state) => {
+                if is_sent {
+                    // Wait for the server to close the session
+                    if recv(handle, &mut incoming, &mut incoming_used)? == 0 {
+                        break;
+                    }
+                } else {
+                    for chunk in data.chunks(BUF_LEN / 2) {
+                        let n = state.encrypt(chunk, &mut outgoing).
+                            map_err(|_| Error::Write)?;
+                        send(handle, &outgoing[..n])?;
+                    }
+                    is_sent = true;
+                }
+            }
+            ConnectionState::PeerClosed | ConnectionState::Closed => {
+                break;
+            }
+            _ => {
+                return Err(Error::Handshake);
+            }
+        }
+        if discard > 0 {
+            incoming.copy_within(discard..incoming_used, 0);
+            incoming_used -= discard;
+        }
+    }
+    Ok(res)
+}
+
+// Send the whole buffer, which can take more than one write when it's larger
+// than the send buffer of the socket
+fn send(handle: usize, buf: &[u8]) -> Result<(), Error> {
+    let mut i = 0;
+    while i < buf.len() {
+        if sys::console::end_of_text() || sys::console::end_of_transmission() {
+            return Err(Error::Interrupted);
+        }
+        match syscall::send(handle, &buf[i..]) {
+            Ok(0) => return Err(Error::Write),
+            Ok(n) => i += n,
+            Err(SocketError::Timeout) => return Err(Error::Timeout),
+            Err(_) => return Err(Error::Write),
+        }
+    }
+    Ok(())
+}
+
+// Append the data received to the buffer and return its length, which
+// will be zero when the connection is closed
+fn recv(
+    handle: usize,
+    buf: &mut Vec<u8>,
+    used: &mut usize
+) -> Result<usize, Error> {
+    if sys::console::end_of_text() || sys::console::end_of_transmission() {
+        return Err(Error::Interrupted);
+    }
+    // NOTE: A read of 1 byte would return the status of the socket
+    if buf.len() - *used < 2 {
+        buf.resize(buf.len() + BUF_LEN, 0);
+    }
+    match syscall::recv(handle, &mut buf[*used..]) {
+        Ok(n) => {
+            *used += n;
+            Ok(n)
+        }
+        Err(SocketError::Timeout) => Err(Error::Timeout),
+        Err(_) => Err(Error::Read),
+    }
+}
@@ -0,0 +1,890 @@
+use crate::sys;
+
+use aes_gcm::aead::consts::{U12, U16};
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use chacha20poly1305::ChaCha20Poly1305;
+use core::convert::TryFrom;
+use core::marker::PhantomData;
+use ::hmac::{Mac, SimpleHmac};
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use p256::ecdsa::signature::Verifier as _;
+use p256::elliptic_curve::rand_core::{CryptoRng, Error as RngError, RngCore};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::der::oid::AssociatedOid;
+use rsa::{pkcs1v15, pss, RsaPublicKey};
+use rustls::crypto::cipher::{
+    make_tls12_aad, make_tls13_aad, AeadKey, InboundOpaqueMessage,
+    InboundPlainMessage, Iv, KeyBlockShape, MessageDecrypter,
+    MessageEncrypter, Nonce, OutboundOpaqueMessage, OutboundPlainMessage,
+    PrefixedPayload, Tls12AeadAlgorithm, Tls13AeadAlgorithm,
+    UnsupportedOperationError,
+};
+use rustls::crypto::tls12::PrfUsingHmac;
+use rustls::crypto::tls13::HkdfUsingHmac;
+use rustls::crypto::{
+    hash, hmac, ActiveKeyExchange, CipherSuiteCommon, CryptoProvider,
+    GetRandomFailed, KeyExchangeAlgorithm, KeyProvider, SecureRandom,
+    SharedSecret, SupportedKxGroup, WebPkiSupportedAlgorithms,
+};
+use rustls::pki_types::alg_id;
+use rustls::pki_types::{
+    AlgorithmIdentifier, InvalidSignature, PrivateKeyDer,
+    SignatureVerificationAlgorithm,
+};
+use rustls::sign::SigningKey;
+use rustls::{
+    CipherSuite, ConnectionTrafficSecrets, ContentType, NamedGroup,
+    PeerMisbehaved, ProtocolVersion, SignatureScheme, SupportedCipherSuite,
+    Tls12CipherSuite, Tls13CipherSuite,
+};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+// Client side cryptography of the TLS sessions, built on the pure Rust
+// implementations of the RustCrypto project that can be compiled for our
+// soft-float target. Only the NIST curves are available for the key
+// exchange because the Curve25519 crates don't build for this target.
+pub fn provider() -> CryptoProvider {
+    CryptoProvider {
+        cipher_suites: vec![
+            SupportedCipherSuite::Tls13(&TLS13_AES_128_GCM_SHA256),
+            SupportedCipherSuite::Tls13(&TLS13_AES_256_GCM_SHA384),
+            SupportedCipherSuite::Tls13(&TLS13_CHACHA20_POLY1305_SHA256),
+            SupportedCipherSuite::Tls12(&TLS12_ECDHE_ECDSA_AES_128_GCM),
+            SupportedCipherSuite::Tls12(&TLS12_ECDHE_ECDSA_AES_256_GCM),
+            SupportedCipherSuite::Tls12(&TLS12_ECDHE_ECDSA_CHACHA20),
+            SupportedCipherSuite::Tls12(&TLS12_ECDHE_RSA_AES_128_GCM),
+            SupportedCipherSuite::Tls12(&TLS12_ECDHE_RSA_AES_256_GCM),
+            SupportedCipherSuite::Tls12(&TLS12_ECDHE_RSA_CHACHA20),
+        ],
+        kx_groups: vec![&SECP256R1, &SECP384R1],
+        signature_verification_algorithms: ALGORITHMS,
+        secure_random: &Random,
+        key_provider: &NoKeys,
+    }
+}
+
+// Random
+
+#[derive(Debug)]
+struct Random;
+
+impl SecureRandom for Random {
+    fn fill(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> {
+        Random.fill_bytes(buf);
+        Ok(())
+    }
+}
+
+impl RngCore for Random {
+    fn next_u32(&mut self) -> u32 {
+        sys::rng::get_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        sys::rng::get_u64()
+    }
+
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let n = chunk.len();
+            chunk.copy_from_slice(&sys::rng::get_u64().to_le_bytes()[0..n]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, buf: &mut [u8]) -> Result<(), RngError> {
+        self.fill_bytes(buf);
+        Ok(())
+    }
+}
+
+impl CryptoRng for Random {}
+
+// Keys
+
+#[derive(Debug)]
+struct NoKeys;
+
+impl KeyProvider for NoKeys {
+    fn load_private_key(
+        &self,
+        _key: PrivateKeyDer<'static>,
+    ) -> Result<Arc<dyn SigningKey>, rustls::Error> {
+        Err(rustls::Error::General("client keys are not supported".into()))
+    }
+}
+
+// Hashes
+
+struct Hash<D>(PhantomData<fn() -> D>);
+
+struct HashContext<D>(D);
+
+static SHA256: Hash<Sha256> = Hash(PhantomData);
+static SHA384: Hash<Sha384> = Hash(PhantomData);
+
+impl<D> hash::Hash for Hash<D>
+where D: Digest + Clone + Send + Sync + HashAlgorithm + 'static
+{
+    fn start(&self) -> Box<dyn hash::Context> {
+        Box::new(HashContext(D::new()))
+    }
+
+    fn hash(&self, data: &[u8]) -> hash::Output {
+        hash::Output::new(&D::digest(data))
+    }
+
+    fn output_len(&self) -> usize {
+        <D as Digest>::output_size()
+    }
+
+    fn algorithm(&self) -> hash::HashAlgorithm {
+        D::ALGORITHM
+    }
+}
+
+impl<D> hash::Context for HashContext<D>
+where D: Digest + Clone + Send + Sync + 'static
+{
+    fn fork_finish(&self) -> hash::Output {
+        hash::Output::new(&self.0.clone().finalize())
+    }
+
+    fn fork(&self) -> Box<dyn hash::Context> {
+        Box::new(HashContext(self.0.clone()))
+    }
+
+    fn finish(self: Box<Self>) -> hash::Output {
+        hash::Output::new(&self.0.finalize())
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+}
+
+trait HashAlgorithm {
+    const ALGORITHM: hash::HashAlgorithm;
+}
+
+impl HashAlgorithm for Sha256 {
+    const ALGORITHM: hash::HashAlgorithm = hash::HashAlgorithm::SHA256;
+}
+
+impl HashAlgorithm for Sha384 {
+    const ALGORITHM: hash::HashAlgorithm = hash::HashAlgorithm::SHA384;
+}
+
+// HMAC
+
+struct Hmac<D>(PhantomData<fn() -> D>);
+
+struct HmacKey<D: Digest + sha2::digest::core_api::BlockSizeUser>(
+    SimpleHmac<D>
+);
+
+static HMAC_SHA256: Hmac<Sha256> = Hmac(PhantomData);
+static HMAC_SHA384: Hmac<Sha384> = Hmac(PhantomData);
+
+impl<D> hmac::Hmac for Hmac<D>
+where D: Digest + sha2::digest::core_api::BlockSizeUser + Clone + Send +
+    Sync + 'static
+{
+    fn with_key(&self, key: &[u8]) -> Box<dyn hmac::Key> {
+        let mac = <SimpleHmac<D> as KeyInit>::new_from_slice(key).
+            expect("HMAC key");
+        Box::new(HmacKey(mac))
+    }
+
+    fn hash_output_len(&self) -> usize {
+        <D as Digest>::output_size()
+    }
+}
+
+impl<D> hmac::Key for HmacKey<D>
+where D: Digest + sha2::digest::core_api::BlockSizeUser + Clone + Send +
+    Sync + 'static
+{
+    fn sign_concat(
+        &self,
+        first: &[u8],
+        middle: &[&[u8]],
+        last: &[u8]
+    ) -> hmac::Tag {
+        let mut mac = self.0.clone();
+        mac.update(first);
+        for data in middle {
+            mac.update(data);
+        }
+        mac.update(last);
+        hmac::Tag::new(&mac.finalize().into_bytes())
+    }
+
+    fn tag_len(&self) -> usize {
+        <D as Digest>::output_size()
+    }
+}
+
+// Key exchange
+
+#[derive(Debug)]
+struct Ecdh(NamedGroup);
+
+static SECP256R1: Ecdh = Ecdh(NamedGroup::secp256r1);
+static SECP384R1: Ecdh = Ecdh(NamedGroup::secp384r1);
+
+enum EphemeralSecret {
+    P256(p256::ecdh::EphemeralSecret),
+    P384(p384::ecdh::EphemeralSecret),
+}
+
+struct KeyExchange {
+    group: NamedGroup,
+    secret: EphemeralSecret,
+    public_key: Vec<u8>,
+}
+
+impl SupportedKxGroup for Ecdh {
+    fn start(&self) -> Result<Box<dyn ActiveKeyExchange>, rustls::Error> {
+        let (secret, public_key) = match self.0 {
+            NamedGroup::secp256r1 => {
+                let secret = p256::ecdh::EphemeralSecret::random(&mut Random);
+                let point = secret.public_key().to_encoded_point(false);
+                (EphemeralSecret::P256(secret), point.as_bytes().to_vec())
+            }
+            NamedGroup::secp384r1 => {
+                let secret = p384::ecdh::EphemeralSecret::random(&mut Random);
+                let point = secret.public_key().to_encoded_point(false);
+                (EphemeralSecret::P384(secret), point.as_bytes().to_vec())
+            }
+            _ => return Err(UnsupportedOperationError.into()),
+        };
+        let group = self.0;
+        Ok(Box::new(KeyExchange { group, secret, public_key }))
+    }
+
+    fn name(&self) -> NamedGroup {
+        self.0
+    }
+}
+
+impl ActiveKeyExchange for KeyExchange {
+    fn complete(
+        self: Box<Self>,
+        peer: &[u8]
+    ) -> Result<SharedSecret, rustls::Error> {
+        let err = PeerMisbehaved::InvalidKeyShare;
+        let secret = match self.secret {
+            EphemeralSecret::P256(secret) => {
+                let key = p256::PublicKey::from_sec1_bytes(peer).
+                    map_err(|_| err)?;
+                secret.diffie_hellman(&key).raw_secret_bytes().to_vec()
+            }
+            EphemeralSecret::P384(secret) => {
+                let key = p384::PublicKey::from_sec1_bytes(peer).
+                    map_err(|_| err)?;
+                secret.diffie_hellman(&key).raw_secret_bytes().to_vec()
+            }
+        };
+        Ok(SharedSecret::from(secret))
+    }
+
+    fn pub_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn group(&self) -> NamedGroup {
+        self.group
+    }
+}
+
+// Signature verification
+
+// Verify the signature of a message with a public key
+type Verify = fn(&[u8], &[u8], &[u8]) -> Option<()>;
+
+#[derive(Debug)]
+struct Verifier {
+    public_key_alg_id: AlgorithmIdentifier,
+    signature_alg_id: AlgorithmIdentifier,
+    verify: Verify,
+}
+
+impl SignatureVerificationAlgorithm for Verifier {
+    fn verify_signature(
+        &self,
+        public_key: &[u8],
+        message: &[u8],
+        signature: &[u8]
+    ) -> Result<(), InvalidSignature> {
+        (self.verify)(public_key, message, signature).ok_or(InvalidSignature)
+    }
+
+    fn public_key_alg_id(&self) -> AlgorithmIdentifier {
+        self.public_key_alg_id
+    }
+
+    fn signature_alg_id(&self) -> AlgorithmIdentifier {
+        self.signature_alg_id
+    }
+}
+
+fn verify_p256<D: Digest>(key: &[u8], msg: &[u8], sig: &[u8]) -> Option<()> {
+    let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(key).ok()?;
+    let sig = p256::ecdsa::DerSignature::try_from(sig).ok()?;
+    key.verify_prehash(&D::digest(msg), &sig).ok()
+}
+
+fn verify_p384<D: Digest>(key: &[u8], msg: &[u8], sig: &[u8]) -> Option<()> {
+    let key = p384::ecdsa::VerifyingKey::from_sec1_bytes(key).ok()?;
+    let sig = p384::ecdsa::DerSignature::try_from(sig).ok()?;
+    key.verify_prehash(&D::digest(msg), &sig).ok()
+}
+
+fn verify_pkcs1<D>(key: &[u8], msg: &[u8], sig: &[u8]) -> Option<()>
+where D: Digest + AssociatedOid
+{
+    let key = RsaPublicKey::from_pkcs1_der(key).ok()?;
+    let sig = pkcs1v15::Signature::try_from(sig).ok()?;
+    pkcs1v15::VerifyingKey::<D>::new(key).verify(msg, &sig).ok()
+}
+
+fn verify_pss<D>(key: &[u8], msg: &[u8], sig: &[u8]) -> Option<()>
+where D: Digest + sha2::digest::FixedOutputReset
+{
+    let key = RsaPublicKey::from_pkcs1_der(key).ok()?;
+    let sig = pss::Signature::try_from(sig).ok()?;
+    pss::VerifyingKey::<D>::new(key).verify(msg, &sig).ok()
+}
+
+static ECDSA_P256_SHA256: Verifier = Verifier {
+    public_key_alg_id: alg_id::ECDSA_P256,
+    signature_alg_id: alg_id::ECDSA_SHA256,
+    verify: verify_p256::<Sha256>,
+};
+
+static ECDSA_P256_SHA384: Verifier = Verifier {
+    public_key_alg_id: alg_id::ECDSA_P256,
+    signature_alg_id: alg_id::ECDSA_SHA384,
+    verify: verify_p256::<Sha384>,
+};
+
+static ECDSA_P384_SHA256: Verifier = Verifier {
+    public_key_alg_id: alg_id::ECDSA_P384,
+    signature_alg_id: alg_id::ECDSA_SHA256,
+    verify: verify_p384::<Sha256>,
+};
+
+static ECDSA_P384_SHA384: Verifier = Verifier {
+    public_key_alg_id: alg_id::ECDSA_P384,
+    signature_alg_id: alg_id::ECDSA_SHA384,
+    verify: verify_p384::<Sha384>,
+};
+
+static RSA_PKCS1_SHA256: Verifier = Verifier {
+    public_key_alg_id: alg_id::RSA_ENCRYPTION,
+    signature_alg_id: alg_id::RSA_PKCS1_SHA256,
+    verify: verify_pkcs1::<Sha256>,
+};
+
+static RSA_PKCS1_SHA384: Verifier = Verifier {
+    public_key_alg_id: alg_id::RSA_ENCRYPTION,
+    signature_alg_id: alg_id::RSA_PKCS1_SHA384,
+    verify: verify_pkcs1::<Sha384>,
+};
+
+static RSA_PKCS1_SHA512: Verifier = Verifier {
+    public_key_alg_id: alg_id::RSA_ENCRYPTION,
+    signature_alg_id: alg_id::RSA_PKCS1_SHA512,
+    verify: verify_pkcs1::<Sha512>,
+};
+
+static RSA_PSS_SHA256: Verifier = Verifier {
+    public_key_alg_id: alg_id::RSA_ENCRYPTION,
+    signature_alg_id: alg_id::RSA_PSS_SHA256,
+    verify: verify_pss::<Sha256>,
+};
+
+static RSA_PSS_SHA384: Verifier = Verifier {
+    public_key_alg_id: alg_id::RSA_ENCRYPTION,
+    signature_alg_id: alg_id::RSA_PSS_SHA384,
+    verify: verify_pss::<Sha384>,
+};
+
+static RSA_PSS_SHA512: Verifier = Verifier {
+    public_key_alg_id: alg_id::RSA_ENCRYPTION,
+    signature_alg_id: alg_id::RSA_PSS_SHA512,
+    verify: verify_pss::<Sha512>,
+};
+
+static ALGORITHMS: WebPkiSupportedAlgorithms = WebPkiSupportedAlgorithms {
+    all: &[
+        &ECDSA_P256_SHA256,
+        &ECDSA_P256_SHA384,
+        &ECDSA_P384_SHA256,
+        &ECDSA_P384_SHA384,
+        &RSA_PKCS1_SHA256,
+        &RSA_PKCS1_SHA384,
+        &RSA_PKCS1_SHA512,
+        &RSA_PSS_SHA256,
+        &RSA_PSS_SHA384,
+        &RSA_PSS_SHA512,
+    ],
+    mapping: &[
+        (
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            &[&ECDSA_P384_SHA384, &ECDSA_P256_SHA384],
+        ),
+        (
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            &[&ECDSA_P256_SHA256, &ECDSA_P384_SHA256],
+        ),
+        (SignatureScheme::RSA_PSS_SHA512, &[&RSA_PSS_SHA512]),
+        (SignatureScheme::RSA_PSS_SHA384, &[&RSA_PSS_SHA384]),
+        (SignatureScheme::RSA_PSS_SHA256, &[&RSA_PSS_SHA256]),
+        (SignatureScheme::RSA_PKCS1_SHA512, &[&RSA_PKCS1_SHA512]),
+        (SignatureScheme::RSA_PKCS1_SHA384, &[&RSA_PKCS1_SHA384]),
+        (SignatureScheme::RSA_PKCS1_SHA256, &[&RSA_PKCS1_SHA256]),
+    ],
+};
+
+// Record encryption
+
+const TAG_LEN: usize = 16;
+const EXPLICIT_NONCE_LEN: usize = 8;
+
+trait Aead: AeadInPlace<NonceSize = U12, TagSize = U16> + KeyInit +
+    Send + Sync + 'static {}
+
+impl<A> Aead for A where A: AeadInPlace<NonceSize = U12, TagSize = U16> +
+    KeyInit + Send + Sync + 'static {}
+
+fn cipher<A: Aead>(key: &AeadKey) -> A {
+    A::new_from_slice(key.as_ref()).expect("AEAD key")
+}
+
+fn seal<A: Aead>(aead: &A, nonce: Nonce, aad: &[u8], buf: &mut [u8]) -> Tag {
+    let nonce = GenericArray::from(nonce.0);
+    aead.encrypt_in_place_detached(&nonce, aad, buf).expect("AEAD seal")
+}
+
+fn open<A: Aead>(
+    aead: &A,
+    nonce: Nonce,
+    aad: &[u8],
+    buf: &mut [u8]
+) -> Result<(), rustls::Error> {
+    if buf.len() < TAG_LEN {
+        return Err(rustls::Error::DecryptError);
+    }
+    let nonce = GenericArray::from(nonce.0);
+    let (buf, tag) = buf.split_at_mut(buf.len() - TAG_LEN);
+    let tag = GenericArray::from_slice(tag);
+    aead.decrypt_in_place_detached(&nonce, aad, buf, tag).
+        map_err(|_| rustls::Error::DecryptError)
+}
+
+type Tag = GenericArray<u8, U16>;
+
+// TLS 1.3 records are encrypted with a nonce derived from the sequence
+// number, and the real content type is appended to the plaintext
+struct Tls13Aead<A>(PhantomData<fn() -> A>);
+
+struct Tls13Cipher<A> {
+    aead: A,
+    iv: Iv,
+}
+
+impl<A: Aead> Tls13AeadAlgorithm for Tls13Aead<A> {
+    fn encrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageEncrypter> {
+        Box::new(Tls13Cipher { aead: cipher::<A>(&key), iv })
+    }
+
+    fn decrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageDecrypter> {
+        Box::new(Tls13Cipher { aead: cipher::<A>(&key), iv })
+    }
+
+    fn key_len(&self) -> usize {
+        A::key_size()
+    }
+
+    fn extract_keys(
+        &self,
+        _key: AeadKey,
+        _iv: Iv
+    ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError> {
+        Err(UnsupportedOperationError)
+    }
+}
+
+impl<A: Aead> MessageEncrypter for Tls13Cipher<A> {
+    fn encrypt(
+        &mut self,
+        msg: OutboundPlainMessage<'_>,
+        seq: u64
+    ) -> Result<OutboundOpaqueMessage, rustls::Error> {
+        let len = self.encrypted_payload_len(msg.payload.len());
+        let mut payload = PrefixedPayload::with_capacity(len);
+        payload.extend_from_chunks(&msg.payload);
+        payload.extend_from_slice(&msg.typ.to_array());
+        let nonce = Nonce::new(&self.iv, seq);
+        let aad = make_tls13_aad(len);
+        let tag = seal(&self.aead, nonce, &aad, payload.as_mut());
+        payload.extend_from_slice(&tag);
+        let (typ, version) = (ContentType::ApplicationData,
+            ProtocolVersion::TLSv1_2);
+        Ok(OutboundOpaqueMessage::new(typ, version, payload))
+    }
+
+    fn encrypted_payload_len(&self, len: usize) -> usize {
+        len + 1 + TAG_LEN
+    }
+}
+
+impl<A: Aead> MessageDecrypter for Tls13Cipher<A> {
+    fn decrypt<'a>(
+        &mut self,
+        mut msg: InboundOpaqueMessage<'a>,
+        seq: u64
+    ) -> Result<InboundPlainMessage<'a>, rustls::Error> {
+        let len = msg.payload.len();
+        let nonce = Nonce::new(&self.iv, seq);
+        let aad = make_tls13_aad(len);
+        open(&self.aead, nonce, &aad, &mut msg.payload)?;
+        msg.payload.truncate(len - TAG_LEN);
+        msg.into_tls13_unpadded_message()
+    }
+}
+
+// TLS 1.2 records encrypted with AES-GCM carry an explicit part of the
+// nonce, while ChaCha20-Poly1305 derives the whole nonce like TLS 1.3
+struct Tls12Aead<A> {
+    explicit_nonce_len: usize,
+    aead: PhantomData<fn() -> A>,
+}
+
+struct Tls12Cipher<A> {
+    aead: A,
+    iv: Iv,
+    explicit_nonce_len: usize,
+}
+
+impl<A: Aead> Tls12AeadAlgorithm for Tls12Aead<A> {
+    fn encrypter(
+        &self,
+        key: AeadKey,
+        iv: &[u8],
+        extra: &[u8]
+    ) -> Box<dyn MessageEncrypter> {
+        let mut nonce = [0; 12];
+        nonce[0..iv.len()].copy_from_slice(iv);
+        nonce[iv.len()..].copy_from_slice(extra);
+        let aead = cipher::<A>(&key);
+        let explicit_nonce_len = self.explicit_nonce_len;
+        Box::new(Tls12Cipher { aead, iv: Iv::new(nonce), explicit_nonce_len })
+    }
+
+    fn decrypter(&self, key: AeadKey, iv: &[u8]) -> Box<dyn MessageDecrypter> {
+        let mut nonce = [0; 12];
+        nonce[0..iv.len()].copy_from_slice(iv);
+        let aead = cipher::<A>(&key);
+        let explicit_nonce_len = self.explicit_nonce_len;
+        Box::new(Tls12Cipher { aead, iv: Iv::new(nonce), explicit_nonce_len })
+    }
+
+    fn key_block_shape(&self) -> KeyBlockShape {
+        KeyBlockShape {
+            enc_key_len: A::key_size(),
+            fixed_iv_len: 12 - self.explicit_nonce_len,
+            explicit_nonce_len: self.explicit_nonce_len,
+        }
+    }
+
+    fn extract_keys(
+        &self,
+        _key: AeadKey,
+        _iv: &[u8],
+        _explicit: &[u8]
+    ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError> {
+        Err(UnsupportedOperationError)
+    }
+}
+
+impl<A: Aead> MessageEncrypter for Tls12Cipher<A> {
+    fn encrypt(
+        &mut self,
+        msg: OutboundPlainMessage<'_>,
+        seq: u64
+    ) -> Result<OutboundOpaqueMessage, rustls::Error> {
+        let n = self.explicit_nonce_len;
+        let len = self.encrypted_payload_len(msg.payload.len());
+        let nonce = Nonce::new(&self.iv, seq);
+        let aad = make_tls12_aad(seq, msg.typ, msg.version, msg.payload.len());
+        let mut payload = PrefixedPayload::with_capacity(len);
+        payload.extend_from_slice(&nonce.0[(12 - n)..]);
+        payload.extend_from_chunks(&msg.payload);
+        let tag = seal(&self.aead, nonce, &aad, &mut payload.as_mut()[n..]);
+        payload.extend_from_slice(&tag);
+        Ok(OutboundOpaqueMessage::new(msg.typ, msg.version, payload))
+    }
+
+    fn encrypted_payload_len(&self, len: usize) -> usize {
+        self.explicit_nonce_len + len + TAG_LEN
+    }
+}
+
+impl<A: Aead> MessageDecrypter for Tls12Cipher<A> {
+    fn decrypt<'a>(
+        &mut self,
+        mut msg: InboundOpaqueMessage<'a>,
+        seq: u64
+    ) -> Result<InboundPlainMessage<'a>, rustls::Error> {
+        let n = self.explicit_nonce_len;
+        let len = msg.payload.len();
+        if len < n + TAG_LEN {
+            return Err(rustls::Error::DecryptError);
+        }
+        let nonce = if n == 0 {
+            Nonce::new(&self.iv, seq)
+        } else {
+            let mut nonce = self.iv.as_ref().to_vec();
+            nonce[(12 - n)..].copy_from_slice(&msg.payload[0..n]);
+            Nonce(<[u8; 12]>::try_from(nonce.as_slice()).unwrap())
+        };
+        let aad = make_tls12_aad(seq, msg.typ, msg.version, len - n - TAG_LEN);
+        open(&self.aead, nonce, &aad, &mut msg.payload[n..])?;
+        msg.payload.copy_within(n..(len - TAG_LEN), 0);
+        msg.payload.truncate(len - n - TAG_LEN);
+        Ok(msg.into_plain_message())
+    }
+}
+
+// Cipher suites
+
+static AES_128_GCM: Tls13Aead<Aes128Gcm> = Tls13Aead(PhantomData);
+static AES_256_GCM: Tls13Aead<Aes256Gcm> = Tls13Aead(PhantomData);
+static CHACHA20: Tls13Aead<ChaCha20Poly1305> = Tls13Aead(PhantomData);
+
+static TLS12_AES_128_GCM: Tls12Aead<Aes128Gcm> = Tls12Aead {
+    explicit_nonce_len: EXPLICIT_NONCE_LEN,
+    aead: PhantomData,
+};
+
+static TLS12_AES_256_GCM: Tls12Aead<Aes256Gcm> = Tls12Aead {
+    explicit_nonce_len: EXPLICIT_NONCE_LEN,
+    aead: PhantomData,
+};
+
+static TLS12_CHACHA20: Tls12Aead<ChaCha20Poly1305> = Tls12Aead {
+    explicit_nonce_len: 0,
+    aead: PhantomData,
+};
+
+// Limit of records encrypted with the same AES-GCM key recommended by
+// rustls to keep the probability of an attack below 2^-60
+const AES_GCM_LIMIT: u64 = 1 << 24;
+
+static TLS13_AES_128_GCM_SHA256: Tls13CipherSuite = Tls13CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS13_AES_128_GCM_SHA256,
+        hash_provider: &SHA256,
+        confidentiality_limit: AES_GCM_LIMIT,
+    },
+    hkdf_provider: &HkdfUsingHmac(&HMAC_SHA256),
+    aead_alg: &AES_128_GCM,
+    quic: None,
+};
+
+static TLS13_AES_256_GCM_SHA384: Tls13CipherSuite = Tls13CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS13_AES_256_GCM_SHA384,
+        hash_provider: &SHA384,
+        confidentiality_limit: AES_GCM_LIMIT,
+    },
+    hkdf_provider: &HkdfUsingHmac(&HMAC_SHA384),
+    aead_alg: &AES_256_GCM,
+    quic: None,
+};
+
+static TLS13_CHACHA20_POLY1305_SHA256: Tls13CipherSuite = Tls13CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+        hash_provider: &SHA256,
+        confidentiality_limit: u64::MAX,
+    },
+    hkdf_provider: &HkdfUsingHmac(&HMAC_SHA256),
+    aead_alg: &CHACHA20,
+    quic: None,
+};
+
+static ECDSA_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+];
+
+static RSA_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+static TLS12_ECDHE_ECDSA_AES_128_GCM: Tls12CipherSuite = Tls12CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+        hash_provider: &SHA256,
+        confidentiality_limit: AES_GCM_LIMIT,
+    },
+    prf_provider: &PrfUsingHmac(&HMAC_SHA256),
+    kx: KeyExchangeAlgorithm::ECDHE,
+    sign: ECDSA_SCHEMES,
+    aead_alg: &TLS12_AES_128_GCM,
+};
+
+static TLS12_ECDHE_ECDSA_AES_256_GCM: Tls12CipherSuite = Tls12CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+        hash_provider: &SHA384,
+        confidentiality_limit: AES_GCM_LIMIT,
+    },
+    prf_provider: &PrfUsingHmac(&HMAC_SHA384),
+    kx: KeyExchangeAlgorithm::ECDHE,
+    sign: ECDSA_SCHEMES,
+    aead_alg: &TLS12_AES_256_GCM,
+};
+
+static TLS12_ECDHE_ECDSA_CHACHA20: Tls12CipherSuite = Tls12CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+        hash_provider: &SHA256,
+        confidentiality_limit: u64::MAX,
+    },
+    prf_provider: &PrfUsingHmac(&HMAC_SHA256),
+    kx: KeyExchangeAlgorithm::ECDHE,
+    sign: ECDSA_SCHEMES,
+    aead_alg: &TLS12_CHACHA20,
+};
+
+static TLS12_ECDHE_RSA_AES_128_GCM: Tls12CipherSuite = Tls12CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+        hash_provider: &SHA256,
+        confidentiality_limit: AES_GCM_LIMIT,
+    },
+    prf_provider: &PrfUsingHmac(&HMAC_SHA256),
+    kx: KeyExchangeAlgorithm::ECDHE,
+    sign: RSA_SCHEMES,
+    aead_alg: &TLS12_AES_128_GCM,
+};
+
+static TLS12_ECDHE_RSA_AES_256_GCM: Tls12CipherSuite = Tls12CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+        hash_provider: &SHA384,
+        confidentiality_limit: AES_GCM_LIMIT,
+    },
+    prf_provider: &PrfUsingHmac(&HMAC_SHA384),
+    kx: KeyExchangeAlgorithm::ECDHE,
+    sign: RSA_SCHEMES,
+    aead_alg: &TLS12_AES_256_GCM,
+};
+
+static TLS12_ECDHE_RSA_CHACHA20: Tls12CipherSuite = Tls12CipherSuite {
+    common: CipherSuiteCommon {
+        suite: CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+        hash_provider: &SHA256,
+        confidentiality_limit: u64::MAX,
+    },
+    prf_provider: &PrfUsingHmac(&HMAC_SHA256),
+    kx: KeyExchangeAlgorithm::ECDHE,
+    sign: RSA_SCHEMES,
+    aead_alg: &TLS12_CHACHA20,
+};
+
+#[test_case]
+fn test_key_exchange() {
+    for group in [&SECP256R1, &SECP384R1] {
+        let alice = group.start().unwrap();
+        let bob = group.start().unwrap();
+        let alice_key = alice.pub_key().to_vec();
+        let bob_key = bob.pub_key().to_vec();
+        let alice_secret = alice.complete(&bob_key).unwrap();
+        let bob_secret = bob.complete(&alice_key).unwrap();
+        assert_eq!(alice_secret.secret_bytes(), bob_secret.secret_bytes());
+        assert!(group.start().unwrap().complete(&[4, 1, 2, 3]).is_err());
+    }
+}
+
+#[cfg(test)]
+fn roundtrip(
+    mut encrypter: Box<dyn MessageEncrypter>,
+    mut decrypter: Box<dyn MessageDecrypter>
+) {
+    use rustls::crypto::cipher::OutboundChunks;
+
+    let data = b"Hello, World!";
+    for seq in 0..3 {
+        let msg = OutboundPlainMessage {
+            typ: ContentType::ApplicationData,
+            version: ProtocolVersion::TLSv1_2,
+            payload: OutboundChunks::Single(data),
+        };
+        let len = encrypter.encrypted_payload_len(data.len());
+        let mut buf = encrypter.encrypt(msg, seq).unwrap().encode();
+        assert_eq!(buf.len(), 5 + len);
+        assert_ne!(&buf[5..], data);
+
+        let (typ, version) = (ContentType::ApplicationData,
+            ProtocolVersion::TLSv1_2);
+        let msg = InboundOpaqueMessage::new(typ, version, &mut buf[5..]);
+        let msg = decrypter.decrypt(msg, seq).unwrap();
+        assert_eq!(msg.typ, ContentType::ApplicationData);
+        assert_eq!(msg.payload, data);
+
+        let mut buf = encrypter.encrypt(OutboundPlainMessage {
+            typ: ContentType::ApplicationData,
+            version: ProtocolVersion::TLSv1_2,
+            payload: OutboundChunks::Single(data),
+        }, seq).unwrap().encode();
+        buf[len] ^= 1;
+        let msg = InboundOpaqueMessage::new(typ, version, &mut buf[5..]);
+        assert!(decrypter.decrypt(msg, seq).is_err());
+    }
+}
+
+#[test_case]
+fn test_tls13_records() {
+    let algs: [&dyn Tls13AeadAlgorithm; 2] = [&AES_256_GCM, &CHACHA20];
+    for alg in algs {
+        let iv = Iv::new([7; 12]);
+        let encrypter = alg.encrypter(AeadKey::from([42; 32]), iv);
+        let iv = Iv::new([7; 12]);
+        let decrypter = alg.decrypter(AeadKey::from([42; 32]), iv);
+        roundtrip(encrypter, decrypter);
+    }
+}
+
+#[test_case]
+fn test_tls12_records() {
+    let algs: [&dyn Tls12AeadAlgorithm; 2] = [
+        &TLS12_AES_256_GCM, &TLS12_CHACHA20
+    ];
+    for alg in algs {
+        let shape = alg.key_block_shape();
+        let iv = [7; 12];
+        let extra = [9; 8];
+        let (iv, extra) = (&iv[..shape.fixed_iv_len],
+            &extra[..shape.explicit_nonce_len]);
+        let encrypter = alg.encrypter(AeadKey::from([42; 32]), iv, extra);
+        let decrypter = alg.decrypter(AeadKey::from([42; 32]), iv);
+        roundtrip(encrypter, decrypter);
+    }
+}
@@ -41,7 +41,9 @@ pub fn init(boot_info: &'static BootInfo) {
     sys::pci::init(); // Require MEM
     sys::net::init(); // Require PCI
     sys::ata::init();
-    sys::fs::init(); // Require ATA
+    sys::ahci::init(); // Require PCI
+    sys::virtio::init(); // Require PCI
+    sys::fs::init(); // Require ATA, AHCI and VIRTIO
     sys::clock::init(); // Require MEM
 }
 
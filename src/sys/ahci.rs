@@ -0,0 +1,525 @@
+use crate::sys;
+use crate::sys::allocator::PhysBuf;
+use crate::sys::fs::read_drive_blocks;
+use crate::api::fs::{FileIO, IO};
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bit_field::BitField;
+use core::convert::TryInto;
+use core::fmt;
+use core::hint::spin_loop;
+use core::ptr;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::PhysAddr;
+
+// Serial ATA Advanced Host Controller Interface (AHCI)
+// Revision 1.3.1 (2013)
+
+pub const BLOCK_SIZE: usize = 512;
+
+// Maximum number of blocks transferred by a single command
+const BLOCKS_COUNT: usize = 128;
+
+// Generic Host Control Registers
+const REG_CAP: u64 = 0x00; // Host Capabilities
+const REG_GHC: u64 = 0x04; // Global Host Control
+const REG_PI: u64 =  0x0C; // Ports Implemented
+
+// Port Registers
+const REG_PX_CLB: u64 =  0x00; // Command List Base Address
+const REG_PX_CLBU: u64 = 0x04; // Command List Base Address Upper 32-bits
+const REG_PX_FB: u64 =   0x08; // FIS Base Address
+const REG_PX_FBU: u64 =  0x0C; // FIS Base Address Upper 32-bits
+const REG_PX_IS: u64 =   0x10; // Interrupt Status
+const REG_PX_IE: u64 =   0x14; // Interrupt Enable
+const REG_PX_CMD: u64 =  0x18; // Command and Status
+const REG_PX_TFD: u64 =  0x20; // Task File Data
+const REG_PX_SIG: u64 =  0x24; // Signature
+const REG_PX_SSTS: u64 = 0x28; // SATA Status
+const REG_PX_SERR: u64 = 0x30; // SATA Error
+const REG_PX_CI: u64 =   0x38; // Command Issue
+
+const GHC_AE: u32 = 1 << 31; // AHCI Enable
+
+const CMD_ST: u32 =  1 << 0;  // Start
+const CMD_FRE: u32 = 1 << 4;  // FIS Receive Enable
+const CMD_FR: u32 =  1 << 14; // FIS Receive Running
+const CMD_CR: u32 =  1 << 15; // Command List Running
+
+const TFD_ERR: u32 = 1 << 0; // Error
+const TFD_DRQ: u32 = 1 << 3; // Data Request
+const TFD_BSY: u32 = 1 << 7; // Busy
+
+const IS_TFES: u32 = 1 << 30; // Task File Error Status
+
+const SSTS_DET_PRESENT: u32 = 3; // Device detected and communication ready
+const SSTS_IPM_ACTIVE: u32 = 1; // Interface in active state
+
+const SIG_ATA: u32 = 0x00000101;
+
+const FIS_TYPE_REG_H2D: u8 = 0x27; // Register FIS - Host to Device
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+enum Command {
+    ReadDmaExt  = 0x25,
+    WriteDmaExt = 0x35,
+    Identify    = 0xEC,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct CommandHeader {
+    flags: u16, // Command FIS Length (0..5) and Write (6)
+    prdtl: u16, // Physical Region Descriptor Table Length
+    prdbc: u32, // Physical Region Descriptor Byte Count
+    ctba: u64,  // Command Table Base Address
+    reserved: [u32; 4],
+}
+
+#[derive(Debug)]
+#[repr(C, align(1024))]
+struct CommandList([CommandHeader; 32]);
+
+#[repr(C, align(256))]
+struct ReceivedFis([u8; 256]);
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct PrdtEntry {
+    dba: u64, // Data Base Address
+    reserved: u32,
+    dbc: u32, // Data Byte Count (0..21) minus one
+}
+
+#[repr(C, align(128))]
+struct CommandTable {
+    cfis: [u8; 64], // Command FIS
+    acmd: [u8; 16], // ATAPI Command
+    reserved: [u8; 48],
+    prdt: [PrdtEntry; 1],
+}
+
+#[derive(Clone)]
+pub struct Port {
+    id: u8,
+    mem_base: PhysAddr,
+    command_list: Arc<Mutex<CommandList>>,
+    received_fis: Arc<Mutex<ReceivedFis>>,
+    command_table: PhysBuf,
+    buffer: PhysBuf,
+}
+
+impl Port {
+    // Return a port only if it has an ATA device that could be initialized
+    pub fn new(id: u8, mem_base: PhysAddr) -> Option<Self> {
+        let mem_base = mem_base + 0x100 + (id as u64) * 0x80;
+        if !is_present(mem_base) || !is_ata(mem_base) {
+            return None;
+        }
+        let header = CommandHeader::default();
+        let align = core::mem::align_of::<CommandTable>();
+        let size = core::mem::size_of::<CommandTable>();
+        let mut port = Self {
+            id,
+            mem_base,
+            command_list: Arc::new(Mutex::new(CommandList([header; 32]))),
+            received_fis: Arc::new(Mutex::new(ReceivedFis([0; 256]))),
+            command_table: PhysBuf::new(size + align),
+            buffer: PhysBuf::new(BLOCKS_COUNT * BLOCK_SIZE),
+        };
+        port.init().ok()?;
+        Some(port)
+    }
+
+    // The command table must be physically contiguous and aligned on 128
+    // bytes, so it is placed at the first aligned address of a physical
+    // buffer that is large enough to hold it after any offset.
+    fn command_table(&mut self) -> &mut CommandTable {
+        let align = core::mem::align_of::<CommandTable>();
+        let offset = (align - (self.command_table.addr() as usize) % align)
+            % align;
+        let ptr = self.command_table[offset..].as_mut_ptr();
+        unsafe { &mut *(ptr as *mut CommandTable) }
+    }
+
+    fn init(&mut self) -> Result<(), ()> {
+        self.stop()?;
+
+        let command_list = self.command_list.clone();
+        let mut command_list = command_list.lock();
+        let ptr = ptr::addr_of!(command_list.0[0]) as *const u8;
+        let addr = sys::allocator::phys_addr(ptr);
+        self.write(REG_PX_CLB, addr.get_bits(0..32) as u32);
+        self.write(REG_PX_CLBU, addr.get_bits(32..64) as u32);
+
+        let received_fis = self.received_fis.clone();
+        let received_fis = received_fis.lock();
+        let ptr = ptr::addr_of!(received_fis.0[0]);
+        let addr = sys::allocator::phys_addr(ptr);
+        self.write(REG_PX_FB, addr.get_bits(0..32) as u32);
+        self.write(REG_PX_FBU, addr.get_bits(32..64) as u32);
+
+        // Only the first command slot is used
+        let ptr = ptr::addr_of!(self.command_table().cfis[0]);
+        command_list.0[0].ctba = sys::allocator::phys_addr(ptr);
+
+        self.write(REG_PX_SERR, 0xFFFFFFFF); // Clear errors
+        self.write(REG_PX_IE, 0); // Disable interrupts
+        self.write(REG_PX_IS, 0xFFFFFFFF); // Clear interrupts
+
+        self.start()
+    }
+
+    fn start(&self) -> Result<(), ()> {
+        self.wait(CMD_CR, "starting")?;
+        let cmd = self.read(REG_PX_CMD);
+        self.write(REG_PX_CMD, cmd | CMD_FRE);
+        let cmd = self.read(REG_PX_CMD);
+        self.write(REG_PX_CMD, cmd | CMD_ST);
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ()> {
+        let cmd = self.read(REG_PX_CMD);
+        self.write(REG_PX_CMD, cmd & !CMD_ST);
+        let cmd = self.read(REG_PX_CMD);
+        self.write(REG_PX_CMD, cmd & !CMD_FRE);
+        self.wait(CMD_CR | CMD_FR, "stopping")
+    }
+
+    // Wait for the bits of the mask to be cleared in the command register
+    fn wait(&self, mask: u32, action: &str) -> Result<(), ()> {
+        let start = sys::clock::uptime();
+        while self.read(REG_PX_CMD) & mask > 0 {
+            if sys::clock::uptime() - start > 1.0 {
+                debug!("AHCI hanged while {} port {}", action, self.id);
+                return Err(());
+            }
+            spin_loop();
+        }
+        Ok(())
+    }
+
+    fn poll(&self, mask: u32) -> Result<(), ()> {
+        let start = sys::clock::uptime();
+        while self.read(REG_PX_TFD) & mask > 0 {
+            if sys::clock::uptime() - start > 1.0 {
+                debug!("AHCI hanged while polling port {}", self.id);
+                return Err(());
+            }
+            spin_loop();
+        }
+        Ok(())
+    }
+
+    fn command(
+        &mut self,
+        cmd: Command,
+        block: u64,
+        len: usize,
+        write: bool
+    ) -> Result<(), ()> {
+        debug_assert!(len > 0 && len <= self.buffer.len());
+        let count = if len < BLOCK_SIZE { 1 } else { len / BLOCK_SIZE };
+        self.poll(TFD_BSY | TFD_DRQ)?;
+
+        {
+            let dba = self.buffer.addr();
+            let table = self.command_table();
+            table.cfis = [0; 64];
+            table.cfis[0] = FIS_TYPE_REG_H2D;
+            table.cfis[1] = 1 << 7; // Command
+            table.cfis[2] = cmd as u8;
+            table.cfis[4] = block.get_bits(0..8) as u8;
+            table.cfis[5] = block.get_bits(8..16) as u8;
+            table.cfis[6] = block.get_bits(16..24) as u8;
+            table.cfis[7] = 1 << 6; // LBA mode
+            table.cfis[8] = block.get_bits(24..32) as u8;
+            table.cfis[9] = block.get_bits(32..40) as u8;
+            table.cfis[10] = block.get_bits(40..48) as u8;
+            table.cfis[12] = count.get_bits(0..8) as u8;
+            table.cfis[13] = count.get_bits(8..16) as u8;
+            table.prdt[0] = PrdtEntry {
+                dba,
+                reserved: 0,
+                dbc: (len - 1) as u32,
+            };
+        }
+
+        {
+            let mut list = self.command_list.lock();
+            let fis_len = 5; // Length of the command FIS in dwords
+            let mut flags = fis_len;
+            flags.set_bit(6, write);
+            list.0[0].flags = flags;
+            list.0[0].prdtl = 1;
+            list.0[0].prdbc = 0;
+        }
+
+        self.write(REG_PX_IS, 0xFFFFFFFF);
+        self.write(REG_PX_CI, 1);
+
+        let start = sys::clock::uptime();
+        while self.read(REG_PX_CI) & 1 > 0 {
+            if self.read(REG_PX_IS) & IS_TFES > 0 {
+                break;
+            }
+            if sys::clock::uptime() - start > 1.0 {
+                debug!("AHCI hanged while waiting for command {:?}", cmd);
+                return Err(());
+            }
+            spin_loop();
+        }
+
+        if self.read(REG_PX_IS) & IS_TFES > 0 {
+            debug!("AHCI command {:?} errored", cmd);
+            self.debug();
+            self.write(REG_PX_SERR, 0xFFFFFFFF);
+            self.write(REG_PX_IS, 0xFFFFFFFF);
+            return Err(());
+        }
+        if self.read(REG_PX_TFD) & TFD_ERR > 0 {
+            return Err(());
+        }
+        Ok(())
+    }
+
+    fn identify(&mut self) -> Result<[u16; 256], ()> {
+        self.command(Command::Identify, 0, BLOCK_SIZE, false)?;
+        let mut res = [0; 256];
+        for (i, chunk) in self.buffer[0..BLOCK_SIZE].chunks(2).enumerate() {
+            res[i] = u16::from_le_bytes(chunk.try_into().unwrap());
+        }
+        Ok(res)
+    }
+
+    fn read_blocks(&mut self, block: u32, buf: &mut [u8]) -> Result<(), ()> {
+        debug_assert!(buf.len() % BLOCK_SIZE == 0);
+        let n = BLOCKS_COUNT * BLOCK_SIZE;
+        for (i, chunk) in buf.chunks_mut(n).enumerate() {
+            let addr = (block as u64) + (i * BLOCKS_COUNT) as u64;
+            self.command(Command::ReadDmaExt, addr, chunk.len(), false)?;
+            chunk.copy_from_slice(&self.buffer[0..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, block: u32, buf: &[u8]) -> Result<(), ()> {
+        debug_assert!(buf.len() % BLOCK_SIZE == 0);
+        let n = BLOCKS_COUNT * BLOCK_SIZE;
+        for (i, chunk) in buf.chunks(n).enumerate() {
+            let addr = (block as u64) + (i * BLOCKS_COUNT) as u64;
+            self.buffer[0..chunk.len()].copy_from_slice(chunk);
+            self.command(Command::WriteDmaExt, addr, chunk.len(), true)?;
+        }
+        Ok(())
+    }
+
+    fn read(&self, addr: u64) -> u32 {
+        let phys = self.mem_base + addr;
+        let addr = sys::mem::phys_to_virt(phys).as_u64() as *const u32;
+        unsafe { core::ptr::read_volatile(addr) }
+    }
+
+    fn write(&self, addr: u64, data: u32) {
+        let phys = self.mem_base + addr;
+        let addr = sys::mem::phys_to_virt(phys).as_u64() as *mut u32;
+        unsafe { core::ptr::write_volatile(addr, data) }
+    }
+
+    #[allow(dead_code)]
+    fn debug(&self) {
+        debug!("AHCI port {} CMD:  {:#034b}", self.id, self.read(REG_PX_CMD));
+        debug!("AHCI port {} TFD:  {:#034b}", self.id, self.read(REG_PX_TFD));
+        debug!("AHCI port {} IS:   {:#034b}", self.id, self.read(REG_PX_IS));
+        debug!("AHCI port {} SERR: {:#034b}", self.id, self.read(REG_PX_SERR));
+    }
+}
+
+lazy_static! {
+    pub static ref PORTS: Mutex<Vec<Port>> = Mutex::new(Vec::new());
+}
+
+fn read_hba(mem_base: PhysAddr, addr: u64) -> u32 {
+    let phys = mem_base + addr;
+    let addr = sys::mem::phys_to_virt(phys).as_u64() as *const u32;
+    unsafe { core::ptr::read_volatile(addr) }
+}
+
+fn write_hba(mem_base: PhysAddr, addr: u64, data: u32) {
+    let phys = mem_base + addr;
+    let addr = sys::mem::phys_to_virt(phys).as_u64() as *mut u32;
+    unsafe { core::ptr::write_volatile(addr, data) }
+}
+
+// The registers of a port can be read before its initialization to check
+// if a device is attached to it
+fn is_present(port_base: PhysAddr) -> bool {
+    let ssts = read_hba(port_base, REG_PX_SSTS);
+    let det = ssts.get_bits(0..4);
+    let ipm = ssts.get_bits(8..12);
+    det == SSTS_DET_PRESENT && ipm == SSTS_IPM_ACTIVE
+}
+
+fn is_ata(port_base: PhysAddr) -> bool {
+    read_hba(port_base, REG_PX_SIG) == SIG_ATA
+}
+
+pub fn init() {
+    // Mass storage controller with a SATA AHCI programming interface
+    let dev = match sys::pci::find_class(0x01, 0x06) {
+        Some(mut dev) if dev.prog == 0x01 => {
+            dev.enable_bus_mastering();
+            dev
+        }
+        _ => return,
+    };
+    let mem_base = PhysAddr::new((dev.base_addresses[5] & 0xFFFFF000) as u64);
+    let ghc = read_hba(mem_base, REG_GHC);
+    write_hba(mem_base, REG_GHC, ghc | GHC_AE);
+    let cap = read_hba(mem_base, REG_CAP);
+    let n = cap.get_bits(0..5) + 1; // Number of ports
+    log!("AHCI {} ports", n);
+
+    {
+        let mut ports = PORTS.lock();
+        let implemented = read_hba(mem_base, REG_PI);
+        for id in 0..32 {
+            if implemented.get_bit(id) {
+                if let Some(port) = Port::new(id as u8, mem_base) {
+                    ports.push(port);
+                }
+            }
+        }
+    }
+
+    for drive in list() {
+        log!("AHCI {} {}", drive.port, drive);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Drive {
+    pub port: u8,
+    model: String,
+    serial: String,
+    block_count: u32,
+    block_index: u32,
+}
+
+impl Drive {
+    pub fn size() -> usize {
+        BLOCK_SIZE
+    }
+
+    pub fn open(port: u8) -> Option<Self> {
+        let mut ports = PORTS.lock();
+        let port = ports.iter_mut().find(|p| p.id == port)?;
+        if let Ok(res) = port.identify() {
+            let buf = res.map(u16::to_be_bytes).concat();
+            let model = String::from_utf8_lossy(&buf[54..94]).trim().into();
+            let serial = String::from_utf8_lossy(&buf[20..40]).trim().into();
+            let lba28 = u32::from_be_bytes(
+                buf[120..124].try_into().unwrap()
+            ).rotate_left(16);
+            let lba48 = res[100..104].iter().rev().fold(0, |acc, w|
+                (acc << 16) | (*w as u64)
+            );
+            let block_count = if lba48 > 0 {
+                lba48.min(u32::MAX as u64) as u32
+            } else {
+                lba28
+            };
+            let block_index = 0;
+
+            Some(Self {
+                port: port.id,
+                model,
+                serial,
+                block_count,
+                block_index,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub const fn block_size(&self) -> u32 {
+        BLOCK_SIZE as u32
+    }
+
+    pub fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    fn humanized_size(&self) -> (usize, String) {
+        let size = self.block_size() as usize;
+        let count = self.block_count() as usize;
+        let bytes = size * count;
+        if bytes >> 20 < 1000 {
+            (bytes >> 20, String::from("MB"))
+        } else {
+            (bytes >> 30, String::from("GB"))
+        }
+    }
+}
+
+impl FileIO for Drive {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        let port = self.port;
+        read_drive_blocks(
+            buf, BLOCK_SIZE, &mut self.block_index, self.block_count,
+            |block, buf| read(port, block, buf)
+        )
+    }
+
+    fn write(&mut self, _buf: &[u8]) -> Result<usize, ()> {
+        Err(())
+    }
+
+    fn close(&mut self) {
+    }
+
+    fn poll(&mut self, event: IO) -> bool {
+        match event {
+            IO::Read => true,
+            IO::Write => false,
+        }
+    }
+}
+
+impl fmt::Display for Drive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (size, unit) = self.humanized_size();
+        write!(f, "{} {} ({} {})", self.model, self.serial, size, unit)
+    }
+}
+
+pub fn list() -> Vec<Drive> {
+    let ids: Vec<u8> = PORTS.lock().iter().map(|port| port.id).collect();
+    ids.into_iter().filter_map(Drive::open).collect()
+}
+
+pub fn read(port: u8, block: u32, buf: &mut [u8]) -> Result<(), ()> {
+    let mut ports = PORTS.lock();
+    let port = ports.iter_mut().find(|p| p.id == port).ok_or(())?;
+    port.read_blocks(block, buf)
+}
+
+pub fn write(port: u8, block: u32, buf: &[u8]) -> Result<(), ()> {
+    let mut ports = PORTS.lock();
+    let port = ports.iter_mut().find(|p| p.id == port).ok_or(())?;
+    port.write_blocks(block, buf)
+}
+
+#[test_case]
+fn test_structs() {
+    assert_eq!(core::mem::size_of::<CommandHeader>(), 32);
+    assert_eq!(core::mem::size_of::<CommandList>(), 1024);
+    assert_eq!(core::mem::size_of::<PrdtEntry>(), 16);
+    assert_eq!(core::mem::size_of::<CommandTable>(), 256);
+}
@@ -144,6 +144,12 @@ impl PhysBuf {
     }
 }
 
+impl core::fmt::Debug for PhysBuf {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "PhysBuf({:#X})", self.addr())
+    }
+}
+
 pub fn phys_addr(ptr: *const u8) -> u64 {
     let virt_addr = VirtAddr::new(ptr as u64);
     let phys_addr = sys::mem::virt_to_phys(virt_addr).unwrap();
@@ -184,6 +190,12 @@ impl core::ops::DerefMut for PhysBuf {
     }
 }
 
+// Interrupt handlers must not allocate while the interrupted code is holding
+// the lock on the heap
+pub fn is_locked() -> bool {
+    ALLOCATOR.is_locked()
+}
+
 pub fn memory_size() -> usize {
     ALLOCATOR.lock().size()
 }
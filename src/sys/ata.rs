@@ -1,12 +1,17 @@
 use crate::sys;
+use crate::sys::allocator::PhysBuf;
+use crate::sys::fs::read_drive_blocks;
 use crate::api::fs::{FileIO, IO};
 
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bit_field::BitField;
 use core::convert::TryInto;
 use core::fmt;
 use core::hint::spin_loop;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
@@ -15,8 +20,33 @@ use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
 // AT Attachment with Packet Interface Extension (ATA/ATAPI-4)
 // (1998)
 
+// Programming Interface for Bus Master IDE Controller
+// Revision 1.0 (1994)
+
 pub const BLOCK_SIZE: usize = 512;
 
+// Maximum number of blocks transferred by a single DMA command
+const DMA_BLOCKS_COUNT: usize = 128;
+
+// Bus Master IDE Command Register
+const BM_CMD_START: u8 = 1 << 0; // Start/Stop Bus Master
+const BM_CMD_READ: u8 =  1 << 3; // Read/Write Control
+
+// Bus Master IDE Status Register
+const BM_STATUS_ERROR: u8 =     1 << 1; // Error
+const BM_STATUS_INTERRUPT: u8 = 1 << 2; // Interrupt
+
+// Physical Region Descriptor
+const PRD_EOT: u16 = 1 << 15; // End Of Table
+
+// NOTE: A 64 KB buffer can cross at most one 64 KB boundary
+const PRD_ENTRIES_COUNT: usize = 4;
+
+static IRQ_RECEIVED: [AtomicBool; 2] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
 // Keep track of the last selected bus and drive pair to speed up operations
 pub static LAST_SELECTED: Mutex<Option<(u8, u8)>> = Mutex::new(None);
 
@@ -25,6 +55,8 @@ pub static LAST_SELECTED: Mutex<Option<(u8, u8)>> = Mutex::new(None);
 enum Command {
     Read     = 0x20,
     Write    = 0x30,
+    ReadDma  = 0xC8,
+    WriteDma = 0xCA,
     Identify = 0xEC,
 }
 
@@ -49,6 +81,101 @@ enum Status {
     BSY  = 7, // Busy
 }
 
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct PrdEntry {
+    addr: u32,
+    len: u16, // A value of 0 means 64 KB
+    flags: u16,
+}
+
+// NOTE: The table must be aligned to avoid crossing a 64 KB boundary
+#[derive(Debug)]
+#[repr(C, align(32))]
+struct Prdt([PrdEntry; PRD_ENTRIES_COUNT]);
+
+#[derive(Debug, Clone)]
+struct BusMaster {
+    command_register: Port<u8>,
+    status_register: Port<u8>,
+    prdt_register: Port<u32>,
+    prdt: Arc<Mutex<Prdt>>,
+    buffer: PhysBuf,
+}
+
+impl BusMaster {
+    pub fn new(io_base: u16) -> Self {
+        let prdt = Prdt([PrdEntry::default(); PRD_ENTRIES_COUNT]);
+        Self {
+            command_register: Port::new(io_base),
+            status_register: Port::new(io_base + 2),
+            prdt_register: Port::new(io_base + 4),
+            prdt: Arc::new(Mutex::new(prdt)),
+            buffer: PhysBuf::new(DMA_BLOCKS_COUNT * BLOCK_SIZE),
+        }
+    }
+
+    fn status(&mut self) -> u8 {
+        unsafe { self.status_register.read() }
+    }
+
+    fn clear_status(&mut self) {
+        // The error and interrupt bits are cleared by writing ones to them
+        unsafe {
+            self.status_register.write(BM_STATUS_ERROR | BM_STATUS_INTERRUPT)
+        }
+    }
+
+    // Describe the first `len` bytes of the buffer in the PRD table
+    fn setup(&mut self, len: usize, read: bool) {
+        debug_assert!(len <= self.buffer.len());
+        let phys_addr = {
+            let mut prdt = self.prdt.lock();
+            let mut addr = self.buffer.addr();
+            let end = addr + len as u64;
+            let mut i = 0;
+            while addr < end {
+                // A region must not cross a 64 KB boundary
+                let boundary = (addr & !0xFFFF) + 0x10000;
+                let n = boundary.min(end) - addr;
+                debug_assert!(addr + n <= u32::MAX as u64);
+                prdt.0[i] = PrdEntry {
+                    addr: addr as u32,
+                    len: n as u16,
+                    flags: 0,
+                };
+                addr += n;
+                i += 1;
+            }
+            prdt.0[i - 1].flags = PRD_EOT;
+
+            let ptr = ptr::addr_of!(prdt.0[0]) as *const u8;
+            sys::allocator::phys_addr(ptr)
+        };
+        let cmd = if read { BM_CMD_READ } else { 0 };
+        unsafe {
+            self.command_register.write(0); // Stop any previous transfer
+            self.prdt_register.write(phys_addr as u32);
+            self.command_register.write(cmd);
+        }
+        self.clear_status();
+    }
+
+    fn start(&mut self) {
+        unsafe {
+            let cmd = self.command_register.read();
+            self.command_register.write(cmd | BM_CMD_START);
+        }
+    }
+
+    fn stop(&mut self) {
+        unsafe {
+            let cmd = self.command_register.read();
+            self.command_register.write(cmd & !BM_CMD_START);
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct Bus {
@@ -69,6 +196,8 @@ pub struct Bus {
     alternate_status_register: PortReadOnly<u8>,
     control_register: PortWriteOnly<u8>,
     drive_blockess_register: PortReadOnly<u8>,
+
+    bus_master: Option<BusMaster>,
 }
 
 impl Bus {
@@ -89,9 +218,14 @@ impl Bus {
             alternate_status_register: PortReadOnly::new(ctrl_base + 0),
             control_register: PortWriteOnly::new(ctrl_base + 0),
             drive_blockess_register: PortReadOnly::new(ctrl_base + 1),
+            bus_master: None,
         }
     }
 
+    pub fn enable_dma(&mut self, io_base: u16) {
+        self.bus_master = Some(BusMaster::new(io_base));
+    }
+
     fn check_floating_bus(&mut self) -> Result<(), ()> {
         match self.status() {
             0xFF | 0x7F => Err(()),
@@ -173,7 +307,8 @@ impl Bus {
     fn write_command_params(
         &mut self,
         drive: u8,
-        block: u32
+        block: u32,
+        count: u8
     ) -> Result<(), ()> {
         let lba = true;
         let mut bytes = block.to_le_bytes();
@@ -182,7 +317,7 @@ impl Bus {
         bytes[3].set_bit(6, lba);
         bytes[3].set_bit(7, true);
         unsafe {
-            self.sector_count_register.write(1);
+            self.sector_count_register.write(count);
             self.lba0_register.write(bytes[0]);
             self.lba1_register.write(bytes[1]);
             self.lba2_register.write(bytes[2]);
@@ -211,15 +346,157 @@ impl Bus {
 
     fn setup_pio(&mut self, drive: u8, block: u32) -> Result<(), ()> {
         self.select_drive(drive)?;
-        self.write_command_params(drive, block)?;
+        self.write_command_params(drive, block, 1)?;
         Ok(())
     }
 
+    fn setup_dma(
+        &mut self,
+        drive: u8,
+        block: u32,
+        len: usize,
+        read: bool
+    ) -> Result<(), ()> {
+        let count = (len / BLOCK_SIZE) as u8;
+        if let Some(bus_master) = &mut self.bus_master {
+            bus_master.setup(len, read);
+        } else {
+            return Err(());
+        }
+        self.select_drive(drive)?;
+        self.write_command_params(drive, block, count)?;
+        IRQ_RECEIVED[self.id as usize].store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn write_dma_command(&mut self, cmd: Command) -> Result<(), ()> {
+        unsafe { self.command_register.write(cmd as u8) }
+        self.wait(400); // Wait at least 400 ns
+        if let Some(bus_master) = &mut self.bus_master {
+            bus_master.start();
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    fn wait_dma(&mut self) -> Result<(), ()> {
+        let irq = &IRQ_RECEIVED[self.id as usize];
+        let bus_master = self.bus_master.as_mut().ok_or(())?;
+        let start = sys::clock::uptime();
+
+        // NOTE: The IRQ will not be received while interrupts are disabled,
+        // during a syscall for example, so we also check the status of the
+        // bus master.
+        let mut status = bus_master.status();
+        while !irq.load(Ordering::SeqCst) {
+            if status & (BM_STATUS_INTERRUPT | BM_STATUS_ERROR) > 0 {
+                break;
+            }
+            if sys::clock::uptime() - start > 1.0 {
+                bus_master.stop();
+                bus_master.clear_status();
+                debug!("ATA hanged while waiting for DMA transfer");
+                self.debug();
+                return Err(());
+            }
+            spin_loop();
+            status = bus_master.status();
+        }
+        status |= bus_master.status();
+        bus_master.stop();
+        bus_master.clear_status();
+        self.clear_interrupt();
+        self.poll(Status::BSY, false)?;
+        if status & BM_STATUS_ERROR > 0 || self.is_error() {
+            self.debug();
+            Err(())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_dma(
+        &mut self,
+        drive: u8,
+        block: u32,
+        buf: &mut [u8]
+    ) -> Result<(), ()> {
+        let n = buf.len();
+        self.setup_dma(drive, block, n, true)?;
+        self.write_dma_command(Command::ReadDma)?;
+        self.wait_dma()?;
+        if let Some(bus_master) = &self.bus_master {
+            buf.copy_from_slice(&bus_master.buffer[0..n]);
+        }
+        Ok(())
+    }
+
+    fn write_dma(
+        &mut self,
+        drive: u8,
+        block: u32,
+        buf: &[u8]
+    ) -> Result<(), ()> {
+        let n = buf.len();
+        if let Some(bus_master) = &mut self.bus_master {
+            bus_master.buffer[0..n].copy_from_slice(buf);
+        }
+        self.setup_dma(drive, block, n, false)?;
+        self.write_dma_command(Command::WriteDma)?;
+        self.wait_dma()
+    }
+
+    // Read one or more blocks using DMA when available with PIO as fallback
     fn read(
         &mut self,
         drive: u8,
         block: u32,
         buf: &mut [u8]
+    ) -> Result<(), ()> {
+        debug_assert!(buf.len() % BLOCK_SIZE == 0);
+        let n = DMA_BLOCKS_COUNT * BLOCK_SIZE;
+        for (i, chunk) in buf.chunks_mut(n).enumerate() {
+            let addr = block + (i * DMA_BLOCKS_COUNT) as u32;
+            if self.bus_master.is_some() {
+                if self.read_dma(drive, addr, chunk).is_ok() {
+                    continue;
+                }
+                debug!("ATA {} DMA read failed, falling back to PIO", self.id);
+                self.bus_master = None;
+            }
+            for (j, data) in chunk.chunks_mut(BLOCK_SIZE).enumerate() {
+                self.read_pio(drive, addr + j as u32, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Write one or more blocks using DMA when available with PIO as fallback
+    fn write(&mut self, drive: u8, block: u32, buf: &[u8]) -> Result<(), ()> {
+        debug_assert!(buf.len() % BLOCK_SIZE == 0);
+        let n = DMA_BLOCKS_COUNT * BLOCK_SIZE;
+        for (i, chunk) in buf.chunks(n).enumerate() {
+            let addr = block + (i * DMA_BLOCKS_COUNT) as u32;
+            if self.bus_master.is_some() {
+                if self.write_dma(drive, addr, chunk).is_ok() {
+                    continue;
+                }
+                debug!("ATA {} DMA write failed, falling back to PIO", self.id);
+                self.bus_master = None;
+            }
+            for (j, data) in chunk.chunks(BLOCK_SIZE).enumerate() {
+                self.write_pio(drive, addr + j as u32, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_pio(
+        &mut self,
+        drive: u8,
+        block: u32,
+        buf: &mut [u8]
     ) -> Result<(), ()> {
         debug_assert!(buf.len() == BLOCK_SIZE);
         self.setup_pio(drive, block)?;
@@ -237,7 +514,12 @@ impl Bus {
         }
     }
 
-    fn write(&mut self, drive: u8, block: u32, buf: &[u8]) -> Result<(), ()> {
+    fn write_pio(
+        &mut self,
+        drive: u8,
+        block: u32,
+        buf: &[u8]
+    ) -> Result<(), ()> {
         debug_assert!(buf.len() == BLOCK_SIZE);
         self.setup_pio(drive, block)?;
         self.write_command(Command::Write)?;
@@ -259,7 +541,7 @@ impl Bus {
             return Ok(IdentifyResponse::None);
         }
         self.select_drive(drive)?;
-        self.write_command_params(drive, 0)?;
+        self.write_command_params(drive, 0, 1)?;
         if self.write_command(Command::Identify).is_err() {
             if self.status() == 0 {
                 return Ok(IdentifyResponse::None);
@@ -306,12 +588,33 @@ lazy_static! {
     pub static ref BUSES: Mutex<Vec<Bus>> = Mutex::new(Vec::new());
 }
 
+fn primary_interrupt_handler() {
+    IRQ_RECEIVED[0].store(true, Ordering::SeqCst);
+}
+
+fn secondary_interrupt_handler() {
+    IRQ_RECEIVED[1].store(true, Ordering::SeqCst);
+}
+
 pub fn init() {
     {
         let mut buses = BUSES.lock();
         buses.push(Bus::new(0, 0x1F0, 0x3F6, 14));
         buses.push(Bus::new(1, 0x170, 0x376, 15));
+
+        // IDE controller supporting bus mastering
+        if let Some(mut dev) = sys::pci::find_class(0x01, 0x01) {
+            if dev.prog.get_bit(7) {
+                dev.enable_bus_mastering();
+                let io_base = (dev.base_addresses[4] as u16) & 0xFFFC;
+                log!("ATA DMA {:#X}", io_base);
+                buses[0].enable_dma(io_base);
+                buses[1].enable_dma(io_base + 8);
+            }
+        }
     }
+    sys::idt::set_irq_handler(14, primary_interrupt_handler);
+    sys::idt::set_irq_handler(15, secondary_interrupt_handler);
 
     for drive in list() {
         log!("ATA {}:{} {}", drive.bus, drive.dsk, drive);
@@ -380,19 +683,15 @@ impl Drive {
 
 impl FileIO for Drive {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
-        if self.block_index == self.block_count {
-            return Ok(0);
-        }
-
-        let mut buses = BUSES.lock();
-        let _ = buses[self.bus as usize].read(self.dsk, self.block_index, buf);
-        let n = buf.len();
-        self.block_index += 1;
-        Ok(n)
+        let (bus, dsk) = (self.bus, self.dsk);
+        read_drive_blocks(
+            buf, BLOCK_SIZE, &mut self.block_index, self.block_count,
+            |block, buf| read(bus, dsk, block, buf)
+        )
     }
 
     fn write(&mut self, _buf: &[u8]) -> Result<usize, ()> {
-        unimplemented!();
+        Err(())
     }
 
     fn close(&mut self) {
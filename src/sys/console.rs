@@ -142,6 +142,7 @@ pub fn read_char() -> char {
     sys::console::enable_raw();
     loop {
         sys::time::halt();
+        sys::net::poll();
         let res = interrupts::without_interrupts(|| {
             let mut stdin = STDIN.lock();
             if !stdin.is_empty() {
@@ -161,6 +162,7 @@ pub fn read_char() -> char {
 pub fn read_line() -> String {
     loop {
         sys::time::halt();
+        sys::net::poll();
         let res = interrupts::without_interrupts(|| {
             let mut stdin = STDIN.lock();
             match stdin.chars().next_back() {
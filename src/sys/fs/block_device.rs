@@ -13,6 +13,8 @@ pub static BLOCK_DEVICE: Mutex<Option<BlockDevice>> = Mutex::new(None);
 pub enum BlockDevice {
     Mem(MemBlockDevice),
     Ata(AtaBlockDevice),
+    Ahci(AhciBlockDevice),
+    Virtio(VirtioBlockDevice),
 }
 
 pub trait BlockDeviceIO {
@@ -27,6 +29,8 @@ impl BlockDeviceIO for BlockDevice {
         match self {
             BlockDevice::Mem(dev) => dev.read(addr, buf),
             BlockDevice::Ata(dev) => dev.read(addr, buf),
+            BlockDevice::Ahci(dev) => dev.read(addr, buf),
+            BlockDevice::Virtio(dev) => dev.read(addr, buf),
         }
     }
 
@@ -34,6 +38,8 @@ impl BlockDeviceIO for BlockDevice {
         match self {
             BlockDevice::Mem(dev) => dev.write(addr, buf),
             BlockDevice::Ata(dev) => dev.write(addr, buf),
+            BlockDevice::Ahci(dev) => dev.write(addr, buf),
+            BlockDevice::Virtio(dev) => dev.write(addr, buf),
         }
     }
 
@@ -41,6 +47,8 @@ impl BlockDeviceIO for BlockDevice {
         match self {
             BlockDevice::Mem(dev) => dev.block_size(),
             BlockDevice::Ata(dev) => dev.block_size(),
+            BlockDevice::Ahci(dev) => dev.block_size(),
+            BlockDevice::Virtio(dev) => dev.block_size(),
         }
     }
 
@@ -48,6 +56,8 @@ impl BlockDeviceIO for BlockDevice {
         match self {
             BlockDevice::Mem(dev) => dev.block_count(),
             BlockDevice::Ata(dev) => dev.block_count(),
+            BlockDevice::Ahci(dev) => dev.block_count(),
+            BlockDevice::Virtio(dev) => dev.block_count(),
         }
     }
 }
@@ -101,35 +111,26 @@ pub fn format_mem() {
     }
 }
 
-const ATA_CACHE_SIZE: usize = 1024;
+const CACHE_SIZE: usize = 1024;
 
 #[derive(Clone)]
-pub struct AtaBlockDevice {
-    cache: [Option<(u32, Vec<u8>)>; ATA_CACHE_SIZE],
-    dev: sys::ata::Drive,
+struct BlockCache {
+    blocks: [Option<(u32, Vec<u8>)>; CACHE_SIZE],
 }
 
-impl AtaBlockDevice {
-    pub fn new(bus: u8, dsk: u8) -> Option<Self> {
-        sys::ata::Drive::open(bus, dsk).map(|dev| {
-            let cache = [(); ATA_CACHE_SIZE].map(|_| None);
-            Self { dev, cache }
-        })
+impl BlockCache {
+    fn new() -> Self {
+        let blocks = [(); CACHE_SIZE].map(|_| None);
+        Self { blocks }
     }
 
-    /*
-    pub fn len(&self) -> usize {
-        self.block_size() * self.block_count()
-    }
-    */
-
     fn hash(&self, block_addr: u32) -> usize {
-        (block_addr as usize) % self.cache.len()
+        (block_addr as usize) % self.blocks.len()
     }
 
-    fn cached_block(&self, block_addr: u32) -> Option<&[u8]> {
+    fn get(&self, block_addr: u32) -> Option<&[u8]> {
         let h = self.hash(block_addr);
-        if let Some((cached_addr, cached_buf)) = &self.cache[h] {
+        if let Some((cached_addr, cached_buf)) = &self.blocks[h] {
             if block_addr == *cached_addr {
                 return Some(cached_buf);
             }
@@ -137,32 +138,53 @@ impl AtaBlockDevice {
         None
     }
 
-    fn set_cached_block(&mut self, block_addr: u32, buf: &[u8]) {
+    fn set(&mut self, block_addr: u32, buf: &[u8]) {
         let h = self.hash(block_addr);
-        self.cache[h] = Some((block_addr, buf.to_vec()));
+        self.blocks[h] = Some((block_addr, buf.to_vec()));
     }
 
-    fn unset_cached_block(&mut self, block_addr: u32) {
+    fn unset(&mut self, block_addr: u32) {
         let h = self.hash(block_addr);
-        self.cache[h] = None;
+        self.blocks[h] = None;
+    }
+}
+
+#[derive(Clone)]
+pub struct AtaBlockDevice {
+    cache: BlockCache,
+    dev: sys::ata::Drive,
+}
+
+impl AtaBlockDevice {
+    pub fn new(bus: u8, dsk: u8) -> Option<Self> {
+        sys::ata::Drive::open(bus, dsk).map(|dev| {
+            let cache = BlockCache::new();
+            Self { dev, cache }
+        })
     }
+
+    /*
+    pub fn len(&self) -> usize {
+        self.block_size() * self.block_count()
+    }
+    */
 }
 
 impl BlockDeviceIO for AtaBlockDevice {
     fn read(&mut self, block_addr: u32, buf: &mut [u8]) -> Result<(), ()> {
-        if let Some(cached) = self.cached_block(block_addr) {
+        if let Some(cached) = self.cache.get(block_addr) {
             buf.copy_from_slice(cached);
             return Ok(());
         }
 
         sys::ata::read(self.dev.bus, self.dev.dsk, block_addr, buf)?;
-        self.set_cached_block(block_addr, buf);
+        self.cache.set(block_addr, buf);
         Ok(())
     }
 
     fn write(&mut self, block_addr: u32, buf: &[u8]) -> Result<(), ()> {
         sys::ata::write(self.dev.bus, self.dev.dsk, block_addr, buf)?;
-        self.unset_cached_block(block_addr);
+        self.cache.unset(block_addr);
         Ok(())
     }
 
@@ -180,6 +202,111 @@ pub fn mount_ata(bus: u8, dsk: u8) {
 }
 
 pub fn format_ata() {
+    format_disk();
+}
+
+#[derive(Clone)]
+pub struct AhciBlockDevice {
+    cache: BlockCache,
+    dev: sys::ahci::Drive,
+}
+
+impl AhciBlockDevice {
+    pub fn new(port: u8) -> Option<Self> {
+        sys::ahci::Drive::open(port).map(|dev| {
+            let cache = BlockCache::new();
+            Self { dev, cache }
+        })
+    }
+}
+
+impl BlockDeviceIO for AhciBlockDevice {
+    fn read(&mut self, block_addr: u32, buf: &mut [u8]) -> Result<(), ()> {
+        if let Some(cached) = self.cache.get(block_addr) {
+            buf.copy_from_slice(cached);
+            return Ok(());
+        }
+
+        sys::ahci::read(self.dev.port, block_addr, buf)?;
+        self.cache.set(block_addr, buf);
+        Ok(())
+    }
+
+    fn write(&mut self, block_addr: u32, buf: &[u8]) -> Result<(), ()> {
+        sys::ahci::write(self.dev.port, block_addr, buf)?;
+        self.cache.unset(block_addr);
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        self.dev.block_size() as usize
+    }
+
+    fn block_count(&self) -> usize {
+        self.dev.block_count() as usize
+    }
+}
+
+pub fn mount_ahci(port: u8) {
+    *BLOCK_DEVICE.lock() = AhciBlockDevice::new(port).map(BlockDevice::Ahci);
+}
+
+pub fn format_ahci() {
+    format_disk();
+}
+
+#[derive(Clone)]
+pub struct VirtioBlockDevice {
+    cache: BlockCache,
+    dev: sys::virtio::blk::Drive,
+}
+
+impl VirtioBlockDevice {
+    pub fn new(id: u8) -> Option<Self> {
+        sys::virtio::blk::Drive::open(id).map(|dev| {
+            let cache = BlockCache::new();
+            Self { dev, cache }
+        })
+    }
+}
+
+impl BlockDeviceIO for VirtioBlockDevice {
+    fn read(&mut self, block_addr: u32, buf: &mut [u8]) -> Result<(), ()> {
+        if let Some(cached) = self.cache.get(block_addr) {
+            buf.copy_from_slice(cached);
+            return Ok(());
+        }
+
+        sys::virtio::blk::read(self.dev.id, block_addr, buf)?;
+        self.cache.set(block_addr, buf);
+        Ok(())
+    }
+
+    fn write(&mut self, block_addr: u32, buf: &[u8]) -> Result<(), ()> {
+        sys::virtio::blk::write(self.dev.id, block_addr, buf)?;
+        self.cache.unset(block_addr);
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        self.dev.block_size() as usize
+    }
+
+    fn block_count(&self) -> usize {
+        self.dev.block_count() as usize
+    }
+}
+
+pub fn mount_virtio(id: u8) {
+    let dev = VirtioBlockDevice::new(id).map(BlockDevice::Virtio);
+    *BLOCK_DEVICE.lock() = dev;
+}
+
+pub fn format_virtio() {
+    format_disk();
+}
+
+fn format_disk() {
     if let Some(sb) = SuperBlock::new() {
         // Write super_block
         sb.write();
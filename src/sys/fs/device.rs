@@ -3,34 +3,66 @@ use super::dir::Dir;
 use super::file::File;
 use super::{dirname, filename, realpath, FileIO, IO};
 
+use crate::sys::ahci::Drive as AhciDrive;
 use crate::sys::ata::Drive;
 use crate::sys::clock::{Realtime, Uptime};
 use crate::sys::cmos::RTC;
 use crate::sys::console::Console;
+use crate::sys::net::socket::icmp::IcmpSocket;
 use crate::sys::net::socket::tcp::TcpSocket;
 use crate::sys::net::socket::udp::UdpSocket;
 use crate::sys::rng::Random;
 use crate::sys::vga::VgaFont;
+use crate::sys::virtio::blk::Drive as VirtioDrive;
 
 use alloc::vec;
 use alloc::vec::Vec;
 use core::convert::TryFrom;
 use core::convert::TryInto;
 
+// Read from the current block of a drive as many blocks as the buffer can
+// hold, using the function given to read a range of blocks into a buffer
+pub fn read_drive_blocks<F>(
+    buf: &mut [u8],
+    block_size: usize,
+    block_index: &mut u32,
+    block_count: u32,
+    read: F
+) -> Result<usize, ()>
+where
+    F: FnOnce(u32, &mut [u8]) -> Result<(), ()>
+{
+    if *block_index == block_count {
+        return Ok(0);
+    }
+    let count = (buf.len() / block_size) as u32;
+    if count == 0 {
+        return Err(());
+    }
+    let count = count.min(block_count - *block_index);
+    let n = (count as usize) * block_size;
+    read(*block_index, &mut buf[0..n])?;
+    *block_index += count;
+    Ok(n)
+}
+
 #[derive(PartialEq, Eq, Clone, Copy)]
 #[repr(u8)]
 pub enum DeviceType {
-    Null      = 0,
-    File      = 1,
-    Console   = 2,
-    Random    = 3,
-    Uptime    = 4,
-    Realtime  = 5,
-    RTC       = 6,
-    TcpSocket = 7,
-    UdpSocket = 8,
-    Drive     = 9,
-    VgaFont   = 10,
+    Null        = 0,
+    File        = 1,
+    Console     = 2,
+    Random      = 3,
+    Uptime      = 4,
+    Realtime    = 5,
+    RTC         = 6,
+    TcpSocket   = 7,
+    UdpSocket   = 8,
+    Drive       = 9,
+    VgaFont     = 10,
+    AhciDrive   = 11,
+    VirtioDrive = 12,
+    IcmpSocket  = 13,
 }
 
 impl TryFrom<&[u8]> for DeviceType {
@@ -49,6 +81,9 @@ impl TryFrom<&[u8]> for DeviceType {
              8 => Ok(DeviceType::UdpSocket),
              9 => Ok(DeviceType::Drive),
             10 => Ok(DeviceType::VgaFont),
+            11 => Ok(DeviceType::AhciDrive),
+            12 => Ok(DeviceType::VirtioDrive),
+            13 => Ok(DeviceType::IcmpSocket),
              _ => Err(()),
         }
     }
@@ -60,14 +95,17 @@ impl DeviceType {
     // store specific device informations.
     pub fn buf(self) -> Vec<u8> {
         let len = match self {
-            DeviceType::RTC       => RTC::size(),
-            DeviceType::Uptime    => Uptime::size(),
-            DeviceType::Realtime  => Realtime::size(),
-            DeviceType::Console   => Console::size(),
-            DeviceType::TcpSocket => TcpSocket::size(),
-            DeviceType::UdpSocket => UdpSocket::size(),
-            DeviceType::Drive     => Drive::size(),
-            _                     => 1,
+            DeviceType::RTC         => RTC::size(),
+            DeviceType::Uptime      => Uptime::size(),
+            DeviceType::Realtime    => Realtime::size(),
+            DeviceType::Console     => Console::size(),
+            DeviceType::TcpSocket   => TcpSocket::size(),
+            DeviceType::UdpSocket   => UdpSocket::size(),
+            DeviceType::Drive       => Drive::size(),
+            DeviceType::AhciDrive   => AhciDrive::size(),
+            DeviceType::VirtioDrive => VirtioDrive::size(),
+            DeviceType::IcmpSocket  => IcmpSocket::size(),
+            _                       => 1,
         };
         let mut res = vec![0; len];
         res[0] = self as u8; // Device type
@@ -88,6 +126,9 @@ pub enum Device {
     UdpSocket(UdpSocket),
     VgaFont(VgaFont),
     Drive(Drive),
+    AhciDrive(AhciDrive),
+    VirtioDrive(VirtioDrive),
+    IcmpSocket(IcmpSocket),
 }
 
 impl TryFrom<&[u8]> for Device {
@@ -104,6 +145,9 @@ impl TryFrom<&[u8]> for Device {
             DeviceType::RTC       => Ok(Device::RTC(RTC::new())),
             DeviceType::TcpSocket => Ok(Device::TcpSocket(TcpSocket::new())),
             DeviceType::UdpSocket => Ok(Device::UdpSocket(UdpSocket::new())),
+            DeviceType::IcmpSocket => {
+                Ok(Device::IcmpSocket(IcmpSocket::new()))
+            }
             DeviceType::VgaFont   => Ok(Device::VgaFont(VgaFont::new())),
             DeviceType::Drive if buf.len() > 2 => {
                 let bus = buf[1];
@@ -114,6 +158,22 @@ impl TryFrom<&[u8]> for Device {
                     Err(())
                 }
             }
+            DeviceType::AhciDrive if buf.len() > 1 => {
+                let port = buf[1];
+                if let Some(drive) = AhciDrive::open(port) {
+                    Ok(Device::AhciDrive(drive))
+                } else {
+                    Err(())
+                }
+            }
+            DeviceType::VirtioDrive if buf.len() > 1 => {
+                let id = buf[1];
+                if let Some(drive) = VirtioDrive::open(id) {
+                    Ok(Device::VirtioDrive(drive))
+                } else {
+                    Err(())
+                }
+            }
             _ => Err(()),
         }
     }
@@ -165,6 +225,9 @@ impl FileIO for Device {
             Device::UdpSocket(io) => io.read(buf),
             Device::VgaFont(io)   => io.read(buf),
             Device::Drive(io)     => io.read(buf),
+            Device::AhciDrive(io) => io.read(buf),
+            Device::VirtioDrive(io) => io.read(buf),
+            Device::IcmpSocket(io) => io.read(buf),
         }
     }
 
@@ -181,6 +244,9 @@ impl FileIO for Device {
             Device::UdpSocket(io) => io.write(buf),
             Device::VgaFont(io)   => io.write(buf),
             Device::Drive(io)     => io.write(buf),
+            Device::AhciDrive(io) => io.write(buf),
+            Device::VirtioDrive(io) => io.write(buf),
+            Device::IcmpSocket(io) => io.write(buf),
         }
     }
 
@@ -197,6 +263,9 @@ impl FileIO for Device {
             Device::UdpSocket(io) => io.close(),
             Device::VgaFont(io)   => io.close(),
             Device::Drive(io)     => io.close(),
+            Device::AhciDrive(io) => io.close(),
+            Device::VirtioDrive(io) => io.close(),
+            Device::IcmpSocket(io) => io.close(),
         }
     }
 
@@ -213,6 +282,9 @@ impl FileIO for Device {
             Device::UdpSocket(io) => io.poll(event),
             Device::VgaFont(io)   => io.poll(event),
             Device::Drive(io)     => io.poll(event),
+            Device::AhciDrive(io) => io.poll(event),
+            Device::VirtioDrive(io) => io.poll(event),
+            Device::IcmpSocket(io) => io.poll(event),
         }
     }
 }
@@ -14,9 +14,10 @@ pub use crate::api::fs::{dirname, filename, realpath, FileIO, IO};
 pub use crate::sys::ata::BLOCK_SIZE;
 pub use bitmap_block::BITMAP_SIZE;
 pub use block_device::{
-    dismount, format_ata, format_mem, is_mounted, mount_ata, mount_mem
+    dismount, format_ahci, format_ata, format_mem, format_virtio, is_mounted,
+    mount_ahci, mount_ata, mount_mem, mount_virtio
 };
-pub use device::{Device, DeviceType};
+pub use device::{read_drive_blocks, Device, DeviceType};
 pub use dir::Dir;
 pub use dir_entry::FileInfo;
 pub use file::{File, SeekFrom};
@@ -202,4 +203,18 @@ pub fn init() {
             }
         }
     }
+    for drive in sys::ahci::list() {
+        if SuperBlock::check_ahci(drive.port) {
+            log!("MFS Superblock found in AHCI {}", drive.port);
+            mount_ahci(drive.port);
+            return;
+        }
+    }
+    for drive in sys::virtio::blk::list() {
+        if SuperBlock::check_virtio(drive.id) {
+            log!("MFS Superblock found in VIRTIO BLK {}", drive.id);
+            mount_virtio(drive.id);
+            return;
+        }
+    }
 }
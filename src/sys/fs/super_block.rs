@@ -25,6 +25,22 @@ impl SuperBlock {
         &buf[0..8] == SIGNATURE
     }
 
+    pub fn check_ahci(port: u8) -> bool {
+        let mut buf = [0u8; super::BLOCK_SIZE];
+        if sys::ahci::read(port, SUPERBLOCK_ADDR, &mut buf).is_err() {
+            return false;
+        }
+        &buf[0..8] == SIGNATURE
+    }
+
+    pub fn check_virtio(id: u8) -> bool {
+        let mut buf = [0u8; super::BLOCK_SIZE];
+        if sys::virtio::blk::read(id, SUPERBLOCK_ADDR, &mut buf).is_err() {
+            return false;
+        }
+        &buf[0..8] == SIGNATURE
+    }
+
     pub fn new() -> Option<Self> {
         if let Some(ref dev) = *super::block_device::BLOCK_DEVICE.lock() {
             let mut sb = Self {
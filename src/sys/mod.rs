@@ -38,6 +38,7 @@ macro_rules! log {
 }
 
 pub mod acpi;
+pub mod ahci;
 pub mod allocator;
 pub mod ata;
 pub mod clock;
@@ -59,3 +60,4 @@ pub mod serial;
 pub mod syscall;
 pub mod time;
 pub mod vga;
+pub mod virtio;
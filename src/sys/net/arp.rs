@@ -0,0 +1,181 @@
+use crate::sys;
+use crate::sys::net::{EthernetDeviceIO, NetworkInterface};
+
+use alloc::vec;
+use alloc::vec::Vec;
+use smoltcp::wire::{ArpOperation, ArpPacket, ArpRepr, EthernetAddress};
+use smoltcp::wire::{EthernetFrame, EthernetProtocol, EthernetRepr};
+use smoltcp::wire::{HardwareAddress, IpCidr, Ipv4Address};
+
+// The neighbor cache of smoltcp is private so the ARP packets received by
+// each device are also used to keep a copy of it to be able to list its
+// entries. The static entries are given to the cache of the interface by
+// receiving fake ARP replies before their expiration.
+
+// Lifetime of an entry in the neighbor cache of smoltcp
+const ENTRY_LIFETIME: f64 = 60.0;
+
+// Delay between two replies given to the cache for a static entry
+const REFRESH_INTERVAL: f64 = 30.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Neighbor {
+    pub addr: Ipv4Address,
+    pub mac: EthernetAddress,
+    pub updated_at: f64, // Uptime
+    pub is_static: bool,
+}
+
+impl Neighbor {
+    pub fn age(&self) -> f64 {
+        sys::clock::uptime() - self.updated_at
+    }
+}
+
+pub fn is_arp(frame: &[u8]) -> bool {
+    frame.get(12..14) == Some(&[0x08, 0x06])
+}
+
+fn parse(frame: &[u8]) -> Option<ArpRepr> {
+    let frame = EthernetFrame::new_checked(frame).ok()?;
+    let packet = ArpPacket::new_checked(frame.payload()).ok()?;
+    ArpRepr::parse(&packet).ok()
+}
+
+fn reply(
+    src_mac: EthernetAddress,
+    src_addr: Ipv4Address,
+    dst_mac: EthernetAddress,
+    dst_addr: Ipv4Address
+) -> Vec<u8> {
+    let arp_repr = ArpRepr::EthernetIpv4 {
+        operation: ArpOperation::Reply,
+        source_hardware_addr: src_mac,
+        source_protocol_addr: src_addr,
+        target_hardware_addr: dst_mac,
+        target_protocol_addr: dst_addr,
+    };
+    let eth_repr = EthernetRepr {
+        src_addr: src_mac,
+        dst_addr: dst_mac,
+        ethertype: EthernetProtocol::Arp,
+    };
+    let mut buf = vec![0; eth_repr.buffer_len() + arp_repr.buffer_len()];
+    let mut frame = EthernetFrame::new_unchecked(&mut buf);
+    eth_repr.emit(&mut frame);
+    arp_repr.emit(&mut ArpPacket::new_unchecked(frame.payload_mut()));
+    buf
+}
+
+// Return the address of the interface in the same network as a neighbor
+fn local_addr(ni: &NetworkInterface, addr: Ipv4Address) -> Option<Ipv4Address> {
+    ni.iface.ip_addrs().iter().find_map(|cidr| match cidr {
+        IpCidr::Ipv4(cidr) if cidr.contains_addr(&addr) => Some(cidr.address()),
+        _ => None,
+    })
+}
+
+// Called after each poll of the interface
+pub fn update(ni: &mut NetworkInterface) {
+    let now = sys::clock::uptime();
+    let HardwareAddress::Ethernet(mac) = ni.iface.hardware_addr();
+
+    // Follow the rules used by smoltcp to fill its cache
+    for frame in ni.device.config().take_arp_packets() {
+        if let Some(ArpRepr::EthernetIpv4 {
+            source_hardware_addr, source_protocol_addr, target_protocol_addr, ..
+        }) = parse(&frame) {
+            let addr = source_protocol_addr;
+            let is_ours = ni.iface.has_ip_addr(target_protocol_addr);
+            let is_unicast = addr.is_unicast()
+                          && source_hardware_addr.is_unicast();
+            if !is_ours || !is_unicast || local_addr(ni, addr).is_none() {
+                continue;
+            }
+            match ni.neighbors.iter_mut().find(|n| n.addr == addr) {
+                Some(n) if n.is_static => {
+                    // Give the static entry back to the cache if it has been
+                    // replaced by another address
+                    if n.mac != source_hardware_addr {
+                        n.updated_at = 0.0;
+                    }
+                }
+                Some(n) => {
+                    n.mac = source_hardware_addr;
+                    n.updated_at = now;
+                }
+                None => {
+                    ni.neighbors.push(Neighbor {
+                        addr,
+                        mac: source_hardware_addr,
+                        updated_at: now,
+                        is_static: false,
+                    });
+                }
+            }
+        }
+    }
+
+    ni.neighbors.retain(|n| n.is_static || now - n.updated_at < ENTRY_LIFETIME);
+
+    let config = ni.device.config();
+    for i in 0..ni.neighbors.len() {
+        let n = &ni.neighbors[i];
+        if !n.is_static || now - n.updated_at < REFRESH_INTERVAL {
+            continue;
+        }
+        if let Some(local_addr) = local_addr(ni, n.addr) {
+            config.push_rx_packet(reply(n.mac, n.addr, mac, local_addr));
+            ni.neighbors[i].updated_at = now;
+        }
+    }
+}
+
+pub fn add(ni: &mut NetworkInterface, addr: Ipv4Address, mac: EthernetAddress) {
+    ni.neighbors.retain(|n| n.addr != addr);
+    ni.neighbors.push(Neighbor {
+        addr,
+        mac,
+        updated_at: 0.0, // Given to the cache after the next poll
+        is_static: true,
+    });
+}
+
+pub fn delete(ni: &mut NetworkInterface, addr: Ipv4Address) -> Result<(), ()> {
+    let n = ni.neighbors.len();
+    ni.neighbors.retain(|n| n.addr != addr);
+    if ni.neighbors.len() < n {
+        flush(ni);
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+// Remove every entry from the cache except the static ones that will be
+// given back to it after the next poll.
+pub fn flush(ni: &mut NetworkInterface) {
+    // Updating the addresses of the interface is flushing its cache
+    ni.iface.update_ip_addrs(|_| {});
+    ni.neighbors.retain(|n| n.is_static);
+    for n in ni.neighbors.iter_mut() {
+        n.updated_at = 0.0;
+    }
+}
+
+#[test_case]
+fn test_reply() {
+    let src_mac = EthernetAddress([0x52, 0x55, 0x0A, 0x00, 0x02, 0x02]);
+    let dst_mac = EthernetAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    let src_addr = Ipv4Address::new(10, 0, 2, 2);
+    let dst_addr = Ipv4Address::new(10, 0, 2, 15);
+    let frame = reply(src_mac, src_addr, dst_mac, dst_addr);
+    assert!(is_arp(&frame));
+    assert_eq!(parse(&frame), Some(ArpRepr::EthernetIpv4 {
+        operation: ArpOperation::Reply,
+        source_hardware_addr: src_mac,
+        source_protocol_addr: src_addr,
+        target_hardware_addr: dst_mac,
+        target_protocol_addr: dst_addr,
+    }));
+}
@@ -0,0 +1,141 @@
+use crate::sys;
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::ptr::addr_of_mut;
+use smoltcp::iface::{Interface, SocketHandle, SocketSet};
+use smoltcp::socket::dhcpv4;
+use smoltcp::wire::{DhcpRepr, IpCidr, Ipv4Address, Ipv4Cidr};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+// The DHCP client runs in the background with the other sockets of the
+// interface it is configuring, and there can only be one interface using
+// it at a time. The socket is taking care of renewing the lease at T1 and of
+// rebinding it at T2, and its events are processed after each poll of the
+// network stack to apply any changes to the interface.
+
+static CLIENT: Mutex<Option<(usize, SocketHandle)>> = Mutex::new(None);
+static LEASE: Mutex<Option<Lease>> = Mutex::new(None);
+
+// The last packet received from the server is needed to get the duration of
+// the lease. The buffer is owned by the client and given to each of its
+// sockets in turn, only while holding the lock on `CLIENT` and after the
+// removal of the previous socket, so that it is never borrowed twice.
+static mut PACKET_BUFFER: [u8; 1500] = [0; 1500];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub server: Ipv4Address,
+    pub address: Ipv4Cidr,
+    pub router: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+    pub expires_at: Option<f64>, // Uptime
+}
+
+pub fn start(i: usize) -> Result<(), ()> {
+    interrupts::without_interrupts(|| {
+        let mut net = sys::net::NET.lock();
+        if i >= net.len() {
+            return Err(());
+        }
+        let mut client = CLIENT.lock();
+        if let Some((j, handle)) = client.take() {
+            net[j].sockets.remove(handle);
+        }
+        *LEASE.lock() = None;
+
+        let buffer = unsafe { &mut *addr_of_mut!(PACKET_BUFFER) };
+        let mut socket = dhcpv4::Socket::new();
+        socket.set_receive_packet_buffer(buffer);
+        *client = Some((i, net[i].sockets.add(socket)));
+        Ok(())
+    })
+}
+
+pub fn stop() {
+    interrupts::without_interrupts(|| {
+        if let Some((i, handle)) = CLIENT.lock().take() {
+            sys::net::NET.lock()[i].sockets.remove(handle);
+        }
+        *LEASE.lock() = None;
+    })
+}
+
+// Return the index of the interface configured by the client
+pub fn interface() -> Option<usize> {
+    interrupts::without_interrupts(|| CLIENT.lock().map(|(i, _)| i))
+}
+
+pub fn lease() -> Option<Lease> {
+    interrupts::without_interrupts(|| LEASE.lock().clone())
+}
+
+// Called by `sys::net::poll` after polling each interface
+pub fn update(i: usize, iface: &mut Interface, sockets: &mut SocketSet) {
+    let handle = match CLIENT.try_lock().and_then(|client| *client) {
+        Some((j, handle)) if i == j => handle,
+        _ => return,
+    };
+    let mut lease = match LEASE.try_lock() {
+        Some(lease) => lease,
+        None => return,
+    };
+    match sockets.get_mut::<dhcpv4::Socket>(handle).poll() {
+        None => {}
+        Some(dhcpv4::Event::Configured(config)) => {
+            let duration = config.packet.as_ref().and_then(|packet|
+                DhcpRepr::parse(packet).ok()
+            ).and_then(|repr| repr.lease_duration);
+            let new_lease = Lease {
+                server: config.server.identifier,
+                address: config.address,
+                router: config.router,
+                dns_servers: config.dns_servers.iter().cloned().collect(),
+                expires_at: duration.map(|d|
+                    sys::clock::uptime() + d as f64
+                ),
+            };
+            configure(iface, lease.as_ref(), &new_lease);
+            *lease = Some(new_lease);
+        }
+        Some(dhcpv4::Event::Deconfigured) => {
+            if lease.take().is_some() {
+                iface.update_ip_addrs(|addrs| {
+                    addrs.retain(|addr| !matches!(addr, IpCidr::Ipv4(_)));
+                });
+                iface.routes_mut().remove_default_ipv4_route();
+                log!("NET DHCP Lease lost");
+            }
+        }
+    }
+}
+
+// Apply the changes between the old and the new lease to the interface
+fn configure(iface: &mut Interface, old: Option<&Lease>, new: &Lease) {
+    if old.map(|lease| lease.address) != Some(new.address) {
+        iface.update_ip_addrs(|addrs| {
+            addrs.retain(|addr| !matches!(addr, IpCidr::Ipv4(_)));
+            if addrs.push(IpCidr::Ipv4(new.address)).is_ok() {
+                log!("NET IP {}", new.address);
+            }
+        });
+    }
+    if old.map(|lease| lease.router) != Some(new.router) {
+        let routes = iface.routes_mut();
+        if let Some(router) = new.router {
+            if routes.add_default_ipv4_route(router).is_ok() {
+                log!("NET GW {}", router);
+            }
+        } else {
+            routes.remove_default_ipv4_route();
+        }
+    }
+    let dns_servers = old.map(|lease| &lease.dns_servers);
+    if dns_servers != Some(&new.dns_servers) && !new.dns_servers.is_empty() {
+        let servers: Vec<_> = new.dns_servers.iter().map(|server|
+            server.to_string()
+        ).collect();
+        log!("NET DNS {}", servers.join(","));
+    }
+}
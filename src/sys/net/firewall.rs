@@ -0,0 +1,197 @@
+use crate::sys::net::{parse_frame, FrameInfo, Protocol};
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use smoltcp::wire::{IpAddress, IpCidr};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+// Stateless packet filter
+//
+// Each frame received is checked against the rules in order and the first
+// matching rule decides if it is allowed or denied. The frames that are not
+// matching any rule are allowed.
+
+static RULES: Mutex<Vec<Rule>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    pub protocol: Option<Protocol>,
+    pub source: Option<IpCidr>,
+    pub port: Option<u16>,
+    pub hits: u64,
+}
+
+impl Rule {
+    // Parse a rule like "deny tcp from 10.0.2.0/24 port 80"
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut words = s.split_whitespace();
+        let action = match words.next()? {
+            "allow" => Action::Allow,
+            "deny" => Action::Deny,
+            _ => return None,
+        };
+        let mut rule = Rule {
+            action,
+            protocol: None,
+            source: None,
+            port: None,
+            hits: 0,
+        };
+        while let Some(word) = words.next() {
+            match word {
+                "from" if rule.source.is_none() => {
+                    rule.source = Some(parse_cidr(words.next()?)?);
+                }
+                "port" if rule.port.is_none() => {
+                    rule.port = Some(words.next()?.parse().ok()?);
+                }
+                _ if rule.protocol.is_none() => {
+                    rule.protocol = Some(Protocol::from(word)?);
+                }
+                _ => return None,
+            }
+        }
+        Some(rule)
+    }
+
+    fn is_match(&self, info: Option<FrameInfo>) -> bool {
+        let info = match info {
+            Some(info) => info,
+            None => {
+                let is_empty = self.protocol.is_none()
+                            && self.source.is_none()
+                            && self.port.is_none();
+                return is_empty;
+            }
+        };
+        if self.protocol.map_or(false, |p| p != info.protocol) {
+            return false;
+        }
+        if self.source.map_or(false, |c| !c.contains_addr(&info.src_addr)) {
+            return false;
+        }
+        if let Some(port) = self.port {
+            return info.ports.map_or(false, |(_, dst)| dst == port);
+        }
+        true
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.action {
+            Action::Allow => write!(f, "allow")?,
+            Action::Deny => write!(f, "deny")?,
+        }
+        if let Some(protocol) = self.protocol {
+            write!(f, " {}", protocol.name())?;
+        }
+        if let Some(source) = self.source {
+            write!(f, " from {}", source)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, " port {}", port)?;
+        }
+        Ok(())
+    }
+}
+
+// An address without a prefix is a network of its own
+fn parse_cidr(s: &str) -> Option<IpCidr> {
+    if let Ok(cidr) = IpCidr::from_str(s) {
+        return Some(cidr);
+    }
+    match IpAddress::from_str(s).ok()? {
+        addr @ IpAddress::Ipv4(_) => Some(IpCidr::new(addr, 32)),
+        addr @ IpAddress::Ipv6(_) => Some(IpCidr::new(addr, 128)),
+    }
+}
+
+// The rules are also used by the network stack when it is polled by the
+// timer interrupt handler, so they must be locked with interrupts disabled.
+pub fn rules() -> Vec<Rule> {
+    interrupts::without_interrupts(|| RULES.lock().clone())
+}
+
+pub fn add(rule: Rule) {
+    interrupts::without_interrupts(|| RULES.lock().push(rule));
+}
+
+pub fn delete(i: usize) -> Result<(), ()> {
+    interrupts::without_interrupts(|| {
+        let mut rules = RULES.lock();
+        if i < rules.len() {
+            rules.remove(i);
+            Ok(())
+        } else {
+            Err(())
+        }
+    })
+}
+
+pub fn flush() {
+    interrupts::without_interrupts(|| RULES.lock().clear());
+}
+
+// Called by the network stack for each frame received
+pub fn is_allowed(frame: &[u8]) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut rules = RULES.lock();
+        if rules.is_empty() {
+            return true;
+        }
+        let info = parse_frame(frame);
+        for rule in rules.iter_mut() {
+            if rule.is_match(info) {
+                rule.hits += 1;
+                return rule.action == Action::Allow;
+            }
+        }
+        true
+    })
+}
+
+#[test_case]
+fn test_rule_parse() {
+    use alloc::string::ToString;
+
+    let s = "deny tcp from 10.0.2.0/24 port 80";
+    let rule = Rule::parse(s).unwrap();
+    assert_eq!(rule.action, Action::Deny);
+    assert_eq!(rule.protocol, Some(Protocol::Tcp));
+    assert_eq!(rule.port, Some(80));
+    assert_eq!(rule.to_string(), s);
+
+    let rule = Rule::parse("allow from 10.0.2.2").unwrap();
+    assert_eq!(rule.to_string(), "allow from 10.0.2.2/32");
+
+    assert!(Rule::parse("allow").is_some());
+    assert!(Rule::parse("drop tcp").is_none());
+    assert!(Rule::parse("deny tcp udp").is_none());
+    assert!(Rule::parse("deny port http").is_none());
+}
+
+#[test_case]
+fn test_rule_match() {
+    let info = Some(FrameInfo {
+        protocol: Protocol::Tcp,
+        src_addr: IpAddress::v4(10, 0, 2, 2),
+        ports: Some((49152, 80)),
+    });
+    assert!(Rule::parse("deny").unwrap().is_match(info));
+    assert!(Rule::parse("deny tcp port 80").unwrap().is_match(info));
+    assert!(Rule::parse("deny from 10.0.2.0/24").unwrap().is_match(info));
+    assert!(!Rule::parse("deny udp").unwrap().is_match(info));
+    assert!(!Rule::parse("deny port 49152").unwrap().is_match(info));
+    assert!(!Rule::parse("deny from 10.0.3.0/24").unwrap().is_match(info));
+    assert!(!Rule::parse("deny tcp").unwrap().is_match(None));
+}
@@ -1,20 +1,67 @@
 mod nic;
+pub mod arp;
+pub mod dhcp;
+pub mod firewall;
+pub mod pcap;
 pub mod socket;
 
 use crate::{sys, usr};
 use crate::sys::pci::DeviceConfig;
 
+use alloc::collections::vec_deque::VecDeque;
 use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
-use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use smoltcp::iface::Interface;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use smoltcp::iface::{Interface, SocketSet};
 use smoltcp::phy::DeviceCapabilities;
-use smoltcp::time::Instant;
-use smoltcp::wire::EthernetAddress;
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr};
+use smoltcp::wire::{Ipv4Address, Ipv6Address};
 use spin::Mutex;
+use x86_64::instructions::interrupts;
 
-pub static NET: Mutex<Option<(Interface, EthernetDevice)>> = Mutex::new(None);
+// Maximum number of looped back packets waiting to be processed by the
+// network stack
+const RX_QUEUE_LEN: usize = 64;
+
+// Number of timer ticks between two polls of the network stack
+const POLL_INTERVAL: usize = 10;
+
+pub static NET: Mutex<Vec<NetworkInterface>> = Mutex::new(Vec::new());
+
+// A clone of each device used by the interrupt handler to acknowledge their
+// interrupts without having to lock the interfaces.
+static IRQ_DEVICES: Mutex<Vec<EthernetDevice>> = Mutex::new(Vec::new());
+
+static LAST_POLL: AtomicUsize = AtomicUsize::new(0);
+
+// Number of polls done to receive packets signaled by the devices
+static RX_POLLS: AtomicUsize = AtomicUsize::new(0);
+
+// Each interface has its own set of sockets, that are polled only with the
+// device of this interface.
+pub struct NetworkInterface {
+    pub name: String,
+    pub iface: Interface,
+    pub device: EthernetDevice,
+    pub sockets: SocketSet<'static>,
+    pub neighbors: Vec<arp::Neighbor>,
+}
+
+impl NetworkInterface {
+    pub fn poll(&mut self) {
+        self.device.config().clear_rx_pending();
+        self.iface.poll(time(), &mut self.device, &mut self.sockets);
+        arp::update(self);
+    }
+
+    pub fn poll_delay(&mut self) -> Option<Duration> {
+        self.iface.poll_delay(time(), &self.sockets)
+    }
+}
 
 #[repr(u8)]
 pub enum SocketStatus {
@@ -36,12 +83,14 @@ pub enum EthernetDevice {
     RTL8139(nic::rtl8139::Device),
     PCNET(nic::pcnet::Device),
     E1000(nic::e1000::Device),
-    //VirtIO,
+    VirtIO(nic::virtio::Device),
+    Loopback(nic::loopback::Device),
 }
 
 pub trait EthernetDeviceIO {
     fn config(&self) -> Arc<Config>;
     fn stats(&self) -> Arc<Stats>;
+    fn ack_interrupt(&mut self);
     fn receive_packet(&mut self) -> Option<Vec<u8>>;
     fn transmit_packet(&mut self, len: usize);
     fn next_tx_buffer(&mut self, len: usize) -> &mut [u8];
@@ -53,6 +102,8 @@ impl EthernetDeviceIO for EthernetDevice {
             EthernetDevice::RTL8139(dev) => dev.config(),
             EthernetDevice::PCNET(dev) => dev.config(),
             EthernetDevice::E1000(dev) => dev.config(),
+            EthernetDevice::VirtIO(dev) => dev.config(),
+            EthernetDevice::Loopback(dev) => dev.config(),
         }
     }
 
@@ -61,6 +112,18 @@ impl EthernetDeviceIO for EthernetDevice {
             EthernetDevice::RTL8139(dev) => dev.stats(),
             EthernetDevice::PCNET(dev) => dev.stats(),
             EthernetDevice::E1000(dev) => dev.stats(),
+            EthernetDevice::VirtIO(dev) => dev.stats(),
+            EthernetDevice::Loopback(dev) => dev.stats(),
+        }
+    }
+
+    fn ack_interrupt(&mut self) {
+        match self {
+            EthernetDevice::RTL8139(dev) => dev.ack_interrupt(),
+            EthernetDevice::PCNET(dev) => dev.ack_interrupt(),
+            EthernetDevice::E1000(dev) => dev.ack_interrupt(),
+            EthernetDevice::VirtIO(dev) => dev.ack_interrupt(),
+            EthernetDevice::Loopback(dev) => dev.ack_interrupt(),
         }
     }
 
@@ -69,6 +132,8 @@ impl EthernetDeviceIO for EthernetDevice {
             EthernetDevice::RTL8139(dev) => dev.receive_packet(),
             EthernetDevice::PCNET(dev) => dev.receive_packet(),
             EthernetDevice::E1000(dev) => dev.receive_packet(),
+            EthernetDevice::VirtIO(dev) => dev.receive_packet(),
+            EthernetDevice::Loopback(dev) => dev.receive_packet(),
         }
     }
 
@@ -77,6 +142,8 @@ impl EthernetDeviceIO for EthernetDevice {
             EthernetDevice::RTL8139(dev) => dev.transmit_packet(len),
             EthernetDevice::PCNET(dev) => dev.transmit_packet(len),
             EthernetDevice::E1000(dev) => dev.transmit_packet(len),
+            EthernetDevice::VirtIO(dev) => dev.transmit_packet(len),
+            EthernetDevice::Loopback(dev) => dev.transmit_packet(len),
         }
     }
 
@@ -85,6 +152,8 @@ impl EthernetDeviceIO for EthernetDevice {
             EthernetDevice::RTL8139(dev) => dev.next_tx_buffer(len),
             EthernetDevice::PCNET(dev) => dev.next_tx_buffer(len),
             EthernetDevice::E1000(dev) => dev.next_tx_buffer(len),
+            EthernetDevice::VirtIO(dev) => dev.next_tx_buffer(len),
+            EthernetDevice::Loopback(dev) => dev.next_tx_buffer(len),
         }
     }
 }
@@ -104,19 +173,33 @@ impl<'a> smoltcp::phy::Device for EthernetDevice {
         &mut self,
         _instant: smoltcp::time::Instant,
     ) -> Option<(Self::RxToken<'a>, Self::TxToken<'a>)> {
-        if let Some(buffer) = self.receive_packet() {
-            if self.config().is_debug_enabled() {
+        // Packets looped back to the device are received before the ones
+        // waiting in its rx ring
+        let config = self.config();
+        loop {
+            let buffer = config.pop_rx_packet().
+                or_else(|| self.receive_packet())?;
+            if config.is_debug_enabled() {
                 debug!("NET Packet Received");
                 usr::hex::print_hex(&buffer);
             }
+            if config.is_capture_enabled() {
+                pcap::capture(&buffer);
+            }
             self.stats().rx_add(buffer.len() as u64);
+
+            // Denied packets are dropped before reaching the interface
+            if !firewall::is_allowed(&buffer) {
+                continue;
+            }
+            if arp::is_arp(&buffer) {
+                config.push_arp_packet(buffer.clone());
+            }
             let rx = RxToken { buffer };
             let tx = TxToken {
                 device: self.clone(),
             };
-            Some((rx, tx))
-        } else {
-            None
+            return Some((rx, tx));
         }
     }
 
@@ -161,6 +244,9 @@ impl smoltcp::phy::TxToken for TxToken {
             debug!("NET Packet Transmitted");
             usr::hex::print_hex(buf);
         }
+        if config.is_capture_enabled() {
+            pcap::capture(buf);
+        }
         self.device.transmit_packet(len);
         self.device.stats().tx_add(len as u64);
         res
@@ -169,18 +255,26 @@ impl smoltcp::phy::TxToken for TxToken {
 
 pub struct Config {
     debug: AtomicBool,
+    capture: AtomicBool,
+    rx_pending: AtomicBool,
     mac: Mutex<Option<EthernetAddress>>,
+    rx_queue: Mutex<VecDeque<Vec<u8>>>,
+    arp_packets: Mutex<Vec<Vec<u8>>>,
 }
 
 impl Config {
     fn new() -> Self {
         Self {
             debug: AtomicBool::new(false),
+            capture: AtomicBool::new(false),
+            rx_pending: AtomicBool::new(false),
             mac: Mutex::new(None),
+            rx_queue: Mutex::new(VecDeque::new()),
+            arp_packets: Mutex::new(Vec::new()),
         }
     }
 
-    fn is_debug_enabled(&self) -> bool {
+    pub fn is_debug_enabled(&self) -> bool {
         self.debug.load(Ordering::Relaxed)
     }
 
@@ -192,6 +286,18 @@ impl Config {
         self.debug.store(false, Ordering::Relaxed)
     }
 
+    pub fn is_capture_enabled(&self) -> bool {
+        self.capture.load(Ordering::Relaxed)
+    }
+
+    pub fn enable_capture(&self) {
+        self.capture.store(true, Ordering::Relaxed);
+    }
+
+    pub fn disable_capture(&self) {
+        self.capture.store(false, Ordering::Relaxed)
+    }
+
     fn mac(&self) -> Option<EthernetAddress> {
         *self.mac.lock()
     }
@@ -199,6 +305,48 @@ impl Config {
     fn update_mac(&self, mac: EthernetAddress) {
         *self.mac.lock() = Some(mac);
     }
+
+    // Set by the interrupt handler to signal that the rx ring of the device
+    // may have packets to drain
+    fn set_rx_pending(&self) {
+        self.rx_pending.store(true, Ordering::Relaxed);
+    }
+
+    fn clear_rx_pending(&self) {
+        self.rx_pending.store(false, Ordering::Relaxed);
+    }
+
+    fn is_rx_pending(&self) -> bool {
+        self.rx_pending.load(Ordering::Relaxed)
+    }
+
+    // Packets sent to ourself are queued to be received on the next poll
+    fn push_rx_packet(&self, packet: Vec<u8>) {
+        let mut queue = self.rx_queue.lock();
+        if queue.len() < RX_QUEUE_LEN {
+            queue.push_back(packet);
+        }
+    }
+
+    fn pop_rx_packet(&self) -> Option<Vec<u8>> {
+        self.rx_queue.lock().pop_front()
+    }
+
+    fn has_rx_packet(&self) -> bool {
+        !self.rx_queue.lock().is_empty()
+    }
+
+    // A copy of the ARP packets received since the last poll
+    fn push_arp_packet(&self, packet: Vec<u8>) {
+        let mut packets = self.arp_packets.lock();
+        if packets.len() < RX_QUEUE_LEN {
+            packets.push(packet);
+        }
+    }
+
+    fn take_arp_packets(&self) -> Vec<Vec<u8>> {
+        core::mem::take(&mut *self.arp_packets.lock())
+    }
 }
 
 pub struct Stats {
@@ -245,13 +393,208 @@ impl Stats {
     }
 }
 
-fn find_device(vendor_id: u16, device_id: u16) -> Option<DeviceConfig> {
-    if let Some(mut dev) = sys::pci::find_device(vendor_id, device_id) {
+// Combine a 64 bits prefix with an interface identifier made from the MAC
+// address in the modified EUI-64 format (RFC 4291)
+pub fn ipv6_eui64_addr(
+    prefix: Ipv6Address,
+    mac: EthernetAddress
+) -> Ipv6Address {
+    let mac = mac.as_bytes();
+    let mut bytes = [0; 16];
+    bytes[0..8].copy_from_slice(&prefix.as_bytes()[0..8]);
+    bytes[8] = mac[0] ^ 0x02; // Flip the universal/local bit
+    bytes[9] = mac[1];
+    bytes[10] = mac[2];
+    bytes[11] = 0xFF;
+    bytes[12] = 0xFE;
+    bytes[13..16].copy_from_slice(&mac[3..6]);
+    Ipv6Address::from_bytes(&bytes)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Arp,
+    Icmp,
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn from(name: &str) -> Option<Self> {
+        match name {
+            "arp" => Some(Protocol::Arp),
+            "icmp" => Some(Protocol::Icmp),
+            "tcp" => Some(Protocol::Tcp),
+            "udp" => Some(Protocol::Udp),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Protocol::Arp => "arp",
+            Protocol::Icmp => "icmp",
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+// The fields of a frame used to filter it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    pub protocol: Protocol,
+    pub src_addr: IpAddress,
+    pub ports: Option<(u16, u16)>,
+}
+
+fn read_u16(buf: &[u8], i: usize) -> Option<u16> {
+    let bytes = buf.get(i..i + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// Return the protocol of a frame, its source address, and its source and
+// destination ports for TCP and UDP.
+pub fn parse_frame(frame: &[u8]) -> Option<FrameInfo> {
+    let (next_header, src_addr, offset) = match read_u16(frame, 12)? {
+        0x0806 => {
+            // The sender protocol address of an IPv4 ARP packet
+            let src_addr = Ipv4Address::from_bytes(frame.get(28..32)?).into();
+            let protocol = Protocol::Arp;
+            let ports = None;
+            return Some(FrameInfo { protocol, src_addr, ports });
+        }
+        0x0800 => {
+            let ihl = (*frame.get(14)? & 0x0F) as usize * 4;
+            let src_addr = Ipv4Address::from_bytes(frame.get(26..30)?);
+            (*frame.get(23)?, IpAddress::from(src_addr), 14 + ihl)
+        }
+        0x86DD => {
+            let src_addr = Ipv6Address::from_bytes(frame.get(22..38)?);
+            (*frame.get(20)?, IpAddress::from(src_addr), 14 + 40)
+        }
+        _ => return None,
+    };
+    let protocol = match next_header {
+        1 | 58 => Protocol::Icmp,
+        6 => Protocol::Tcp,
+        17 => Protocol::Udp,
+        _ => return None,
+    };
+    let ports = match protocol {
+        Protocol::Tcp | Protocol::Udp => {
+            Some((read_u16(frame, offset)?, read_u16(frame, offset + 2)?))
+        }
+        _ => None,
+    };
+    Some(FrameInfo { protocol, src_addr, ports })
+}
+
+// Select the interface used to reach an address with the longest prefix
+// matching it among the networks of each interface and their routes. The
+// first interface is used when more than one has the same prefix length.
+pub fn route(net: &mut [NetworkInterface], addr: &IpAddress) -> Option<usize> {
+    let mut res: Option<(u8, usize)> = None;
+    for (i, ni) in net.iter_mut().enumerate() {
+        let mut cidrs: Vec<IpCidr> = ni.iface.ip_addrs().to_vec();
+        ni.iface.routes_mut().update(|storage| {
+            cidrs.extend(storage.iter().map(|route| route.cidr));
+        });
+        for cidr in cidrs {
+            let len = cidr.prefix_len();
+            if cidr.contains_addr(addr) && res.map_or(true, |(n, _)| len > n) {
+                res = Some((len, i));
+            }
+        }
+    }
+    res.map(|(_, i)| i)
+}
+
+pub fn find_interface(name: &str) -> Option<usize> {
+    interrupts::without_interrupts(||
+        NET.lock().iter().position(|ni| ni.name == name)
+    )
+}
+
+fn has_rx_packet() -> bool {
+    IRQ_DEVICES.lock().iter().any(|device| {
+        let config = device.config();
+        config.is_rx_pending() || config.has_rx_packet()
+    })
+}
+
+// The interrupt handler doesn't touch the rx rings and doesn't allocate, it
+// only acknowledges the interrupt and leaves the packets to the next poll.
+fn interrupt_handler() {
+    for device in IRQ_DEVICES.lock().iter_mut() {
+        device.ack_interrupt();
+        device.config().set_rx_pending();
+    }
+}
+
+// Called from process context while waiting for the user, for the time or
+// for packets, to keep the network stack running when it's not already
+// polled by a syscall or a command holding the lock.
+pub fn poll() {
+    let now = sys::time::ticks();
+    let last = LAST_POLL.load(Ordering::Relaxed);
+    let is_due = now.wrapping_sub(last) >= POLL_INTERVAL;
+
+    // The queues of the devices are checked only while holding the lock on
+    // the network, as they are used without disabling interrupts
+    if let Some(mut net) = NET.try_lock() {
+        let has_rx = interrupts::without_interrupts(has_rx_packet);
+        if !is_due && !has_rx {
+            return;
+        }
+        if has_rx {
+            RX_POLLS.fetch_add(1, Ordering::Relaxed);
+        }
+        LAST_POLL.store(now, Ordering::Relaxed);
+        for (i, ni) in net.iter_mut().enumerate() {
+            ni.poll();
+            dhcp::update(i, &mut ni.iface, &mut ni.sockets);
+        }
+    }
+}
+
+// Called by the timer interrupt handler to keep the network stack running
+// while a program is busy without waiting. The packets signaled by the
+// interrupt handler of the devices are received on the next tick, unless the
+// interrupted code is holding the lock on the network or on the heap, in
+// which case the poll is deferred to a later tick.
+pub fn timer_poll() {
+    if !sys::allocator::is_locked() {
+        poll();
+    }
+}
+
+// Wait until the delay has expired or a packet has been received, possibly
+// already processed by the timer interrupt handler
+pub fn wait(delay: Duration) {
+    let seconds = (delay.total_micros() as f64) / 1000000.0;
+    let started = sys::clock::uptime();
+    let polls = RX_POLLS.load(Ordering::Relaxed);
+    while sys::clock::uptime() - started < seconds {
+        if interrupts::without_interrupts(has_rx_packet) {
+            break;
+        }
+        if RX_POLLS.load(Ordering::Relaxed) != polls {
+            break;
+        }
+        sys::time::halt();
+    }
+}
+
+// Find every network card of a model to give each one its own interface
+fn find_devices(vendor_id: u16, device_id: u16) -> Vec<DeviceConfig> {
+    let mut devices: Vec<_> = sys::pci::list().into_iter().filter(|dev|
+        dev.vendor_id == vendor_id && dev.device_id == device_id
+    ).collect();
+    for dev in devices.iter_mut() {
         dev.enable_bus_mastering();
-        Some(dev)
-    } else {
-        None
     }
+    devices
 }
 
 const E1000_DEVICES: [u16; 9] = [
@@ -266,36 +609,97 @@ const E1000_DEVICES: [u16; 9] = [
     0x153A, // I217-LM
 ];
 
+// The interfaces are named in the order in which their devices are found
 pub fn init() {
-    let add = |mut device: EthernetDevice, name| {
-        log!("NET DRV {}", name);
+    let add = |mut device: EthernetDevice, driver, irq: u8| {
+        let is_loopback = matches!(device, EthernetDevice::Loopback(_));
+        let name = if is_loopback {
+            "lo".into()
+        } else {
+            format!("eth{}", NET.lock().len())
+        };
+        log!("NET DRV {} {}", driver, name);
         if let Some(mac) = device.config().mac() {
             let addr = format!("{}", mac).to_uppercase();
             log!("NET MAC {}", addr);
 
             let config = smoltcp::iface::Config::new(mac.into());
-            let iface = Interface::new(config, &mut device, time());
+            let mut iface = Interface::new(config, &mut device, time());
+
+            // Loopback addresses
+            if is_loopback {
+                let addr = Ipv4Address::new(127, 0, 0, 1);
+                iface.update_ip_addrs(|addrs| {
+                    addrs.push(IpCidr::new(addr.into(), 8)).unwrap();
+                });
+                log!("NET IP {}/8", addr);
+
+                let addr = Ipv6Address::LOOPBACK;
+                iface.update_ip_addrs(|addrs| {
+                    addrs.push(IpCidr::new(addr.into(), 128)).unwrap();
+                });
+                log!("NET IP6 {}/128", addr);
+            }
 
-            *NET.lock() = Some((iface, device));
+            // Link-local IPv6 address, also needed on the loopback interface
+            // because smoltcp never selects `::1` as a source address.
+            let prefix = Ipv6Address::new(0xFE80, 0, 0, 0, 0, 0, 0, 0);
+            let addr = ipv6_eui64_addr(prefix, mac);
+            iface.update_ip_addrs(|addrs| {
+                addrs.push(IpCidr::new(addr.into(), 64)).unwrap();
+            });
+            log!("NET IP6 {}/64", addr);
+
+            let sockets = SocketSet::new(vec![]);
+            interrupts::without_interrupts(||
+                IRQ_DEVICES.lock().push(device.clone())
+            );
+            let neighbors = Vec::new();
+            NET.lock().push(NetworkInterface {
+                name, iface, device, sockets, neighbors
+            });
+            if irq < 16 {
+                sys::idt::set_irq_handler(irq, interrupt_handler);
+            }
         }
     };
-    if let Some(dev) = find_device(0x10EC, 0x8139) {
+    for dev in find_devices(0x10EC, 0x8139) {
         let io = dev.io_base();
         let nic = nic::rtl8139::Device::new(io);
-        add(EthernetDevice::RTL8139(nic), "RTL8139");
+        add(EthernetDevice::RTL8139(nic), "RTL8139", dev.interrupt_line);
     }
-    if let Some(dev) = find_device(0x1022, 0x2000) {
+    for dev in find_devices(0x1022, 0x2000) {
         let io = dev.io_base();
         let nic = nic::pcnet::Device::new(io);
-        add(EthernetDevice::PCNET(nic), "PCNET");
+        add(EthernetDevice::PCNET(nic), "PCNET", dev.interrupt_line);
     }
     for id in E1000_DEVICES {
-        if let Some(dev) = find_device(0x8086, id) {
+        for dev in find_devices(0x8086, id) {
             let io = dev.io_base();
             let mem = dev.mem_base();
             let bar = dev.bar_type();
             let nic = nic::e1000::Device::new(io, mem, bar);
-            add(EthernetDevice::E1000(nic), "E1000");
+            add(EthernetDevice::E1000(nic), "E1000", dev.interrupt_line);
         }
     }
+    for dev in find_devices(0x1AF4, 0x1000) {
+        let io = dev.io_base();
+        if let Some(nic) = nic::virtio::Device::new(io) {
+            add(EthernetDevice::VirtIO(nic), "VIRTIO", dev.interrupt_line);
+        }
+    }
+
+    // The loopback interface is added last to keep the network cards first
+    // in the list of interfaces, and to route the link-local addresses to
+    // them.
+    let nic = nic::loopback::Device::new();
+    add(EthernetDevice::Loopback(nic), "LOOPBACK", 0xFF);
+}
+
+#[test_case]
+fn test_ipv6_eui64_addr() {
+    let prefix = Ipv6Address::new(0xFE80, 0, 0, 0, 0, 0, 0, 0);
+    let mac = EthernetAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    let addr = Ipv6Address::new(0xFE80, 0, 0, 0, 0x5054, 0xFF, 0xFE12, 0x3456);
+    assert_eq!(ipv6_eui64_addr(prefix, mac), addr);
 }
@@ -47,6 +47,7 @@ const ICR_RXDMT0: u32 = 1 << 4; // Receive Descriptor Minimum Threshold Reached
 const ICR_RXT0: u32 =   1 << 7; // Receiver Timer Interrupt
 
 const RCTL_EN: u32 =    1 << 1;  // Receiver Enable
+const RCTL_MPE: u32 =   1 << 4;  // Multicast Promiscuous Enabled
 const RCTL_BAM: u32 =   1 << 15; // Broadcast Accept Mode
 const RCTL_SECRC: u32 = 1 << 26; // Strip Ethernet CRC
 
@@ -186,9 +187,8 @@ impl Device {
         self.init_tx();
         self.link_up();
 
-        // TODO: Enable interrupts
-        //self.write(REG_IMS, ICR_LSC | ICR_RXDMT0 | ICR_RXT0);
-        self.write(REG_IMS, 0);
+        // Enable interrupts
+        self.write(REG_IMS, ICR_LSC | ICR_RXDMT0 | ICR_RXT0);
 
         // Clear interrupts
         self.read(REG_ICR);
@@ -221,7 +221,8 @@ impl Device {
         self.write(REG_RDT, (n - 1) as u32);
 
         // Control Register
-        self.write(REG_RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC | RCTL_BSIZE);
+        let flags = RCTL_EN | RCTL_MPE | RCTL_BAM | RCTL_SECRC | RCTL_BSIZE;
+        self.write(REG_RCTL, flags);
     }
 
     fn init_tx(&mut self) {
@@ -381,28 +382,20 @@ impl EthernetDeviceIO for Device {
         self.stats.clone()
     }
 
-    fn receive_packet(&mut self) -> Option<Vec<u8>> {
+    fn ack_interrupt(&mut self) {
         let icr = self.read(REG_ICR);
         self.write(REG_ICR, icr);
 
         // Link Status Change
-        if icr & ICR_LSC > 0 {
-            if self.read(REG_STATUS) & DSTA_LU == 0 {
-                self.link_up();
-                return None;
-            }
-        }
-
-        // Receive Descriptor Minimum Threshold
-        if icr & ICR_RXDMT0 > 0 {
-            // TODO
+        if icr & ICR_LSC > 0 && self.read(REG_STATUS) & DSTA_LU == 0 {
+            self.link_up();
         }
 
-        // Receiver Timer Interrupt
-        if icr & ICR_RXT0 > 0 {
-            // TODO
-        }
+        // The receive interrupts (RXT0 and RXDMT0) don't need anything more
+        // than draining the rx ring which is done on the next poll.
+    }
 
+    fn receive_packet(&mut self) -> Option<Vec<u8>> {
         let rx_id = self.rx_id.load(Ordering::SeqCst);
         let mut rx_descs = self.rx_descs.lock();
 
@@ -0,0 +1,64 @@
+use crate::sys::net::{Config, EthernetDeviceIO, Stats};
+
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use smoltcp::wire::EthernetAddress;
+use spin::Mutex;
+
+const MTU: usize = 1536;
+
+// Maximum number of packets waiting to be received
+const QUEUE_LEN: usize = 64;
+
+// Virtual device of the loopback interface, every packet transmitted is
+// received back by the network stack.
+#[derive(Clone)]
+pub struct Device {
+    config: Arc<Config>,
+    stats: Arc<Stats>,
+    queue: Arc<Mutex<VecDeque<Vec<u8>>>>,
+    tx_buffer: Vec<u8>,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        let device = Self {
+            config: Arc::new(Config::new()),
+            stats: Arc::new(Stats::new()),
+            queue: Arc::new(Mutex::new(VecDeque::new())),
+            tx_buffer: vec![0; MTU],
+        };
+        device.config.update_mac(EthernetAddress([0; 6]));
+        device
+    }
+}
+
+impl EthernetDeviceIO for Device {
+    fn config(&self) -> Arc<Config> {
+        self.config.clone()
+    }
+
+    fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    fn ack_interrupt(&mut self) {
+    }
+
+    fn receive_packet(&mut self) -> Option<Vec<u8>> {
+        self.queue.lock().pop_front()
+    }
+
+    fn transmit_packet(&mut self, len: usize) {
+        let mut queue = self.queue.lock();
+        if queue.len() < QUEUE_LEN {
+            queue.push_back(self.tx_buffer[0..len].to_vec());
+        }
+    }
+
+    fn next_tx_buffer(&mut self, len: usize) -> &mut [u8] {
+        &mut self.tx_buffer[0..len]
+    }
+}
@@ -1,3 +1,5 @@
 pub mod e1000;
+pub mod loopback;
 pub mod pcnet;
 pub mod rtl8139;
+pub mod virtio;
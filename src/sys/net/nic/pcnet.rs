@@ -15,7 +15,7 @@ const CSR0_STRT: usize = 1;
 const CSR0_TDMD: usize = 3;
 //const CSR0_TXON: usize = 4;
 //const CSR0_RXON: usize = 5;
-//const CSR0_IENA: usize = 6;
+const CSR0_IENA: usize = 6;
 //const CSR0_INTR: usize = 7;
 const CSR0_IDON: usize = 8;
 //const CSR0_TINT: usize = 9;
@@ -205,6 +205,8 @@ impl Device {
         init_struct[7] = mac[3];
         init_struct[8] = mac[4];
         init_struct[9] = mac[5];
+        // Logical address filter accepting every multicast packet
+        init_struct[12..20].fill(0xFF);
         let rx_addr = self.rx_des.addr().to_le_bytes();
         init_struct[20] = rx_addr[0];
         init_struct[21] = rx_addr[1];
@@ -235,11 +237,11 @@ impl Device {
         // IDON + INTR + INIT
         debug_assert!(self.ports.read_csr_32(0) == 0b110000001);
 
-        // Start the card
-        self.ports.write_csr_32(0, 1 << CSR0_STRT);
+        // Start the card with interrupts enabled
+        self.ports.write_csr_32(0, (1 << CSR0_STRT) | (1 << CSR0_IENA));
 
-        // IDON + INTR + RXON + TXON + STRT + INIT
-        debug_assert!(self.ports.read_csr_32(0) == 0b110110011);
+        // IDON + INTR + IENA + RXON + TXON + STRT + INIT
+        debug_assert!(self.ports.read_csr_32(0) == 0b111110011);
     }
 
     fn init_descriptor_entry(&mut self, i: usize, is_rx: bool) {
@@ -280,6 +282,14 @@ impl EthernetDeviceIO for Device {
         self.stats.clone()
     }
 
+    fn ack_interrupt(&mut self) {
+        // Clear the interrupt flags (BABL, CERR, MISS, MERR, RINT, TINT and
+        // IDON) by writing them back while keeping interrupts enabled
+        let csr_0 = self.ports.read_csr_32(0);
+        let flags = csr_0.get_bits(8..15) << 8;
+        self.ports.write_csr_32(0, flags | (1 << CSR0_IENA));
+    }
+
     fn receive_packet(&mut self) -> Option<Vec<u8>> {
         let mut packet = Vec::new();
         let mut rx_id = self.rx_id.load(Ordering::SeqCst);
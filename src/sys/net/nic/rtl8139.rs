@@ -61,7 +61,7 @@ const TCR_MXDMA2: u32 = 1 << 10;
 
 // Interrupt Mask Register
 //const IMR_TOK: u16 = 1 << 2; // Transmit OK Interrupt
-//const IMR_ROK: u16 = 1 << 0; // Receive OK Interrupt
+const IMR_ROK: u16 = 1 << 0; // Receive OK Interrupt
 
 //const CRS: u32 = 1 << 31; // Carrier Sense Lost
 //const TAB: u32 = 1 << 30; // Transmit Abort
@@ -98,10 +98,10 @@ pub struct Ports {
     pub cmd: Port<u8>,
 
     // Interrupt Mask Register (IMR)
-    //pub imr: Port<u16>,
+    pub imr: Port<u16>,
 
     // Interrupt Status Register (ISR)
-    //pub isr: Port<u16>,
+    pub isr: Port<u16>,
 
     // Transmit (Tx) Configuration Register (TCR)
     pub tx_config: Port<u32>,
@@ -138,8 +138,8 @@ impl Ports {
             capr: Port::new(io_base + 0x38),
             cba: Port::new(io_base + 0x3A),
             cmd: Port::new(io_base + 0x37),
-            //imr: Port::new(io_base + 0x3C),
-            //isr: Port::new(io_base + 0x3E),
+            imr: Port::new(io_base + 0x3C),
+            isr: Port::new(io_base + 0x3E),
             tx_config: Port::new(io_base + 0x40),
             rx_config: Port::new(io_base + 0x44),
         }
@@ -208,7 +208,7 @@ impl Device {
         }
 
         // Set interrupts
-        //unsafe { self.ports.imr.write(IMR_TOK | IMR_ROK) }
+        unsafe { self.ports.imr.write(IMR_ROK) }
 
         // Enable Receive and Transmitter
         unsafe { self.ports.cmd.write(CR_RE | CR_TE) }
@@ -249,6 +249,14 @@ impl EthernetDeviceIO for Device {
         self.stats.clone()
     }
 
+    fn ack_interrupt(&mut self) {
+        // Clear the interrupt by writing back the status bits
+        unsafe {
+            let isr = self.ports.isr.read();
+            self.ports.isr.write(isr);
+        }
+    }
+
     // RxToken buffer, when not empty, will contains:
     // [header            (2 bytes)]
     // [length            (2 bytes)]
@@ -321,15 +329,3 @@ impl EthernetDeviceIO for Device {
         &mut self.tx_buffers[tx_id][0..len]
     }
 }
-
-/*
-pub fn interrupt_handler() {
-    printk!("RTL8139 interrupt!\n");
-    if let Some(mut guard) = sys::net::IFACE.try_lock() {
-        if let Some(ref mut iface) = *guard {
-            // Clear the interrupt
-            unsafe { iface.device_mut().ports.isr.write(0xFFFF) }
-        }
-    }
-}
-*/
@@ -0,0 +1,188 @@
+use crate::sys;
+use crate::sys::allocator::PhysBuf;
+use crate::sys::net::{Config, EthernetDeviceIO, Stats};
+use crate::sys::virtio::{Transport, Virtqueue};
+use crate::sys::virtio::{STATUS_DRIVER_OK, STATUS_FAILED};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use smoltcp::wire::EthernetAddress;
+use spin::Mutex;
+
+// Virtual I/O Device (VIRTIO) Version 1.0
+// Network Device (5.1)
+
+const F_MAC: u32 = 1 << 5; // Device has given MAC address
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+
+// Every packet is preceded by a header used for checksum and segmentation
+// offloading that we don't negotiate, so it is left zeroed.
+const HEADER_LEN: usize = 10;
+
+const MTU: usize = 1536;
+const RX_BUFFERS_COUNT: usize = 32;
+const TX_BUFFERS_COUNT: usize = 8;
+
+#[derive(Clone)]
+pub struct Device {
+    config: Arc<Config>,
+    stats: Arc<Stats>,
+    transport: Transport,
+    rx_queue: Virtqueue,
+    tx_queue: Virtqueue,
+
+    rx_buffers: Vec<PhysBuf>,
+    tx_buffers: [PhysBuf; TX_BUFFERS_COUNT],
+    rx_heads: Arc<Mutex<Vec<u16>>>, // Head descriptor of each rx buffer
+    tx_heads: Arc<Mutex<[Option<u16>; TX_BUFFERS_COUNT]>>, // Buffers in use
+    tx_id: Arc<AtomicUsize>,
+}
+
+impl Device {
+    pub fn new(io_base: u16) -> Option<Self> {
+        let transport = Transport::new(io_base);
+        let features = transport.init(F_MAC);
+        let queues = Virtqueue::new(&transport, RX_QUEUE).zip(
+            Virtqueue::new(&transport, TX_QUEUE)
+        );
+        let (rx_queue, tx_queue) = match queues {
+            Some(queues) => queues,
+            None => {
+                transport.add_status(STATUS_FAILED);
+                return None;
+            }
+        };
+        let n = RX_BUFFERS_COUNT.min(rx_queue.size());
+        let mut device = Self {
+            config: Arc::new(Config::new()),
+            stats: Arc::new(Stats::new()),
+            transport,
+            rx_queue,
+            tx_queue,
+            rx_buffers: (0..n).map(|_|
+                PhysBuf::new(HEADER_LEN + MTU)
+            ).collect(),
+            tx_buffers: [(); TX_BUFFERS_COUNT].map(|_|
+                PhysBuf::new(HEADER_LEN + MTU)
+            ),
+            rx_heads: Arc::new(Mutex::new(Vec::new())),
+            tx_heads: Arc::new(Mutex::new([None; TX_BUFFERS_COUNT])),
+            tx_id: Arc::new(AtomicUsize::new(0)),
+        };
+        device.init(features);
+        Some(device)
+    }
+
+    fn init(&mut self, features: u32) {
+        // Read MAC addr
+        if features & F_MAC > 0 {
+            let mac: Vec<u8> = (0..6).map(|i|
+                self.transport.read_config_u8(i)
+            ).collect();
+            self.config.update_mac(EthernetAddress::from_bytes(&mac));
+        }
+
+        // Give all the rx buffers to the device
+        let mut rx_heads = Vec::new();
+        for buffer in &self.rx_buffers {
+            let buf = [(buffer.addr(), buffer.len(), true)];
+            if let Some(head) = self.rx_queue.add(&buf) {
+                rx_heads.push(head);
+            }
+        }
+        *self.rx_heads.lock() = rx_heads;
+
+        self.transport.add_status(STATUS_DRIVER_OK);
+        self.rx_queue.notify();
+    }
+
+    // Free the descriptors and the buffers of the packets already sent by
+    // the device
+    fn reclaim_tx_buffers(&mut self) {
+        let mut tx_heads = self.tx_heads.lock();
+        while let Some((head, _)) = self.tx_queue.pop_used() {
+            if let Some(i) = tx_heads.iter().position(|&h| h == Some(head)) {
+                tx_heads[i] = None;
+            }
+        }
+    }
+
+    // Wait up to 100 ms for the device to be done with the current tx
+    // buffer, without using the uptime that is not updated while the stack
+    // is polled by the timer interrupt handler
+    fn wait_tx_buffer(&mut self) -> Result<(), ()> {
+        let tx_id = self.tx_id.load(Ordering::SeqCst);
+        for _ in 0..100_000 {
+            self.reclaim_tx_buffers();
+            if self.tx_heads.lock()[tx_id].is_none() {
+                return Ok(());
+            }
+            sys::time::nanowait(1000);
+        }
+        Err(())
+    }
+}
+
+impl EthernetDeviceIO for Device {
+    fn config(&self) -> Arc<Config> {
+        self.config.clone()
+    }
+
+    fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    fn ack_interrupt(&mut self) {
+        self.transport.isr_status();
+    }
+
+    fn receive_packet(&mut self) -> Option<Vec<u8>> {
+        let (head, len) = self.rx_queue.pop_used()?;
+        let mut rx_heads = self.rx_heads.lock();
+        let i = rx_heads.iter().position(|&id| id == head)?;
+        let buffer = &self.rx_buffers[i];
+        let n = len.clamp(HEADER_LEN, buffer.len());
+        let packet = buffer[HEADER_LEN..n].to_vec();
+
+        // Give back the buffer to the device
+        let buf = [(buffer.addr(), buffer.len(), true)];
+        if let Some(head) = self.rx_queue.add(&buf) {
+            rx_heads[i] = head;
+            self.rx_queue.notify();
+        }
+
+        if !packet.is_empty() {
+            Some(packet)
+        } else {
+            None
+        }
+    }
+
+    fn transmit_packet(&mut self, len: usize) {
+        // The packet is dropped if its buffer is still used by the device
+        if self.wait_tx_buffer().is_err() {
+            return;
+        }
+        let tx_id = self.tx_id.load(Ordering::SeqCst);
+        let buffer = &mut self.tx_buffers[tx_id];
+        buffer[0..HEADER_LEN].fill(0);
+
+        let buf = [(buffer.addr(), HEADER_LEN + len, false)];
+        if let Some(head) = self.tx_queue.add(&buf) {
+            self.tx_heads.lock()[tx_id] = Some(head);
+            self.tx_queue.notify();
+            let next = (tx_id + 1) % TX_BUFFERS_COUNT;
+            self.tx_id.store(next, Ordering::SeqCst);
+        }
+    }
+
+    fn next_tx_buffer(&mut self, len: usize) -> &mut [u8] {
+        // Don't let the packet be written into a buffer still being sent
+        let _ = self.wait_tx_buffer();
+        let tx_id = self.tx_id.load(Ordering::SeqCst);
+        &mut self.tx_buffers[tx_id][HEADER_LEN..(HEADER_LEN + len)]
+    }
+}
@@ -0,0 +1,168 @@
+use crate::sys;
+use crate::sys::net::{parse_frame, Protocol};
+
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+// Packet capture in the pcap format used by tcpdump and Wireshark
+//
+// The frames received and transmitted by the devices with the capture
+// enabled in their config are copied into a queue by the network stack,
+// and a command is draining this queue into a file.
+
+// Maximum number of frames waiting to be saved
+const QUEUE_LEN: usize = 1024;
+
+const MAGIC: u32 = 0xA1B2C3D4;
+const SNAPLEN: u32 = 65535;
+const LINKTYPE_ETHERNET: u32 = 1;
+
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Filter {
+    pub protocol: Option<Protocol>,
+    pub port: Option<u16>,
+}
+
+impl Filter {
+    pub fn is_match(&self, frame: &[u8]) -> bool {
+        let info = match parse_frame(frame) {
+            Some(info) => info,
+            None => return self.protocol.is_none() && self.port.is_none(),
+        };
+        if self.protocol.map_or(false, |p| p != info.protocol) {
+            return false;
+        }
+        if let Some(port) = self.port {
+            return info.ports.map_or(false, |(src, dst)|
+                port == src || port == dst
+            );
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub time: f64,
+    pub frame: Vec<u8>,
+}
+
+struct Capture {
+    filter: Filter,
+    queue: VecDeque<Record>,
+    dropped: usize,
+}
+
+pub fn start(filter: Filter) {
+    interrupts::without_interrupts(|| {
+        *CAPTURE.lock() = Some(Capture {
+            filter,
+            queue: VecDeque::new(),
+            dropped: 0,
+        });
+    });
+}
+
+// Stop the capture and return the number of frames dropped
+pub fn stop() -> usize {
+    interrupts::without_interrupts(|| {
+        CAPTURE.lock().take().map_or(0, |capture| capture.dropped)
+    })
+}
+
+// Take the frames captured since the last call
+pub fn records() -> Vec<Record> {
+    interrupts::without_interrupts(|| {
+        match *CAPTURE.lock() {
+            Some(ref mut capture) => capture.queue.drain(..).collect(),
+            None => Vec::new(),
+        }
+    })
+}
+
+// Called by the network stack for each frame received or transmitted
+pub fn capture(frame: &[u8]) {
+    interrupts::without_interrupts(|| {
+        if let Some(ref mut capture) = *CAPTURE.lock() {
+            if !capture.filter.is_match(frame) {
+                return;
+            }
+            if capture.queue.len() < QUEUE_LEN {
+                let time = sys::clock::realtime();
+                let frame = frame.to_vec();
+                capture.queue.push_back(Record { time, frame });
+            } else {
+                capture.dropped += 1;
+            }
+        }
+    });
+}
+
+pub fn header() -> Vec<u8> {
+    let mut buf = Vec::with_capacity(24);
+    buf.extend_from_slice(&MAGIC.to_le_bytes());
+    buf.extend_from_slice(&2u16.to_le_bytes()); // Major version
+    buf.extend_from_slice(&4u16.to_le_bytes()); // Minor version
+    buf.extend_from_slice(&0i32.to_le_bytes()); // Timezone offset
+    buf.extend_from_slice(&0u32.to_le_bytes()); // Timestamp accuracy
+    buf.extend_from_slice(&SNAPLEN.to_le_bytes());
+    buf.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    buf
+}
+
+impl Record {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = self.frame.len().min(SNAPLEN as usize);
+        let sec = self.time as u32;
+        let usec = ((self.time - sec as f64) * 1000000.0) as u32;
+        let mut buf = Vec::with_capacity(16 + len);
+        buf.extend_from_slice(&sec.to_le_bytes());
+        buf.extend_from_slice(&usec.to_le_bytes());
+        buf.extend_from_slice(&(len as u32).to_le_bytes());
+        buf.extend_from_slice(&(self.frame.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.frame[0..len]);
+        buf
+    }
+}
+
+#[test_case]
+fn test_filter() {
+    let mut frame = [0; 54];
+    frame[12..14].copy_from_slice(&[0x08, 0x00]); // IPv4
+    frame[14] = 0x45; // Header length of 20 bytes
+    frame[23] = 6; // TCP
+    frame[34..36].copy_from_slice(&49152u16.to_be_bytes());
+    frame[36..38].copy_from_slice(&80u16.to_be_bytes());
+
+    let mut filter = Filter::default();
+    assert!(filter.is_match(&frame));
+    filter.protocol = Some(Protocol::Tcp);
+    assert!(filter.is_match(&frame));
+    filter.port = Some(80);
+    assert!(filter.is_match(&frame));
+    filter.port = Some(443);
+    assert!(!filter.is_match(&frame));
+    filter.port = None;
+    filter.protocol = Some(Protocol::Udp);
+    assert!(!filter.is_match(&frame));
+
+    frame[12..14].copy_from_slice(&[0x08, 0x06]); // ARP
+    filter.protocol = Some(Protocol::Arp);
+    assert!(filter.is_match(&frame));
+}
+
+#[test_case]
+fn test_record() {
+    let frame = [0xFF; 60].to_vec();
+    let record = Record { time: 1.5, frame };
+    let buf = record.to_bytes();
+    assert_eq!(buf.len(), 16 + 60);
+    assert_eq!(buf[0..4], 1u32.to_le_bytes());
+    assert_eq!(buf[4..8], 500000u32.to_le_bytes());
+    assert_eq!(buf[8..12], 60u32.to_le_bytes());
+    assert_eq!(header().len(), 24);
+}
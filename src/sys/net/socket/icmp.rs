@@ -0,0 +1,189 @@
+use crate::sys;
+
+use crate::api::fs::{FileIO, IO};
+use crate::sys::net::{wait, SocketStatus};
+
+use super::reroute;
+
+use alloc::vec;
+use bit_field::BitField;
+use smoltcp::iface::SocketHandle;
+use smoltcp::phy::Device;
+use smoltcp::socket::icmp;
+use smoltcp::wire::{IpAddress, Icmpv4Packet, Icmpv6Packet};
+
+fn icmp_socket_status(socket: &icmp::Socket) -> u8 {
+    let mut status = 0;
+    status.set_bit(SocketStatus::IsOpen as usize, socket.is_open());
+    status.set_bit(SocketStatus::CanSend as usize, socket.can_send());
+    status.set_bit(SocketStatus::CanRecv as usize, socket.can_recv());
+    status
+}
+
+// The socket is used to exchange raw ICMP messages with a remote host. The
+// identifier and the checksum of outgoing messages are filled by the kernel,
+// and only the replies to our identifier are received.
+#[derive(Debug, Clone)]
+pub struct IcmpSocket {
+    pub iface: usize,
+    pub handle: SocketHandle,
+    pub remote_addr: Option<IpAddress>,
+    pub ident: u16,
+}
+
+impl IcmpSocket {
+    pub fn size() -> usize {
+        if let Some(ni) = sys::net::NET.lock().first() {
+            let mtu = ni.device.capabilities().max_transmission_unit;
+            let eth_header = 14;
+            let ip_header = 20;
+            mtu - eth_header - ip_header
+        } else {
+            1
+        }
+    }
+
+    pub fn new() -> Self {
+        let icmp_rx_buffer = icmp::PacketBuffer::new(
+            vec![icmp::PacketMetadata::EMPTY; 4], vec![0; 2048]
+        );
+        let icmp_tx_buffer = icmp::PacketBuffer::new(
+            vec![icmp::PacketMetadata::EMPTY; 4], vec![0; 2048]
+        );
+        let icmp_socket = icmp::Socket::new(icmp_rx_buffer, icmp_tx_buffer);
+        let iface = 0;
+        let handle = sys::net::NET.lock()[iface].sockets.add(icmp_socket);
+        let remote_addr = None;
+        let ident = sys::rng::get_u16();
+
+        Self {
+            iface,
+            handle,
+            remote_addr,
+            ident,
+        }
+    }
+
+    pub fn connect(&mut self, addr: IpAddress, _port: u16) -> Result<(), ()> {
+        let mut net = sys::net::NET.lock();
+        let route = reroute(&mut net, self.iface, self.handle, &addr);
+        (self.iface, self.handle) = route.ok_or(())?;
+        let ni = &mut net[self.iface];
+        ni.poll();
+        let socket = ni.sockets.get_mut::<icmp::Socket>(self.handle);
+
+        if !socket.is_open() {
+            let endpoint = icmp::Endpoint::Ident(self.ident);
+            socket.bind(endpoint).map_err(|_| ())?;
+        }
+        self.remote_addr = Some(addr);
+        Ok(())
+    }
+}
+
+impl FileIO for IcmpSocket {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        let timeout = 5.0;
+        let started = sys::clock::realtime();
+        if let Some(ni) = sys::net::NET.lock().get_mut(self.iface) {
+            let bytes;
+            loop {
+                if sys::clock::realtime() - started > timeout {
+                    return Err(());
+                }
+                ni.poll();
+                let socket = ni.sockets.get_mut::<icmp::Socket>(self.handle);
+
+                if buf.len() == 1 {
+                    // 1 byte status read
+                    buf[0] = icmp_socket_status(socket);
+                    return Ok(1);
+                }
+
+                if socket.can_recv() {
+                    (bytes, _) = socket.recv_slice(buf).map_err(|_| ())?;
+                    break;
+                }
+                if let Some(d) = ni.poll_delay() {
+                    wait(d);
+                }
+                sys::time::halt();
+            }
+            Ok(bytes)
+        } else {
+            Err(())
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        // Type, code, checksum, identifier, and sequence number
+        if buf.len() < 8 {
+            return Err(());
+        }
+        let addr = self.remote_addr.ok_or(())?;
+        let mut message = buf.to_vec();
+        message[4..6].copy_from_slice(&self.ident.to_be_bytes());
+
+        let timeout = 5.0;
+        let started = sys::clock::realtime();
+        let mut sent = false;
+        if let Some(ni) = sys::net::NET.lock().get_mut(self.iface) {
+            match addr {
+                IpAddress::Ipv4(_) => {
+                    Icmpv4Packet::new_unchecked(&mut message).fill_checksum();
+                }
+                IpAddress::Ipv6(_) => {
+                    // The checksum covers a pseudo header with the addresses
+                    let src = ni.iface.get_source_address(&addr).ok_or(())?;
+                    let mut packet = Icmpv6Packet::new_unchecked(&mut message);
+                    packet.fill_checksum(&src, &addr);
+                }
+            }
+            loop {
+                if sys::clock::realtime() - started > timeout {
+                    return Err(());
+                }
+                ni.poll();
+                let socket = ni.sockets.get_mut::<icmp::Socket>(self.handle);
+
+                if sent {
+                    break;
+                }
+                if socket.can_send() {
+                    if socket.send_slice(&message, addr).is_err() {
+                        return Err(());
+                    }
+                    sent = true; // Break after next poll
+                }
+
+                if let Some(d) = ni.poll_delay() {
+                    wait(d);
+                }
+                sys::time::halt();
+            }
+            Ok(buf.len())
+        } else {
+            Err(())
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(ni) = sys::net::NET.lock().get_mut(self.iface) {
+            ni.sockets.remove(self.handle);
+        }
+    }
+
+    fn poll(&mut self, event: IO) -> bool {
+        if let Some(ni) = sys::net::NET.lock().get_mut(self.iface) {
+            ni.poll();
+            let socket = ni.sockets.get_mut::<icmp::Socket>(self.handle);
+
+            match event {
+                IO::Read => socket.can_recv(),
+                IO::Write => socket.can_send(),
+            }
+        } else {
+            false
+        }
+    }
+}
@@ -1,24 +1,160 @@
 use crate::sys;
+use crate::sys::net::NetworkInterface;
 
+pub mod icmp;
 pub mod tcp;
 pub mod udp;
 
-use alloc::vec;
-use lazy_static::lazy_static;
-use smoltcp::iface::SocketSet;
-use smoltcp::time::Duration;
-use spin::Mutex;
+use alloc::vec::Vec;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::Socket;
+use smoltcp::wire::IpAddress;
 
-lazy_static! {
-    pub static ref SOCKETS: Mutex<SocketSet<'static>> = {
-        Mutex::new(SocketSet::new(vec![]))
-    };
+// Timeouts in seconds used by new sockets
+const DEFAULT_TIMEOUT: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum SocketOption {
+    ConnectTimeout = 0,
+    ReadTimeout = 1,
+    WriteTimeout = 2,
+    NonBlocking = 3,
+    Terminal = 4,
+}
+
+impl SocketOption {
+    pub fn from(n: usize) -> Option<Self> {
+        match n {
+            0 => Some(SocketOption::ConnectTimeout),
+            1 => Some(SocketOption::ReadTimeout),
+            2 => Some(SocketOption::WriteTimeout),
+            3 => Some(SocketOption::NonBlocking),
+            4 => Some(SocketOption::Terminal),
+            _ => None,
+        }
+    }
+}
+
+// The errors are returned by the syscalls as negative values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(isize)]
+pub enum SocketError {
+    Failure = -1,
+    Timeout = -2,
+    WouldBlock = -3,
+}
+
+impl SocketError {
+    pub fn from(code: isize) -> Self {
+        match code {
+            -2 => SocketError::Timeout,
+            -3 => SocketError::WouldBlock,
+            _ => SocketError::Failure,
+        }
+    }
+}
+
+// A timeout of 0 second will never expire. The timeout of `accept` is the
+// read timeout. A TCP socket in terminal mode translates the data exchanged
+// with a remote VT100 terminal to behave like the console.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SocketOptions {
+    pub connect_timeout: f64,
+    pub read_timeout: f64,
+    pub write_timeout: f64,
+    pub nonblocking: bool,
+    pub terminal: bool,
+}
+
+impl SocketOptions {
+    pub fn new() -> Self {
+        Self {
+            connect_timeout: DEFAULT_TIMEOUT,
+            read_timeout: DEFAULT_TIMEOUT,
+            write_timeout: DEFAULT_TIMEOUT,
+            nonblocking: false,
+            terminal: false,
+        }
+    }
+
+    pub fn set(&mut self, option: SocketOption, value: f64) -> Result<(), ()> {
+        if value.is_nan() || value < 0.0 {
+            return Err(());
+        }
+        match option {
+            SocketOption::ConnectTimeout => self.connect_timeout = value,
+            SocketOption::ReadTimeout => self.read_timeout = value,
+            SocketOption::WriteTimeout => self.write_timeout = value,
+            SocketOption::NonBlocking => self.nonblocking = value != 0.0,
+            SocketOption::Terminal => self.terminal = value != 0.0,
+        }
+        Ok(())
+    }
+}
+
+fn is_expired(started: f64, timeout: f64) -> bool {
+    timeout > 0.0 && sys::clock::realtime() - started > timeout
 }
 
 fn random_port() -> u16 {
     49152 + sys::rng::get_u16() % 16384
 }
 
-fn wait(duration: Duration) {
-    sys::time::sleep((duration.total_micros() as f64) / 1000000.0);
+// A listening socket has copies of itself listening on the other interfaces
+// to be reachable from each of them, they are removed when it is closed.
+fn remove_listeners(
+    net: &mut [NetworkInterface],
+    listeners: &mut Vec<(usize, SocketHandle)>
+) {
+    for (i, handle) in listeners.drain(..) {
+        if let Some(ni) = net.get_mut(i) {
+            ni.sockets.remove(handle);
+        }
+    }
+}
+
+// A socket is created on the first interface and then moved to the one
+// selected by the routing table when it is connected to a remote address.
+// Return the index of the interface and the new handle of the socket.
+fn reroute(
+    net: &mut [NetworkInterface],
+    i: usize,
+    handle: SocketHandle,
+    addr: &IpAddress
+) -> Option<(usize, SocketHandle)> {
+    let j = sys::net::route(net, addr)?;
+    if i == j {
+        return Some((i, handle));
+    }
+    let socket = net[i].sockets.remove(handle);
+    let sockets = &mut net[j].sockets;
+    let handle = match socket {
+        Socket::Raw(socket) => sockets.add(socket),
+        Socket::Icmp(socket) => sockets.add(socket),
+        Socket::Udp(socket) => sockets.add(socket),
+        Socket::Tcp(socket) => sockets.add(socket),
+        Socket::Dhcpv4(socket) => sockets.add(socket),
+    };
+    Some((j, handle))
+}
+
+#[test_case]
+fn test_socket_options() {
+    let mut options = SocketOptions::new();
+    assert_eq!(options.read_timeout, DEFAULT_TIMEOUT);
+    assert!(!options.nonblocking);
+
+    assert!(options.set(SocketOption::ReadTimeout, 0.5).is_ok());
+    assert!(options.set(SocketOption::NonBlocking, 1.0).is_ok());
+    assert!(options.set(SocketOption::WriteTimeout, -1.0).is_err());
+    assert_eq!(options.read_timeout, 0.5);
+    assert_eq!(options.write_timeout, DEFAULT_TIMEOUT);
+    assert!(options.nonblocking);
+
+    assert_eq!(SocketOption::from(3), Some(SocketOption::NonBlocking));
+    assert_eq!(SocketOption::from(4), Some(SocketOption::Terminal));
+    assert_eq!(SocketOption::from(5), None);
+    assert_eq!(SocketError::from(-2), SocketError::Timeout);
+    assert_eq!(SocketError::from(-42), SocketError::Failure);
 }
@@ -1,17 +1,20 @@
 use crate::sys;
 
 use crate::api::fs::{FileIO, IO};
-use crate::sys::net::SocketStatus;
+use crate::sys::net::{wait, SocketStatus};
 
-use super::SOCKETS;
-use super::{random_port, wait};
+use super::{is_expired, random_port, remove_listeners, reroute};
+use super::{SocketError, SocketOptions};
 
+use alloc::sync::Arc;
 use alloc::vec;
+use alloc::vec::Vec;
 use bit_field::BitField;
+use core::sync::atomic::{AtomicBool, Ordering};
 use smoltcp::iface::SocketHandle;
 use smoltcp::phy::Device;
 use smoltcp::socket::tcp;
-use smoltcp::wire::IpAddress;
+use smoltcp::wire::{IpAddress, IpEndpoint};
 
 fn tcp_socket_status(socket: &tcp::Socket) -> u8 {
     let mut status = 0;
@@ -25,15 +28,29 @@ fn tcp_socket_status(socket: &tcp::Socket) -> u8 {
     status
 }
 
+fn tcp_socket() -> tcp::Socket<'static> {
+    let tcp_rx_buffer = tcp::SocketBuffer::new(vec![0; 1024]);
+    let tcp_tx_buffer = tcp::SocketBuffer::new(vec![0; 1024]);
+    tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer)
+}
+
+// The echo of a socket in terminal mode is shared with its duplicates to
+// let the escape sequences written to one of them disable the echo of the
+// lines read from another.
 #[derive(Debug, Clone)]
 pub struct TcpSocket {
+    pub iface: usize,
     pub handle: SocketHandle,
+    pub options: SocketOptions,
+    listeners: Vec<(usize, SocketHandle)>,
+    echo: Arc<AtomicBool>,
+    is_cr: bool,
 }
 
 impl TcpSocket {
     pub fn size() -> usize {
-        if let Some((_, ref mut device)) = *sys::net::NET.lock() {
-            let mtu = device.capabilities().max_transmission_unit;
+        if let Some(ni) = sys::net::NET.lock().first() {
+            let mtu = ni.device.capabilities().max_transmission_unit;
             let eth_header = 14;
             let ip_header = 20;
             let tcp_header = 20;
@@ -44,70 +61,97 @@ impl TcpSocket {
     }
 
     pub fn new() -> Self {
-        let mut sockets = SOCKETS.lock();
-        let tcp_rx_buffer = tcp::SocketBuffer::new(vec![0; 1024]);
-        let tcp_tx_buffer = tcp::SocketBuffer::new(vec![0; 1024]);
-        let tcp_socket = tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer);
-        let handle = sockets.add(tcp_socket);
+        let iface = 0;
+        let handle = sys::net::NET.lock()[iface].sockets.add(tcp_socket());
+        let options = SocketOptions::new();
+        let listeners = Vec::new();
+        let echo = Arc::new(AtomicBool::new(true));
+        let is_cr = false;
 
-        Self { handle }
+        Self { iface, handle, options, listeners, echo, is_cr }
     }
 
-    pub fn connect(&mut self, addr: IpAddress, port: u16) -> Result<(), ()> {
+    // A non-blocking socket will return `WouldBlock` until the connection
+    // is established.
+    pub fn connect(
+        &mut self,
+        addr: IpAddress,
+        port: u16
+    ) -> Result<(), SocketError> {
         let mut connecting = false;
-        let timeout = 5.0;
+        let timeout = self.options.connect_timeout;
         let started = sys::clock::realtime();
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            loop {
-                if sys::clock::realtime() - started > timeout {
-                    return Err(());
-                }
-                let mut sockets = SOCKETS.lock();
-                iface.poll(sys::net::time(), device, &mut sockets);
-                let socket = sockets.get_mut::<tcp::Socket>(self.handle);
-
-                match socket.state() {
-                    tcp::State::Closed => {
-                        if connecting {
-                            return Err(());
-                        }
-                        let cx = iface.context();
-                        let dest = (addr, port);
-                        if socket.connect(cx, dest, random_port()).is_err() {
-                            return Err(());
-                        }
-                        connecting = true;
-                    }
-                    tcp::State::SynSent => {}
-                    tcp::State::Established => {
-                        break;
+        let mut net = sys::net::NET.lock();
+        let route = reroute(&mut net, self.iface, self.handle, &addr);
+        (self.iface, self.handle) = route.ok_or(SocketError::Failure)?;
+        let ni = &mut net[self.iface];
+        loop {
+            if is_expired(started, timeout) {
+                return Err(SocketError::Timeout);
+            }
+            ni.poll();
+            let socket = ni.sockets.get_mut::<tcp::Socket>(self.handle);
+
+            match socket.state() {
+                tcp::State::Closed => {
+                    if connecting {
+                        return Err(SocketError::Failure);
                     }
-                    _ => {
-                        // Did something get sent before the connection closed?
-                        return if socket.can_recv() { Ok(()) } else { Err(()) };
+                    let cx = ni.iface.context();
+                    let dest = (addr, port);
+                    if socket.connect(cx, dest, random_port()).is_err() {
+                        return Err(SocketError::Failure);
                     }
+                    connecting = true;
                 }
-
-                if let Some(d) = iface.poll_delay(sys::net::time(), &sockets) {
-                    wait(d);
+                tcp::State::SynSent if self.options.nonblocking => {
+                    return Err(SocketError::WouldBlock);
                 }
-                sys::time::halt();
+                tcp::State::SynSent => {}
+                tcp::State::Established => {
+                    break;
+                }
+                _ => {
+                    // Did something get sent before the connection closed?
+                    return if socket.can_recv() {
+                        Ok(())
+                    } else {
+                        Err(SocketError::Failure)
+                    };
+                }
+            }
+
+            if let Some(d) = ni.poll_delay() {
+                wait(d);
             }
+            sys::time::halt();
         }
         Ok(())
     }
 
+    // The socket is also listening on the other interfaces, and the first
+    // one receiving a connection is kept by `accept`.
     pub fn listen(&mut self, port: u16) -> Result<(), ()> {
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            let mut sockets = SOCKETS.lock();
-            iface.poll(sys::net::time(), device, &mut sockets);
-            let socket = sockets.get_mut::<tcp::Socket>(self.handle);
+        let mut net = sys::net::NET.lock();
+        remove_listeners(&mut net, &mut self.listeners);
+        for (i, ni) in net.iter_mut().enumerate() {
+            if i != self.iface {
+                let mut socket = tcp_socket();
+                if socket.listen(port).is_err() {
+                    return Err(());
+                }
+                self.listeners.push((i, ni.sockets.add(socket)));
+            }
+        }
+        if let Some(ni) = net.get_mut(self.iface) {
+            ni.poll();
+            let socket = ni.sockets.get_mut::<tcp::Socket>(self.handle);
 
             if socket.listen(port).is_err() {
                 return Err(());
             }
 
-            if let Some(d) = iface.poll_delay(sys::net::time(), &sockets) {
+            if let Some(d) = ni.poll_delay() {
                 wait(d);
             }
             sys::time::halt();
@@ -117,112 +161,249 @@ impl TcpSocket {
         }
     }
 
-    pub fn accept(&mut self) -> Result<IpAddress, ()> {
-        let timeout = 5.0;
+    pub fn accept(&mut self) -> Result<IpEndpoint, SocketError> {
+        let timeout = self.options.read_timeout;
         let started = sys::clock::realtime();
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            loop {
-                if sys::clock::realtime() - started > timeout {
-                    return Err(());
-                }
-                let mut sockets = SOCKETS.lock();
-                iface.poll(sys::net::time(), device, &mut sockets);
-                let socket = sockets.get_mut::<tcp::Socket>(self.handle);
+        let mut net = sys::net::NET.lock();
+        let mut sockets = vec![(self.iface, self.handle)];
+        sockets.extend_from_slice(&self.listeners);
+        loop {
+            if is_expired(started, timeout) {
+                return Err(SocketError::Timeout);
+            }
+            let mut delay = None;
+            for &(i, handle) in &sockets {
+                let ni = net.get_mut(i).ok_or(SocketError::Failure)?;
+                ni.poll();
+                let socket = ni.sockets.get_mut::<tcp::Socket>(handle);
 
                 if let Some(endpoint) = socket.remote_endpoint() {
-                    return Ok(endpoint.addr);
+                    if (i, handle) != (self.iface, self.handle) {
+                        net[self.iface].sockets.remove(self.handle);
+                        self.listeners.retain(|&l| l != (i, handle));
+                        (self.iface, self.handle) = (i, handle);
+                    }
+                    remove_listeners(&mut net, &mut self.listeners);
+                    return Ok(endpoint);
                 }
+                delay = match (delay, ni.poll_delay()) {
+                    (Some(a), Some(b)) => Some(b.min(a)),
+                    (a, b) => a.or(b),
+                };
+            }
+            if self.options.nonblocking {
+                return Err(SocketError::WouldBlock);
+            }
 
-                if let Some(d) = iface.poll_delay(sys::net::time(), &sockets) {
-                    wait(d);
-                }
-                sys::time::halt();
+            if let Some(d) = delay {
+                wait(d);
             }
+            sys::time::halt();
+        }
+    }
+
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize, SocketError> {
+        if buf.len() == 1 {
+            // 1 byte status read
+            buf[0] = self.status().ok_or(SocketError::Failure)?;
+            return Ok(1);
+        }
+        if self.options.terminal {
+            self.recv_terminal(buf)
         } else {
-            Err(())
+            self.recv_bytes(buf)
         }
     }
-}
 
-impl FileIO for TcpSocket {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
-        let timeout = 5.0;
+    fn status(&mut self) -> Option<u8> {
+        let mut net = sys::net::NET.lock();
+        let ni = net.get_mut(self.iface)?;
+        ni.poll();
+        let socket = ni.sockets.get_mut::<tcp::Socket>(self.handle);
+        Some(tcp_socket_status(socket))
+    }
+
+    fn recv_bytes(&mut self, buf: &mut [u8]) -> Result<usize, SocketError> {
+        let timeout = self.options.read_timeout;
         let started = sys::clock::realtime();
         let mut bytes = 0;
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            let mut sockets = SOCKETS.lock();
+        if let Some(ni) = sys::net::NET.lock().get_mut(self.iface) {
             loop {
-                if sys::clock::realtime() - started > timeout {
-                    return Err(());
-                }
-                iface.poll(sys::net::time(), device, &mut sockets);
-                let socket = sockets.get_mut::<tcp::Socket>(self.handle);
-
-                if buf.len() == 1 {
-                    // 1 byte status read
-                    buf[0] = tcp_socket_status(socket);
-                    return Ok(1);
+                if is_expired(started, timeout) {
+                    return Err(SocketError::Timeout);
                 }
+                ni.poll();
+                let socket = ni.sockets.get_mut::<tcp::Socket>(self.handle);
 
                 if socket.can_recv() {
-                    bytes = socket.recv_slice(buf).map_err(|_| ())?;
+                    let res = socket.recv_slice(buf);
+                    bytes = res.map_err(|_| SocketError::Failure)?;
                     break;
                 }
                 if !socket.may_recv() {
                     break;
                 }
-                if let Some(d) = iface.poll_delay(sys::net::time(), &sockets) {
+                if self.options.nonblocking {
+                    return Err(SocketError::WouldBlock);
+                }
+                if let Some(d) = ni.poll_delay() {
                     wait(d);
                 }
                 sys::time::halt();
             }
             Ok(bytes)
         } else {
-            Err(())
+            Err(SocketError::Failure)
         }
     }
 
-    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
-        let timeout = 5.0;
+    // Read one character like the console when the buffer has room for 4
+    // bytes, or a line echoed back to the remote terminal otherwise.
+    fn recv_terminal(&mut self, buf: &mut [u8]) -> Result<usize, SocketError> {
+        let is_char = buf.len() == 4;
+        let mut res = Vec::new();
+        let mut byte = [0; 1];
+        while self.recv_bytes(&mut byte)? > 0 {
+            let was_cr = self.is_cr;
+            self.is_cr = byte[0] == b'\r';
+            let b = match byte[0] {
+                b'\n' if was_cr => continue,
+                b'\0' => continue,
+                b'\r' => b'\n',
+                0x7F => 0x08, // Delete => Backspace
+                b => b,
+            };
+            if is_char {
+                res.push(b);
+                if core::str::from_utf8(&res).is_ok() || res.len() == 4 {
+                    break;
+                }
+                continue;
+            }
+            let echo = self.echo.load(Ordering::SeqCst);
+            if b == 0x08 {
+                if res.pop().is_some() && echo {
+                    self.send_all(b"\x08 \x08")?;
+                }
+                continue;
+            }
+            res.push(b);
+            if echo && (b == b'\n' || b >= 0x20) {
+                self.send_all(&translate_output(&[b], &self.echo))?;
+            }
+            if b == b'\n' || b == 0x03 || b == 0x04 || res.len() == buf.len() {
+                break;
+            }
+        }
+        let n = res.len().min(buf.len());
+        buf[0..n].copy_from_slice(&res[0..n]);
+        Ok(n)
+    }
+
+    pub fn send(&mut self, buf: &[u8]) -> Result<usize, SocketError> {
+        if self.options.terminal {
+            // The translated output may be longer than the given buffer so it
+            // has to be fully sent to report the length of the buffer
+            self.send_all(&translate_output(buf, &self.echo))?;
+            Ok(buf.len())
+        } else {
+            self.send_bytes(buf)
+        }
+    }
+
+    fn send_all(&mut self, buf: &[u8]) -> Result<(), SocketError> {
+        let mut sent = 0;
+        while sent < buf.len() {
+            sent += self.send_bytes(&buf[sent..])?;
+        }
+        Ok(())
+    }
+
+    // Send the whole buffer unless the connection is closed, the write
+    // timeout expires, or the socket is non-blocking and its send buffer is
+    // full, in which case the number of bytes sent so far is returned.
+    fn send_bytes(&mut self, buf: &[u8]) -> Result<usize, SocketError> {
+        let timeout = self.options.write_timeout;
         let started = sys::clock::realtime();
-        let mut sent = false;
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            let mut sockets = SOCKETS.lock();
+        let mut sent = 0;
+        if let Some(ni) = sys::net::NET.lock().get_mut(self.iface) {
             loop {
-                if sys::clock::realtime() - started > timeout {
-                    return Err(());
-                }
-                iface.poll(sys::net::time(), device, &mut sockets);
-                let socket = sockets.get_mut::<tcp::Socket>(self.handle);
+                ni.poll();
+                let socket = ni.sockets.get_mut::<tcp::Socket>(self.handle);
 
-                if sent {
-                    break;
+                if sent == buf.len() {
+                    return Ok(sent);
                 }
                 if socket.can_send() {
-                    if socket.send_slice(buf.as_ref()).is_err() {
-                        return Err(());
-                    }
-                    sent = true; // Break after next poll
-                }
-
-                if let Some(d) = iface.poll_delay(sys::net::time(), &sockets) {
-                    wait(d);
+                    let res = socket.send_slice(&buf[sent..]);
+                    sent += res.map_err(|_| SocketError::Failure)?;
+                    continue; // Poll to transmit what has been queued
                 }
-                sys::time::halt();
+                let err = if !socket.may_send() {
+                    SocketError::Failure
+                } else if self.options.nonblocking {
+                    SocketError::WouldBlock
+                } else if is_expired(started, timeout) {
+                    SocketError::Timeout
+                } else {
+                    if let Some(d) = ni.poll_delay() {
+                        wait(d);
+                    }
+                    sys::time::halt();
+                    continue;
+                };
+                return if sent > 0 { Ok(sent) } else { Err(err) };
             }
-            Ok(buf.len())
         } else {
-            Err(())
+            Err(SocketError::Failure)
+        }
+    }
+}
+
+// Write newlines as CRLF and remove the escape sequences only understood by
+// the console: the page keys moving its scrollback and the send/receive mode
+// disabling its echo, which is applied to the socket instead.
+fn translate_output(buf: &[u8], echo: &AtomicBool) -> Vec<u8> {
+    let mut res = Vec::with_capacity(buf.len());
+    let mut i = 0;
+    while i < buf.len() {
+        let rest = &buf[i..];
+        if rest.starts_with(b"\x1b[12l") {
+            echo.store(false, Ordering::SeqCst);
+            i += 5;
+        } else if rest.starts_with(b"\x1b[12h") {
+            echo.store(true, Ordering::SeqCst);
+            i += 5;
+        } else if rest.starts_with(b"\x1b[5~") || rest.starts_with(b"\x1b[6~") {
+            i += 4;
+        } else {
+            if buf[i] == b'\n' {
+                res.push(b'\r');
+            }
+            res.push(buf[i]);
+            i += 1;
         }
     }
+    res
+}
+
+impl FileIO for TcpSocket {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        self.recv(buf).map_err(|_| ())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        self.send(buf).map_err(|_| ())
+    }
 
     fn close(&mut self) {
         let mut closed = false;
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            let mut sockets = SOCKETS.lock();
+        let mut net = sys::net::NET.lock();
+        remove_listeners(&mut net, &mut self.listeners);
+        if let Some(ni) = net.get_mut(self.iface) {
             loop {
-                iface.poll(sys::net::time(), device, &mut sockets);
-                let socket = sockets.get_mut::<tcp::Socket>(self.handle);
+                ni.poll();
+                let socket = ni.sockets.get_mut::<tcp::Socket>(self.handle);
 
                 if closed {
                     break;
@@ -230,7 +411,7 @@ impl FileIO for TcpSocket {
                 socket.close();
                 closed = true;
 
-                if let Some(d) = iface.poll_delay(sys::net::time(), &sockets) {
+                if let Some(d) = ni.poll_delay() {
                     wait(d);
                 }
                 sys::time::halt();
@@ -239,10 +420,9 @@ impl FileIO for TcpSocket {
     }
 
     fn poll(&mut self, event: IO) -> bool {
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            let mut sockets = SOCKETS.lock();
-            iface.poll(sys::net::time(), device, &mut sockets);
-            let socket = sockets.get_mut::<tcp::Socket>(self.handle);
+        if let Some(ni) = sys::net::NET.lock().get_mut(self.iface) {
+            ni.poll();
+            let socket = ni.sockets.get_mut::<tcp::Socket>(self.handle);
 
             match event {
                 IO::Read => socket.can_recv(),
@@ -253,3 +433,13 @@ impl FileIO for TcpSocket {
         }
     }
 }
+
+#[test_case]
+fn test_translate_output() {
+    let echo = AtomicBool::new(true);
+    let buf = b"\x1b[12lPassword: \x1b[12h\n\x1b[5~> ";
+    assert_eq!(translate_output(buf, &echo), b"Password: \r\n> ".to_vec());
+    assert!(echo.load(Ordering::SeqCst));
+    translate_output(b"\x1b[12l", &echo);
+    assert!(!echo.load(Ordering::SeqCst));
+}
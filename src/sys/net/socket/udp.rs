@@ -1,12 +1,13 @@
 use crate::sys;
 
 use crate::api::fs::{FileIO, IO};
-use crate::sys::net::SocketStatus;
+use crate::sys::net::{wait, SocketStatus};
 
-use super::SOCKETS;
-use super::{random_port, wait};
+use super::{is_expired, random_port, remove_listeners, reroute};
+use super::{SocketError, SocketOptions};
 
 use alloc::vec;
+use alloc::vec::Vec;
 use bit_field::BitField;
 use smoltcp::iface::SocketHandle;
 use smoltcp::phy::Device;
@@ -21,16 +22,29 @@ fn udp_socket_status(socket: &udp::Socket) -> u8 {
     status
 }
 
+fn udp_socket() -> udp::Socket<'static> {
+    let udp_rx_buffer = udp::PacketBuffer::new(
+        vec![udp::PacketMetadata::EMPTY], vec![0; 2048]
+    );
+    let udp_tx_buffer = udp::PacketBuffer::new(
+        vec![udp::PacketMetadata::EMPTY], vec![0; 2048]
+    );
+    udp::Socket::new(udp_rx_buffer, udp_tx_buffer)
+}
+
 #[derive(Debug, Clone)]
 pub struct UdpSocket {
+    pub iface: usize,
     pub handle: SocketHandle,
     pub remote_endpoint: Option<IpEndpoint>,
+    pub options: SocketOptions,
+    listeners: Vec<(usize, SocketHandle)>,
 }
 
 impl UdpSocket {
     pub fn size() -> usize {
-        if let Some((_, ref mut device)) = *sys::net::NET.lock() {
-            let mtu = device.capabilities().max_transmission_unit;
+        if let Some(ni) = sys::net::NET.lock().first() {
+            let mtu = ni.device.capabilities().max_transmission_unit;
             let eth_header = 14;
             let ip_header = 20;
             let udp_header = 8;
@@ -41,73 +55,121 @@ impl UdpSocket {
     }
 
     pub fn new() -> Self {
-        let mut sockets = SOCKETS.lock();
-        let udp_rx_buffer = udp::PacketBuffer::new(
-            vec![udp::PacketMetadata::EMPTY], vec![0; 1024]
-        );
-        let udp_tx_buffer = udp::PacketBuffer::new(
-            vec![udp::PacketMetadata::EMPTY], vec![0; 1024]
-        );
-        let udp_socket = udp::Socket::new(udp_rx_buffer, udp_tx_buffer);
-        let handle = sockets.add(udp_socket);
+        let iface = 0;
+        let handle = sys::net::NET.lock()[iface].sockets.add(udp_socket());
         let remote_endpoint = None;
+        let options = SocketOptions::new();
+        let listeners = Vec::new();
 
         Self {
+            iface,
             handle,
             remote_endpoint,
+            options,
+            listeners,
         }
     }
 
-    pub fn connect(&mut self, addr: IpAddress, port: u16) -> Result<(), ()> {
-        let timeout = 5.0;
-        let started = sys::clock::realtime();
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            loop {
-                if sys::clock::realtime() - started > timeout {
-                    return Err(());
-                }
-                let mut sockets = SOCKETS.lock();
-                iface.poll(sys::net::time(), device, &mut sockets);
-                let socket = sockets.get_mut::<udp::Socket>(self.handle);
+    // A socket already bound keeps its port when it is connected to another
+    // remote endpoint.
+    pub fn connect(
+        &mut self,
+        addr: IpAddress,
+        port: u16
+    ) -> Result<(), SocketError> {
+        let mut net = sys::net::NET.lock();
+        let route = reroute(&mut net, self.iface, self.handle, &addr);
+        (self.iface, self.handle) = route.ok_or(SocketError::Failure)?;
+        let ni = &mut net[self.iface];
+        ni.poll();
+        let socket = ni.sockets.get_mut::<udp::Socket>(self.handle);
 
-                if !socket.is_open() {
-                    let local_endpoint = IpListenEndpoint::from(random_port());
-                    socket.bind(local_endpoint).unwrap();
-                    break;
-                }
-
-                if let Some(d) = iface.poll_delay(sys::net::time(), &sockets) {
-                    wait(d);
-                }
-                sys::time::halt();
-            }
+        if !socket.is_open() {
+            let local_endpoint = IpListenEndpoint::from(random_port());
+            socket.bind(local_endpoint).map_err(|_| SocketError::Failure)?;
         }
         self.remote_endpoint = Some(IpEndpoint::new(addr, port));
         Ok(())
     }
 
-    pub fn listen(&mut self, _port: u16) -> Result<(), ()> {
-        todo!()
+    // The socket is also bound to the port on the other interfaces, and
+    // `accept` switches to the one receiving the next datagram.
+    pub fn listen(&mut self, port: u16) -> Result<(), ()> {
+        let mut net = sys::net::NET.lock();
+        if let Some(ni) = net.get_mut(self.iface) {
+            let socket = ni.sockets.get_mut::<udp::Socket>(self.handle);
+            if socket.is_open() {
+                return Err(());
+            }
+            socket.bind(port).map_err(|_| ())?;
+        } else {
+            return Err(());
+        }
+        remove_listeners(&mut net, &mut self.listeners);
+        for (i, ni) in net.iter_mut().enumerate() {
+            if i != self.iface {
+                let mut socket = udp_socket();
+                socket.bind(port).map_err(|_| ())?;
+                self.listeners.push((i, ni.sockets.add(socket)));
+            }
+        }
+        Ok(())
     }
 
-    pub fn accept(&mut self) -> Result<IpAddress, ()> {
-        todo!()
+    // Wait for the next datagram and send the following ones to its sender
+    pub fn accept(&mut self) -> Result<IpEndpoint, SocketError> {
+        let timeout = self.options.read_timeout;
+        let started = sys::clock::realtime();
+        let mut net = sys::net::NET.lock();
+        let mut sockets = vec![(self.iface, self.handle)];
+        sockets.extend_from_slice(&self.listeners);
+        loop {
+            if is_expired(started, timeout) {
+                return Err(SocketError::Timeout);
+            }
+            let mut delay = None;
+            for &(i, handle) in &sockets {
+                let ni = net.get_mut(i).ok_or(SocketError::Failure)?;
+                ni.poll();
+                let socket = ni.sockets.get_mut::<udp::Socket>(handle);
+
+                if let Ok((_, metadata)) = socket.peek() {
+                    let endpoint = metadata.endpoint;
+                    self.remote_endpoint = Some(endpoint);
+                    if (i, handle) != (self.iface, self.handle) {
+                        self.listeners.retain(|&l| l != (i, handle));
+                        self.listeners.push((self.iface, self.handle));
+                        (self.iface, self.handle) = (i, handle);
+                    }
+                    return Ok(endpoint);
+                }
+                delay = match (delay, ni.poll_delay()) {
+                    (Some(a), Some(b)) => Some(b.min(a)),
+                    (a, b) => a.or(b),
+                };
+            }
+            if self.options.nonblocking {
+                return Err(SocketError::WouldBlock);
+            }
+
+            if let Some(d) = delay {
+                wait(d);
+            }
+            sys::time::halt();
+        }
     }
-}
 
-impl FileIO for UdpSocket {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
-        let timeout = 5.0;
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize, SocketError> {
+        let timeout = self.options.read_timeout;
         let started = sys::clock::realtime();
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
+        if let Some(ni) = sys::net::NET.lock().get_mut(self.iface) {
             let bytes;
-            let mut sockets = SOCKETS.lock();
             loop {
-                if sys::clock::realtime() - started > timeout {
-                    return Err(());
+                if is_expired(started, timeout) {
+                    return Err(SocketError::Timeout);
                 }
-                iface.poll(sys::net::time(), device, &mut sockets);
-                let socket = sockets.get_mut::<udp::Socket>(self.handle);
+                ni.poll();
+                let socket = ni.sockets.get_mut::<udp::Socket>(self.handle);
 
                 if buf.len() == 1 {
                     // 1 byte status read
@@ -116,32 +178,42 @@ impl FileIO for UdpSocket {
                 }
 
                 if socket.can_recv() {
-                    (bytes, _) = socket.recv_slice(buf).map_err(|_| ())?;
+                    let res = socket.recv_slice(buf);
+                    let (n, metadata) = res.map_err(|_| SocketError::Failure)?;
+                    // Drop the datagrams of other peers once connected
+                    if let Some(endpoint) = self.remote_endpoint {
+                        if metadata.endpoint != endpoint {
+                            continue;
+                        }
+                    }
+                    bytes = n;
                     break;
                 }
-                if let Some(d) = iface.poll_delay(sys::net::time(), &sockets) {
+                if self.options.nonblocking {
+                    return Err(SocketError::WouldBlock);
+                }
+                if let Some(d) = ni.poll_delay() {
                     wait(d);
                 }
                 sys::time::halt();
             }
             Ok(bytes)
         } else {
-            Err(())
+            Err(SocketError::Failure)
         }
     }
 
-    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
-        let timeout = 5.0;
+    pub fn send(&mut self, buf: &[u8]) -> Result<usize, SocketError> {
+        let timeout = self.options.write_timeout;
         let started = sys::clock::realtime();
         let mut sent = false;
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            let mut sockets = SOCKETS.lock();
+        if let Some(ni) = sys::net::NET.lock().get_mut(self.iface) {
             loop {
-                if sys::clock::realtime() - started > timeout {
-                    return Err(());
+                if is_expired(started, timeout) {
+                    return Err(SocketError::Timeout);
                 }
-                iface.poll(sys::net::time(), device, &mut sockets);
-                let socket = sockets.get_mut::<udp::Socket>(self.handle);
+                ni.poll();
+                let socket = ni.sockets.get_mut::<udp::Socket>(self.handle);
 
                 if sent {
                     break;
@@ -149,32 +221,45 @@ impl FileIO for UdpSocket {
                 if socket.can_send() {
                     if let Some(endpoint) = self.remote_endpoint {
                         if socket.send_slice(buf.as_ref(), endpoint).is_err() {
-                            return Err(());
+                            return Err(SocketError::Failure);
                         }
                     } else {
-                        return Err(());
+                        return Err(SocketError::Failure);
                     }
                     sent = true; // Break after next poll
+                } else if self.options.nonblocking {
+                    return Err(SocketError::WouldBlock);
                 }
 
-                if let Some(d) = iface.poll_delay(sys::net::time(), &sockets) {
+                if let Some(d) = ni.poll_delay() {
                     wait(d);
                 }
                 sys::time::halt();
             }
             Ok(buf.len())
         } else {
-            Err(())
+            Err(SocketError::Failure)
         }
     }
+}
+
+impl FileIO for UdpSocket {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        self.recv(buf).map_err(|_| ())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        self.send(buf).map_err(|_| ())
+    }
 
     fn close(&mut self) {
         let mut closed = false;
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            let mut sockets = SOCKETS.lock();
+        let mut net = sys::net::NET.lock();
+        remove_listeners(&mut net, &mut self.listeners);
+        if let Some(ni) = net.get_mut(self.iface) {
             loop {
-                iface.poll(sys::net::time(), device, &mut sockets);
-                let socket = sockets.get_mut::<udp::Socket>(self.handle);
+                ni.poll();
+                let socket = ni.sockets.get_mut::<udp::Socket>(self.handle);
 
                 if closed {
                     break;
@@ -182,7 +267,7 @@ impl FileIO for UdpSocket {
                 socket.close();
                 closed = true;
 
-                if let Some(d) = iface.poll_delay(sys::net::time(), &sockets) {
+                if let Some(d) = ni.poll_delay() {
                     wait(d);
                 }
                 sys::time::halt();
@@ -191,10 +276,9 @@ impl FileIO for UdpSocket {
     }
 
     fn poll(&mut self, event: IO) -> bool {
-        if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-            let mut sockets = SOCKETS.lock();
-            iface.poll(sys::net::time(), device, &mut sockets);
-            let socket = sockets.get_mut::<udp::Socket>(self.handle);
+        if let Some(ni) = sys::net::NET.lock().get_mut(self.iface) {
+            ni.poll();
+            let socket = ni.sockets.get_mut::<udp::Socket>(self.handle);
 
             match event {
                 IO::Read => socket.can_recv(),
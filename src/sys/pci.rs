@@ -130,6 +130,12 @@ pub fn find_device(vendor_id: u16, device_id: u16) -> Option<DeviceConfig> {
     None
 }
 
+pub fn find_class(class: u8, subclass: u8) -> Option<DeviceConfig> {
+    PCI_DEVICES.lock().iter().find(|device|
+        device.class == class && device.subclass == subclass
+    ).copied()
+}
+
 fn check_bus(bus: u8) {
     for device in 0..32 {
         check_device(bus, device);
@@ -222,8 +228,9 @@ pub fn init() {
 
     let devs = PCI_DEVICES.lock();
     for dev in devs.iter() {
-        // NOTE: There's not yet an AHCI driver for SATA disks so we must
-        // switch to IDE legacy mode for the ATA driver.
+        // NOTE: The ATA driver only support the legacy ports so we must
+        // switch IDE controllers to compatibility mode. SATA controllers
+        // in AHCI mode are handled by the AHCI driver.
         if dev.class == 0x01 && dev.subclass == 0x01 {
             // IDE Controller
             let mut register = ConfigRegister::new(
@@ -7,7 +7,7 @@ use crate::sys::fs::FileInfo;
 
 use core::arch::asm;
 use smoltcp::wire::IpAddress;
-use smoltcp::wire::Ipv4Address;
+use smoltcp::wire::{Ipv4Address, Ipv6Address};
 
 fn utf8_from_raw_parts(ptr: *mut u8, len: usize) -> &'static str {
     unsafe {
@@ -104,7 +104,11 @@ pub fn dispatcher(
             let ptr = sys::process::ptr_from_addr(arg2 as u64);
             let len = arg3;
             let buf = unsafe { core::slice::from_raw_parts(ptr, len) };
-            let addr = IpAddress::from(Ipv4Address::from_bytes(buf));
+            let addr = match len {
+                4 => IpAddress::from(Ipv4Address::from_bytes(buf)),
+                16 => IpAddress::from(Ipv6Address::from_bytes(buf)),
+                _ => return -1 as isize as usize,
+            };
             let port = arg4 as u16;
             service::connect(handle, addr, port) as usize
         }
@@ -118,13 +122,29 @@ pub fn dispatcher(
             let ptr = sys::process::ptr_from_addr(arg2 as u64);
             let len = arg3;
             let buf = unsafe { core::slice::from_raw_parts_mut(ptr, len) };
-            if let Ok(addr) = service::accept(handle) {
-                buf[0..len].clone_from_slice(addr.as_bytes());
-                0
-            } else {
-                -1 as isize as usize
+            match service::accept(handle) {
+                Ok(endpoint) if endpoint.addr.as_bytes().len() <= len => {
+                    // Return the length of the address to the caller, with
+                    // the port after it when the buffer is large enough
+                    let addr = endpoint.addr.as_bytes();
+                    let n = addr.len();
+                    buf[0..n].clone_from_slice(addr);
+                    if n + 2 <= len {
+                        let port = endpoint.port.to_be_bytes();
+                        buf[n..n + 2].clone_from_slice(&port);
+                    }
+                    n
+                }
+                Ok(_) => -1 as isize as usize,
+                Err(err) => err as isize as usize,
             }
         }
+        number::SOCKOPT => {
+            let handle = arg1;
+            let option = arg2;
+            let value = f64::from_bits(arg3 as u64);
+            service::sockopt(handle, option, value) as usize
+        }
         number::ALLOC => {
             let size = arg1;
             let align = arg2;
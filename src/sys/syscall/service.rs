@@ -4,12 +4,13 @@ use crate::sys;
 use crate::sys::fs::Device;
 use crate::sys::fs::FileInfo;
 use crate::sys::fs::Resource;
+use crate::sys::net::socket::{SocketError, SocketOption};
 use crate::sys::process::Process;
 
 use alloc::vec;
 use core::alloc::Layout;
 use core::arch::asm;
-use smoltcp::wire::IpAddress;
+use smoltcp::wire::{IpAddress, IpEndpoint};
 
 pub fn exit(code: ExitCode) -> ExitCode {
     sys::process::exit();
@@ -70,22 +71,40 @@ pub fn dup(old_handle: usize, new_handle: usize) -> isize {
     -1
 }
 
+// The sockets can return a distinct error when they have timed out or would
+// block in non-blocking mode.
 pub fn read(handle: usize, buf: &mut [u8]) -> isize {
     if let Some(mut file) = sys::process::handle(handle) {
-        if let Ok(bytes) = file.read(buf) {
-            sys::process::update_handle(handle, *file);
-            return bytes as isize;
-        }
+        let res = match *file {
+            Resource::Device(Device::TcpSocket(ref mut dev)) => dev.recv(buf),
+            Resource::Device(Device::UdpSocket(ref mut dev)) => dev.recv(buf),
+            _ => file.read(buf).map_err(|_| SocketError::Failure),
+        };
+        return match res {
+            Ok(bytes) => {
+                sys::process::update_handle(handle, *file);
+                bytes as isize
+            }
+            Err(err) => err as isize,
+        };
     }
     -1
 }
 
 pub fn write(handle: usize, buf: &mut [u8]) -> isize {
     if let Some(mut file) = sys::process::handle(handle) {
-        if let Ok(bytes) = file.write(buf) {
-            sys::process::update_handle(handle, *file);
-            return bytes as isize;
-        }
+        let res = match *file {
+            Resource::Device(Device::TcpSocket(ref mut dev)) => dev.send(buf),
+            Resource::Device(Device::UdpSocket(ref mut dev)) => dev.send(buf),
+            _ => file.write(buf).map_err(|_| SocketError::Failure),
+        };
+        return match res {
+            Ok(bytes) => {
+                sys::process::update_handle(handle, *file);
+                bytes as isize
+            }
+            Err(err) => err as isize,
+        };
     }
     -1
 }
@@ -157,23 +176,40 @@ pub fn connect(handle: usize, addr: IpAddress, port: u16) -> isize {
             Resource::Device(Device::UdpSocket(ref mut dev)) => {
                 dev.connect(addr, port)
             }
-            _ => Err(()),
+            Resource::Device(Device::IcmpSocket(ref mut dev)) => {
+                dev.connect(addr, port).map_err(|_| SocketError::Failure)
+            }
+            _ => Err(SocketError::Failure),
         };
-        if res.is_ok() {
-            sys::process::update_handle(handle, *file);
-            return 0;
+        // The handle is also updated after a non-blocking connect
+        match res {
+            Ok(()) => {
+                sys::process::update_handle(handle, *file);
+                return 0;
+            }
+            Err(SocketError::WouldBlock) => {
+                sys::process::update_handle(handle, *file);
+                return SocketError::WouldBlock as isize;
+            }
+            Err(err) => return err as isize,
         }
     }
     -1
 }
 
 pub fn listen(handle: usize, port: u16) -> isize {
-    if let Some(file) = sys::process::handle(handle) {
+    if let Some(mut file) = sys::process::handle(handle) {
         let res = match *file {
-            Resource::Device(Device::TcpSocket(mut dev)) => dev.listen(port),
-            Resource::Device(Device::UdpSocket(mut dev)) => dev.listen(port),
+            Resource::Device(Device::TcpSocket(ref mut dev)) => {
+                dev.listen(port)
+            }
+            Resource::Device(Device::UdpSocket(ref mut dev)) => {
+                dev.listen(port)
+            }
             _ => Err(()),
         };
+        // The handle is updated with the copies of the listening socket
+        sys::process::update_handle(handle, *file);
         if res.is_ok() {
             return 0;
         }
@@ -181,15 +217,44 @@ pub fn listen(handle: usize, port: u16) -> isize {
     -1
 }
 
-pub fn accept(handle: usize) -> Result<IpAddress, ()> {
-    if let Some(file) = sys::process::handle(handle) {
-        return match *file {
-            Resource::Device(Device::TcpSocket(mut dev)) => dev.accept(),
-            Resource::Device(Device::UdpSocket(mut dev)) => dev.accept(),
+// The handle is updated with the interface receiving the connection, and
+// the remote endpoint of a UDP socket.
+pub fn accept(handle: usize) -> Result<IpEndpoint, SocketError> {
+    if let Some(mut file) = sys::process::handle(handle) {
+        let res = match *file {
+            Resource::Device(Device::TcpSocket(ref mut dev)) => dev.accept(),
+            Resource::Device(Device::UdpSocket(ref mut dev)) => dev.accept(),
+            _ => Err(SocketError::Failure),
+        };
+        if res.is_ok() {
+            sys::process::update_handle(handle, *file);
+        }
+        return res;
+    }
+    Err(SocketError::Failure)
+}
+
+pub fn sockopt(handle: usize, option: usize, value: f64) -> isize {
+    if let Some(mut file) = sys::process::handle(handle) {
+        let option = match SocketOption::from(option) {
+            Some(option) => option,
+            None => return -1,
+        };
+        let res = match *file {
+            Resource::Device(Device::TcpSocket(ref mut dev)) => {
+                dev.options.set(option, value)
+            }
+            Resource::Device(Device::UdpSocket(ref mut dev)) => {
+                dev.options.set(option, value)
+            }
             _ => Err(()),
         };
+        if res.is_ok() {
+            sys::process::update_handle(handle, *file);
+            return 0;
+        }
     }
-    Err(())
+    -1
 }
 
 pub fn alloc(size: usize, align: usize) -> *mut u8 {
@@ -48,6 +48,7 @@ pub fn sleep(seconds: f64) {
     let start = sys::clock::uptime();
     while sys::clock::uptime() - start < seconds {
         halt();
+        sys::net::poll();
     }
 }
 
@@ -77,6 +78,7 @@ pub fn set_pit_frequency_divider(divider: u16, channel: u8) {
 
 pub fn pit_interrupt_handler() {
     PIT_TICKS.fetch_add(1, Ordering::Relaxed);
+    sys::net::timer_poll();
 }
 
 pub fn rtc_interrupt_handler() {
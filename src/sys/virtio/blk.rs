@@ -0,0 +1,249 @@
+use crate::sys;
+use crate::sys::allocator::PhysBuf;
+use crate::sys::fs::read_drive_blocks;
+use crate::sys::virtio::{Transport, Virtqueue};
+use crate::sys::virtio::{STATUS_DRIVER_OK, STATUS_FAILED, VENDOR_ID};
+use crate::api::fs::{FileIO, IO};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::hint::spin_loop;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+// Virtual I/O Device (VIRTIO) Version 1.0
+// Block Device (5.2)
+
+pub const BLOCK_SIZE: usize = 512;
+
+// Maximum number of blocks transferred by a single request
+const BLOCKS_COUNT: usize = 128;
+
+// Transitional PCI Device ID
+const DEVICE_ID: u16 = 0x1001;
+
+const F_RO: u32 = 1 << 5; // Device is read-only
+
+// Request Types
+const T_IN: u32 =  0; // Read
+const T_OUT: u32 = 1; // Write
+
+// Request Status
+const S_OK: u8 = 0;
+
+#[derive(Clone)]
+pub struct Device {
+    id: u8,
+    transport: Transport,
+    queue: Virtqueue,
+    request: PhysBuf, // Request header followed by the status byte
+    buffer: PhysBuf,
+    block_count: u32,
+    is_read_only: bool,
+}
+
+impl Device {
+    pub fn new(id: u8, io_base: u16) -> Option<Self> {
+        let transport = Transport::new(io_base);
+        let features = transport.init(F_RO);
+        let queue = match Virtqueue::new(&transport, 0) {
+            Some(queue) => queue,
+            None => {
+                transport.add_status(STATUS_FAILED);
+                return None;
+            }
+        };
+        transport.add_status(STATUS_DRIVER_OK);
+
+        // The capacity is always expressed in 512 bytes sectors
+        let capacity = transport.read_config_u64(0);
+
+        Some(Self {
+            id,
+            transport,
+            queue,
+            request: PhysBuf::new(17),
+            buffer: PhysBuf::new(BLOCKS_COUNT * BLOCK_SIZE),
+            block_count: capacity.min(u32::MAX as u64) as u32,
+            is_read_only: features & F_RO > 0,
+        })
+    }
+
+    fn request(&mut self, kind: u32, block: u32, len: usize) -> Result<(), ()> {
+        debug_assert!(len > 0 && len <= self.buffer.len());
+        self.request[0..4].copy_from_slice(&kind.to_le_bytes());
+        self.request[4..8].copy_from_slice(&0u32.to_le_bytes());
+        self.request[8..16].copy_from_slice(&(block as u64).to_le_bytes());
+        self.request[16] = 0xFF;
+
+        let addr = self.request.addr();
+        let bufs = [
+            (addr, 16, false),
+            (self.buffer.addr(), len, kind == T_IN),
+            (addr + 16, 1, true),
+        ];
+        let head = self.queue.add(&bufs).ok_or(())?;
+        self.queue.notify();
+
+        let start = sys::clock::uptime();
+        loop {
+            if let Some((id, _)) = self.queue.pop_used() {
+                if id == head {
+                    break;
+                }
+            }
+            if sys::clock::uptime() - start > 1.0 {
+                debug!("VIRTIO BLK hanged while waiting for request");
+                return Err(());
+            }
+            spin_loop();
+        }
+        self.transport.isr_status();
+
+        if self.request[16] == S_OK {
+            Ok(())
+        } else {
+            debug!("VIRTIO BLK request errored");
+            Err(())
+        }
+    }
+
+    fn read_blocks(&mut self, block: u32, buf: &mut [u8]) -> Result<(), ()> {
+        debug_assert!(buf.len() % BLOCK_SIZE == 0);
+        let n = BLOCKS_COUNT * BLOCK_SIZE;
+        for (i, chunk) in buf.chunks_mut(n).enumerate() {
+            let addr = block + (i * BLOCKS_COUNT) as u32;
+            self.request(T_IN, addr, chunk.len())?;
+            chunk.copy_from_slice(&self.buffer[0..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, block: u32, buf: &[u8]) -> Result<(), ()> {
+        debug_assert!(buf.len() % BLOCK_SIZE == 0);
+        if self.is_read_only {
+            return Err(());
+        }
+        let n = BLOCKS_COUNT * BLOCK_SIZE;
+        for (i, chunk) in buf.chunks(n).enumerate() {
+            let addr = block + (i * BLOCKS_COUNT) as u32;
+            self.buffer[0..chunk.len()].copy_from_slice(chunk);
+            self.request(T_OUT, addr, chunk.len())?;
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    pub static ref DEVICES: Mutex<Vec<Device>> = Mutex::new(Vec::new());
+}
+
+pub fn init() {
+    let devs = sys::pci::list().into_iter().filter(|dev|
+        dev.vendor_id == VENDOR_ID && dev.device_id == DEVICE_ID
+    );
+    for (i, mut dev) in devs.enumerate() {
+        dev.enable_bus_mastering();
+        if let Some(device) = Device::new(i as u8, dev.io_base()) {
+            DEVICES.lock().push(device);
+        }
+    }
+
+    for drive in list() {
+        log!("VIRTIO BLK {} {}", drive.id, drive);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Drive {
+    pub id: u8,
+    block_count: u32,
+    block_index: u32,
+    is_read_only: bool,
+}
+
+impl Drive {
+    pub fn size() -> usize {
+        BLOCK_SIZE
+    }
+
+    pub fn open(id: u8) -> Option<Self> {
+        let devices = DEVICES.lock();
+        let device = devices.iter().find(|d| d.id == id)?;
+        Some(Self {
+            id,
+            block_count: device.block_count,
+            block_index: 0,
+            is_read_only: device.is_read_only,
+        })
+    }
+
+    pub const fn block_size(&self) -> u32 {
+        BLOCK_SIZE as u32
+    }
+
+    pub fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    fn humanized_size(&self) -> (usize, String) {
+        let size = self.block_size() as usize;
+        let count = self.block_count() as usize;
+        let bytes = size * count;
+        if bytes >> 20 < 1000 {
+            (bytes >> 20, String::from("MB"))
+        } else {
+            (bytes >> 30, String::from("GB"))
+        }
+    }
+}
+
+impl FileIO for Drive {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        let id = self.id;
+        read_drive_blocks(
+            buf, BLOCK_SIZE, &mut self.block_index, self.block_count,
+            |block, buf| read(id, block, buf)
+        )
+    }
+
+    fn write(&mut self, _buf: &[u8]) -> Result<usize, ()> {
+        Err(())
+    }
+
+    fn close(&mut self) {
+    }
+
+    fn poll(&mut self, event: IO) -> bool {
+        match event {
+            IO::Read => true,
+            IO::Write => false,
+        }
+    }
+}
+
+impl fmt::Display for Drive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (size, unit) = self.humanized_size();
+        let ro = if self.is_read_only { " RO" } else { "" };
+        write!(f, "VirtIO Block Device{} ({} {})", ro, size, unit)
+    }
+}
+
+pub fn list() -> Vec<Drive> {
+    let ids: Vec<u8> = DEVICES.lock().iter().map(|dev| dev.id).collect();
+    ids.into_iter().filter_map(Drive::open).collect()
+}
+
+pub fn read(id: u8, block: u32, buf: &mut [u8]) -> Result<(), ()> {
+    let mut devices = DEVICES.lock();
+    let device = devices.iter_mut().find(|d| d.id == id).ok_or(())?;
+    device.read_blocks(block, buf)
+}
+
+pub fn write(id: u8, block: u32, buf: &[u8]) -> Result<(), ()> {
+    let mut devices = DEVICES.lock();
+    let device = devices.iter_mut().find(|d| d.id == id).ok_or(())?;
+    device.write_blocks(block, buf)
+}
@@ -0,0 +1,256 @@
+pub mod blk;
+
+use crate::sys::allocator::PhysBuf;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, AtomicU16, Ordering};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+// Virtual I/O Device (VIRTIO) Version 1.0
+// Legacy Interface: A Note on PCI Device Layout (4.1.4.8)
+
+// Legacy Registers
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 =  0x04;
+const REG_QUEUE_ADDRESS: u16 =   0x08;
+const REG_QUEUE_SIZE: u16 =      0x0C;
+const REG_QUEUE_SELECT: u16 =    0x0E;
+const REG_QUEUE_NOTIFY: u16 =    0x10;
+const REG_DEVICE_STATUS: u16 =   0x12;
+const REG_ISR_STATUS: u16 =      0x13;
+const REG_DEVICE_CONFIG: u16 =   0x14; // Without MSI-X
+
+// Device Status Field
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 =      2;
+pub const STATUS_DRIVER_OK: u8 =   4;
+pub const STATUS_FAILED: u8 =      128;
+
+// Virtqueue Descriptor Flags
+const DESC_F_NEXT: u16 =  1; // Buffer continue in the next descriptor
+const DESC_F_WRITE: u16 = 2; // Buffer is write-only for the device
+
+// Legacy virtqueues must be aligned on a page
+const QUEUE_ALIGN: usize = 4096;
+
+pub const VENDOR_ID: u16 = 0x1AF4;
+
+fn align(addr: usize) -> usize {
+    (addr + QUEUE_ALIGN - 1) & !(QUEUE_ALIGN - 1)
+}
+
+#[derive(Debug, Clone)]
+pub struct Transport {
+    io_base: u16,
+}
+
+impl Transport {
+    pub fn new(io_base: u16) -> Self {
+        Self { io_base }
+    }
+
+    // Reset the device and negotiate the features supported by the driver
+    pub fn init(&self, driver_features: u32) -> u32 {
+        self.set_status(0);
+        self.add_status(STATUS_ACKNOWLEDGE);
+        self.add_status(STATUS_DRIVER);
+        let features = self.device_features() & driver_features;
+        unsafe {
+            Port::new(self.io_base + REG_GUEST_FEATURES).write(features);
+        }
+        features
+    }
+
+    pub fn device_features(&self) -> u32 {
+        unsafe { Port::new(self.io_base + REG_DEVICE_FEATURES).read() }
+    }
+
+    pub fn status(&self) -> u8 {
+        unsafe { Port::new(self.io_base + REG_DEVICE_STATUS).read() }
+    }
+
+    pub fn set_status(&self, status: u8) {
+        unsafe { Port::new(self.io_base + REG_DEVICE_STATUS).write(status) }
+    }
+
+    pub fn add_status(&self, status: u8) {
+        self.set_status(self.status() | status);
+    }
+
+    // Reading the ISR status acknowledges the interrupt
+    pub fn isr_status(&self) -> u8 {
+        unsafe { Port::new(self.io_base + REG_ISR_STATUS).read() }
+    }
+
+    pub fn read_config_u8(&self, offset: u16) -> u8 {
+        unsafe { Port::new(self.io_base + REG_DEVICE_CONFIG + offset).read() }
+    }
+
+    pub fn read_config_u16(&self, offset: u16) -> u16 {
+        unsafe { Port::new(self.io_base + REG_DEVICE_CONFIG + offset).read() }
+    }
+
+    pub fn read_config_u32(&self, offset: u16) -> u32 {
+        unsafe { Port::new(self.io_base + REG_DEVICE_CONFIG + offset).read() }
+    }
+
+    pub fn read_config_u64(&self, offset: u16) -> u64 {
+        let lo = self.read_config_u32(offset) as u64;
+        let hi = self.read_config_u32(offset + 4) as u64;
+        lo | (hi << 32)
+    }
+
+    fn select_queue(&self, index: u16) {
+        unsafe { Port::new(self.io_base + REG_QUEUE_SELECT).write(index) }
+    }
+
+    fn queue_size(&self) -> u16 {
+        unsafe { Port::new(self.io_base + REG_QUEUE_SIZE).read() }
+    }
+
+    fn set_queue_address(&self, page: u32) {
+        unsafe { Port::new(self.io_base + REG_QUEUE_ADDRESS).write(page) }
+    }
+}
+
+// A split virtqueue made of a descriptor table, an available ring written by
+// the driver and a used ring written by the device:
+//
+// [descriptors (16 * size bytes)]
+// [available ring (6 + 2 * size bytes)]
+// [padding]
+// [used ring (6 + 8 * size bytes)]
+#[derive(Clone)]
+pub struct Virtqueue {
+    index: u16,
+    size: usize,
+    io_base: u16,
+    mem: PhysBuf,
+    offset: usize, // Start of the page aligned queue in memory
+    avail_offset: usize,
+    used_offset: usize,
+    free_descs: Arc<Mutex<Vec<u16>>>,
+    last_used_idx: Arc<AtomicU16>,
+}
+
+impl Virtqueue {
+    pub fn new(transport: &Transport, index: u16) -> Option<Self> {
+        transport.select_queue(index);
+        let size = transport.queue_size() as usize;
+        if size == 0 {
+            return None;
+        }
+        let avail_offset = 16 * size;
+        let used_offset = align(avail_offset + 6 + 2 * size);
+        let len = used_offset + align(6 + 8 * size);
+        let mem = PhysBuf::new(len + QUEUE_ALIGN);
+        let addr = mem.addr() as usize;
+        let offset = align(addr) - addr;
+        transport.set_queue_address(((addr + offset) / QUEUE_ALIGN) as u32);
+
+        Some(Self {
+            index,
+            size,
+            io_base: transport.io_base,
+            mem,
+            offset,
+            avail_offset,
+            used_offset,
+            free_descs: Arc::new(Mutex::new((0..size as u16).rev().collect())),
+            last_used_idx: Arc::new(AtomicU16::new(0)),
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Add a chain of buffers described by their physical address, their
+    // length, and if they are writable by the device, then return the
+    // index of the head of the chain.
+    pub fn add(&mut self, bufs: &[(u64, usize, bool)]) -> Option<u16> {
+        let ids: Vec<u16> = {
+            let mut free_descs = self.free_descs.lock();
+            if bufs.is_empty() || free_descs.len() < bufs.len() {
+                return None;
+            }
+            let n = free_descs.len() - bufs.len();
+            free_descs.drain(n..).rev().collect()
+        };
+        for (i, &(addr, len, is_writable)) in bufs.iter().enumerate() {
+            let mut flags = if is_writable { DESC_F_WRITE } else { 0 };
+            let next = if i + 1 < bufs.len() {
+                flags |= DESC_F_NEXT;
+                ids[i + 1]
+            } else {
+                0
+            };
+            let desc = 16 * ids[i] as usize;
+            self.write_bytes(desc, &addr.to_le_bytes());
+            self.write_bytes(desc + 8, &(len as u32).to_le_bytes());
+            self.write_bytes(desc + 12, &flags.to_le_bytes());
+            self.write_bytes(desc + 14, &next.to_le_bytes());
+        }
+
+        let head = ids[0];
+        let idx = self.read_u16(self.avail_offset + 2);
+        let ring = self.avail_offset + 4 + 2 * (idx as usize % self.size);
+        self.write_bytes(ring, &head.to_le_bytes());
+        fence(Ordering::SeqCst);
+        let idx = idx.wrapping_add(1);
+        self.write_bytes(self.avail_offset + 2, &idx.to_le_bytes());
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+
+    pub fn notify(&self) {
+        unsafe { Port::new(self.io_base + REG_QUEUE_NOTIFY).write(self.index) }
+    }
+
+    // Return the head and the length of the next chain used by the device
+    pub fn pop_used(&mut self) -> Option<(u16, usize)> {
+        let last = self.last_used_idx.load(Ordering::SeqCst);
+        if self.read_u16(self.used_offset + 2) == last {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let ring = self.used_offset + 4 + 8 * (last as usize % self.size);
+        let head = self.read_u32(ring) as u16;
+        let len = self.read_u32(ring + 4) as usize;
+        self.last_used_idx.store(last.wrapping_add(1), Ordering::SeqCst);
+
+        // Free the descriptors of the chain
+        let mut free_descs = self.free_descs.lock();
+        let mut id = head;
+        loop {
+            free_descs.push(id);
+            let desc = 16 * id as usize;
+            if self.read_u16(desc + 12) & DESC_F_NEXT == 0 {
+                break;
+            }
+            id = self.read_u16(desc + 14);
+        }
+        Some((head, len))
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        let ptr = self.mem[self.offset + offset..].as_ptr() as *const u16;
+        unsafe { core::ptr::read_volatile(ptr) }
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        let ptr = self.mem[self.offset + offset..].as_ptr() as *const u32;
+        unsafe { core::ptr::read_volatile(ptr) }
+    }
+
+    fn write_bytes(&mut self, offset: usize, buf: &[u8]) {
+        let i = self.offset + offset;
+        self.mem[i..(i + buf.len())].copy_from_slice(buf);
+    }
+}
+
+pub fn init() {
+    blk::init();
+}
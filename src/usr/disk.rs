@@ -3,7 +3,6 @@ use crate::api::io;
 use crate::api::process::ExitCode;
 use crate::api::unit::SizeUnit;
 use crate::sys;
-use crate::sys::ata::Drive;
 use crate::sys::console;
 
 use alloc::format;
@@ -29,21 +28,73 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
     }
 }
 
-fn parse_disk_path(pathname: &str) -> Result<(u8, u8), String> {
+#[derive(Clone, Copy)]
+enum Disk {
+    Ata(u8, u8),
+    Ahci(u8),
+    Virtio(u8),
+}
+
+impl Disk {
+    fn block_count(&self) -> Option<u32> {
+        match *self {
+            Disk::Ata(bus, dsk) => {
+                sys::ata::Drive::open(bus, dsk).map(|d| d.block_count())
+            }
+            Disk::Ahci(port) => {
+                sys::ahci::Drive::open(port).map(|d| d.block_count())
+            }
+            Disk::Virtio(id) => {
+                sys::virtio::blk::Drive::open(id).map(|d| d.block_count())
+            }
+        }
+    }
+
+    fn write(&self, block: u32, buf: &[u8]) -> Result<(), ()> {
+        match *self {
+            Disk::Ata(bus, dsk) => sys::ata::write(bus, dsk, block, buf),
+            Disk::Ahci(port) => sys::ahci::write(port, block, buf),
+            Disk::Virtio(id) => sys::virtio::blk::write(id, block, buf),
+        }
+    }
+}
+
+fn parse_disk_path(pathname: &str) -> Result<Disk, String> {
     let path: Vec<_> = pathname.split('/').collect();
-    if !pathname.starts_with("/dev/ata/") || path.len() != 5 {
-        return Err(format!("Could not find disk at '{}'", pathname));
+    if pathname.starts_with("/dev/ata/") && path.len() == 5 {
+        let bus = path[3].parse().or(Err("Could not parse <bus>".to_string()))?;
+        let dsk = path[4].parse().or(Err("Could not parse <dsk>".to_string()))?;
+        Ok(Disk::Ata(bus, dsk))
+    } else if pathname.starts_with("/dev/ahci/") && path.len() == 4 {
+        let err = "Could not parse <port>".to_string();
+        let port = path[3].parse().or(Err(err))?;
+        Ok(Disk::Ahci(port))
+    } else if pathname.starts_with("/dev/virtio/") && path.len() == 4 {
+        let err = "Could not parse <id>".to_string();
+        let id = path[3].parse().or(Err(err))?;
+        Ok(Disk::Virtio(id))
+    } else {
+        Err(format!("Could not find disk at '{}'", pathname))
     }
-    let bus = path[3].parse().or(Err("Could not parse <bus>".to_string()))?;
-    let dsk = path[4].parse().or(Err("Could not parse <dsk>".to_string()))?;
-    Ok((bus, dsk))
 }
 
 fn format(pathname: &str) -> Result<(), ExitCode> {
     match parse_disk_path(pathname) {
-        Ok((bus, dsk)) => {
-            sys::fs::mount_ata(bus, dsk);
-            sys::fs::format_ata();
+        Ok(disk) => {
+            match disk {
+                Disk::Ata(bus, dsk) => {
+                    sys::fs::mount_ata(bus, dsk);
+                    sys::fs::format_ata();
+                }
+                Disk::Ahci(port) => {
+                    sys::fs::mount_ahci(port);
+                    sys::fs::format_ahci();
+                }
+                Disk::Virtio(id) => {
+                    sys::fs::mount_virtio(id);
+                    sys::fs::format_virtio();
+                }
+            }
             println!("Disk successfully formatted");
             println!("MFS is now mounted to '/'");
             Ok(())
@@ -61,14 +112,13 @@ fn is_canceled() -> bool {
 
 fn erase(pathname: &str) -> Result<(), ExitCode> {
     match parse_disk_path(pathname) {
-        Ok((bus, dsk)) => {
-            if let Some(drive) = Drive::open(bus, dsk) {
+        Ok(disk) => {
+            if let Some(n) = disk.block_count() {
                 print!("Proceed? [y/N] ");
                 if io::stdin().read_line().trim() == "y" {
                     println!();
 
-                    let n = drive.block_count();
-                    let buf = vec![0; drive.block_size() as usize];
+                    let buf = vec![0; sys::fs::BLOCK_SIZE];
                     print!("\x1b[?25l"); // Disable cursor
                     for i in 0..n {
                         if is_canceled() {
@@ -79,7 +129,7 @@ fn erase(pathname: &str) -> Result<(), ExitCode> {
                         print!("\x1b[2K\x1b[1G");
                         print!("Erasing block {}/{}", i, n);
                         // TODO: Implement drive.write(block, buf)
-                        sys::ata::write(bus, dsk, i, &buf).ok();
+                        disk.write(i, &buf).ok();
                     }
                     println!();
                     print!("\x1b[?25h"); // Enable cursor
@@ -99,6 +149,14 @@ fn list() -> Result<(), ExitCode> {
     for drive in sys::ata::list() {
         println!("/dev/ata/{}/{}    {}", drive.bus, drive.dsk, drive);
     }
+    for drive in sys::ahci::list() {
+        let path = format!("/dev/ahci/{}", drive.port);
+        println!("{:<16}{}", path, drive);
+    }
+    for drive in sys::virtio::blk::list() {
+        let path = format!("/dev/virtio/{}", drive.id);
+        println!("{:<16}{}", path, drive);
+    }
     Ok(())
 }
 
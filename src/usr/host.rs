@@ -1,29 +1,88 @@
+use crate::api::clock;
 use crate::api::console::Style;
+use crate::api::fs;
 use crate::api::process::ExitCode;
 use crate::api::rng;
 use crate::api::syscall;
 use crate::sys::fs::OpenFlag;
 use crate::usr;
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 use bit_field::BitField;
 use core::convert::TryInto;
+use core::fmt;
 use core::str;
 use core::str::FromStr;
-use smoltcp::wire::{IpAddress, Ipv4Address};
+use lazy_static::lazy_static;
+use smoltcp::wire::{IpAddress, Ipv4Address, Ipv6Address};
+use spin::Mutex;
 
 // See RFC 1035 for implementation details
 
+const HOSTS_FILE: &str = "/ini/hosts";
+const HEADER_LEN: usize = 12;
+const MAX_POINTERS: usize = 16;
+const MAX_CACHE_LEN: usize = 256;
+
+lazy_static! {
+    // The answers are cached until the end of their TTL for every process
+    static ref CACHE: Mutex<BTreeMap<(String, u16), Entry>> = {
+        Mutex::new(BTreeMap::new())
+    };
+}
+
+struct Entry {
+    expires: f64,
+    records: Vec<Record>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
-enum QueryType {
+pub enum QueryType {
     A = 1,
     // NS = 2,
     // MD = 3,
     // MF = 4,
-    // CNAME = 5,
+    CNAME = 5,
     // SOA = 6,
-    // MX = 15,
-    // TXT = 16,
+    PTR = 12,
+    MX = 15,
+    TXT = 16,
+    AAAA = 28,
+}
+
+impl QueryType {
+    pub fn from(n: u16) -> Option<Self> {
+        match n {
+            1 => Some(QueryType::A),
+            5 => Some(QueryType::CNAME),
+            12 => Some(QueryType::PTR),
+            15 => Some(QueryType::MX),
+            16 => Some(QueryType::TXT),
+            28 => Some(QueryType::AAAA),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for QueryType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "A" => Ok(QueryType::A),
+            "CNAME" => Ok(QueryType::CNAME),
+            "PTR" => Ok(QueryType::PTR),
+            "MX" => Ok(QueryType::MX),
+            "TXT" => Ok(QueryType::TXT),
+            "AAAA" => Ok(QueryType::AAAA),
+            _ => Err(()),
+        }
+    }
 }
 
 #[repr(u16)]
@@ -31,7 +90,7 @@ enum QueryClass {
     IN = 1,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum ResponseCode {
     NoError = 0,
@@ -45,6 +104,59 @@ pub enum ResponseCode {
     NetworkError,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordData {
+    A(Ipv4Address),
+    AAAA(Ipv6Address),
+    CNAME(String),
+    PTR(String),
+    MX(u16, String),
+    TXT(String),
+}
+
+impl fmt::Display for RecordData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordData::A(addr) => write!(f, "{}", addr),
+            RecordData::AAAA(addr) => write!(f, "{}", addr),
+            RecordData::CNAME(name) => write!(f, "{}", name),
+            RecordData::PTR(name) => write!(f, "{}", name),
+            RecordData::MX(preference, name) => {
+                write!(f, "{} {}", preference, name)
+            }
+            RecordData::TXT(text) => write!(f, "{:?}", text),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+impl Record {
+    pub fn qtype(&self) -> QueryType {
+        match self.data {
+            RecordData::A(_) => QueryType::A,
+            RecordData::AAAA(_) => QueryType::AAAA,
+            RecordData::CNAME(_) => QueryType::CNAME,
+            RecordData::PTR(_) => QueryType::PTR,
+            RecordData::MX(..) => QueryType::MX,
+            RecordData::TXT(_) => QueryType::TXT,
+        }
+    }
+
+    pub fn address(&self) -> Option<IpAddress> {
+        match self.data {
+            RecordData::A(addr) => Some(IpAddress::from(addr)),
+            RecordData::AAAA(addr) => Some(IpAddress::from(addr)),
+            _ => None,
+        }
+    }
+}
+
 struct Message {
     pub datagram: Vec<u8>,
 }
@@ -104,7 +216,7 @@ impl Message {
     }
 
     pub fn code(&self) -> ResponseCode {
-        match self.header().get_bits(11..15) {
+        match self.header().get_bits(0..4) {
             0 => ResponseCode::NoError,
             1 => ResponseCode::FormatError,
             2 => ResponseCode::ServerFailure,
@@ -114,23 +226,280 @@ impl Message {
             _ => ResponseCode::UnknownError,
         }
     }
+
+    fn read_u16(&self, i: usize) -> Option<u16> {
+        let bytes = self.datagram.get(i..(i + 2))?;
+        Some(u16::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    fn read_u32(&self, i: usize) -> Option<u32> {
+        let bytes = self.datagram.get(i..(i + 4))?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    // Return the name starting at the given index with the index following
+    // it, after having followed its compression pointers
+    fn read_name(&self, mut i: usize) -> Option<(String, usize)> {
+        let mut labels = Vec::new();
+        let mut next = None;
+        let mut pointers = 0;
+        loop {
+            let len = *self.datagram.get(i)? as usize;
+            if len == 0 {
+                i += 1;
+                break;
+            } else if len & 0xC0 == 0xC0 { // Compression pointer
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None; // Loop of pointers
+                }
+                if next.is_none() {
+                    next = Some(i + 2);
+                }
+                i = (self.read_u16(i)? & 0x3FFF) as usize;
+            } else {
+                let label = self.datagram.get((i + 1)..(i + 1 + len))?;
+                labels.push(String::from_utf8_lossy(label).to_string());
+                i += len + 1;
+            }
+        }
+        Some((labels.join("."), next.unwrap_or(i)))
+    }
+
+    // Return the records of the answer section with a supported type
+    pub fn answers(&self) -> Option<Vec<Record>> {
+        let mut records = Vec::new();
+        let mut i = HEADER_LEN;
+        for _ in 0..self.read_u16(4)? {
+            i = self.read_name(i)?.1 + 4; // QNAME + QTYPE + QCLASS
+        }
+        for _ in 0..self.read_u16(6)? {
+            let (name, j) = self.read_name(i)?;
+            let rtype = self.read_u16(j)?;
+            let rclass = self.read_u16(j + 2)?;
+            let ttl = self.read_u32(j + 4)?;
+            let rdlength = self.read_u16(j + 8)? as usize;
+            i = j + 10; // TYPE + CLASS + TTL + RDLENGTH
+            let rdata = self.datagram.get(i..(i + rdlength))?;
+            if rclass == QueryClass::IN as u16 {
+                if let Some(data) = self.read_data(rtype, i, rdata) {
+                    records.push(Record { name, ttl, data });
+                }
+            }
+            i += rdlength;
+        }
+        Some(records)
+    }
+
+    // Return the data of a record starting at the given index
+    fn read_data(
+        &self,
+        rtype: u16,
+        i: usize,
+        rdata: &[u8]
+    ) -> Option<RecordData> {
+        match QueryType::from(rtype)? {
+            QueryType::A if rdata.len() == 4 => {
+                Some(RecordData::A(Ipv4Address::from_bytes(rdata)))
+            }
+            QueryType::AAAA if rdata.len() == 16 => {
+                Some(RecordData::AAAA(Ipv6Address::from_bytes(rdata)))
+            }
+            QueryType::CNAME => {
+                Some(RecordData::CNAME(self.read_name(i)?.0))
+            }
+            QueryType::PTR => {
+                Some(RecordData::PTR(self.read_name(i)?.0))
+            }
+            QueryType::MX => {
+                let preference = self.read_u16(i)?;
+                let name = self.read_name(i + 2)?.0;
+                Some(RecordData::MX(preference, name))
+            }
+            QueryType::TXT => {
+                // One or more character strings prefixed by their length
+                let mut text = Vec::new();
+                let mut j = 0;
+                while j < rdata.len() {
+                    let len = rdata[j] as usize;
+                    text.extend_from_slice(rdata.get((j + 1)..(j + 1 + len))?);
+                    j += len + 1;
+                }
+                Some(RecordData::TXT(String::from_utf8_lossy(&text).into()))
+            }
+            _ => None,
+        }
+    }
 }
 
-fn dns_address() -> Option<IpAddress> {
-    if let Some(servers) = usr::net::get_config("dns") {
-        if let Some((server, _)) = servers.split_once(',') {
-            if let Ok(addr) = IpAddress::from_str(server) {
-                return Some(addr);
+fn dns_servers() -> Vec<IpAddress> {
+    let mut servers = Vec::new();
+    if let Some(config) = usr::net::dns_config() {
+        for server in config.split(',') {
+            if let Ok(addr) = IpAddress::from_str(server.trim()) {
+                servers.push(addr);
             }
         }
     }
-    None
+    if servers.is_empty() {
+        servers.push(IpAddress::v4(8, 8, 8, 8));
+    }
+    servers
+}
+
+// Return the name used for reverse lookups of an address
+pub fn reverse_name(addr: IpAddress) -> String {
+    match addr {
+        IpAddress::Ipv4(addr) => {
+            let bytes = addr.as_bytes();
+            format!(
+                "{}.{}.{}.{}.in-addr.arpa",
+                bytes[3], bytes[2], bytes[1], bytes[0]
+            )
+        }
+        IpAddress::Ipv6(addr) => {
+            let mut name = String::new();
+            for b in addr.as_bytes().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", b & 0xF, b >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+// Return the records of the hosts file matching the query, with lines of
+// an address followed by its names like on Unix
+fn parse_hosts(hosts: &str, name: &str, qtype: QueryType) -> Vec<Record> {
+    let mut records = Vec::new();
+    for line in hosts.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let addr = match fields.next().map(IpAddress::from_str) {
+            Some(Ok(addr)) => addr,
+            _ => continue,
+        };
+        let names: Vec<_> = fields.collect();
+        let data = match (qtype, addr) {
+            (QueryType::PTR, _) if reverse_name(addr) == name => {
+                match names.first() {
+                    Some(host) => RecordData::PTR(host.to_string()),
+                    None => continue,
+                }
+            }
+            _ if !names.iter().any(|n| n.eq_ignore_ascii_case(name)) => {
+                continue;
+            }
+            (QueryType::A, IpAddress::Ipv4(addr)) => RecordData::A(addr),
+            (QueryType::AAAA, IpAddress::Ipv6(addr)) => RecordData::AAAA(addr),
+            _ => continue,
+        };
+        let name = name.to_string();
+        records.push(Record { name, ttl: 0, data });
+    }
+    records
+}
+
+fn hosts(name: &str, qtype: QueryType) -> Vec<Record> {
+    match fs::read_to_string(HOSTS_FILE) {
+        Ok(hosts) => parse_hosts(&hosts, name, qtype),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Return the cached records of a query with their remaining TTL
+fn cached(name: &str, qtype: QueryType) -> Option<Vec<Record>> {
+    let key = (name.to_string(), qtype as u16);
+    let cache = CACHE.lock();
+    let entry = cache.get(&key)?;
+    let ttl = entry.expires - clock::uptime();
+    if ttl <= 0.0 {
+        return None;
+    }
+    let mut records = entry.records.clone();
+    for record in records.iter_mut() {
+        record.ttl = ttl as u32;
+    }
+    Some(records)
+}
+
+fn cache(name: &str, qtype: QueryType, records: &[Record]) {
+    let ttl = records.iter().map(|record| record.ttl).min().unwrap_or(0);
+    if ttl == 0 {
+        return;
+    }
+    let now = clock::uptime();
+    let mut cache = CACHE.lock();
+    cache.retain(|_, entry| entry.expires > now);
+    if cache.len() >= MAX_CACHE_LEN {
+        // Make room by removing the entry that would expire first
+        let oldest = cache.iter().min_by(|(_, a), (_, b)|
+            a.expires.total_cmp(&b.expires)
+        ).map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            cache.remove(&key);
+        }
+    }
+    let key = (name.to_string(), qtype as u16);
+    let expires = now + ttl as f64;
+    let records = records.to_vec();
+    cache.insert(key, Entry { expires, records });
+}
+
+// Split a host and an optional port, with IPv6 addresses enclosed in brackets
+// to be separated from the port: `[::1]:80`
+pub fn split_port(endpoint: &str) -> (&str, Option<&str>) {
+    let i = endpoint.rfind(']').unwrap_or(0);
+    let (host, port) = match endpoint[i..].find(':') {
+        Some(j) => (&endpoint[..i + j], Some(&endpoint[i + j + 1..])),
+        None => (endpoint, None),
+    };
+    (host.trim_start_matches('[').trim_end_matches(']'), port)
 }
 
 pub fn resolve(name: &str) -> Result<IpAddress, ResponseCode> {
-    let addr = dns_address().unwrap_or(IpAddress::v4(8, 8, 8, 8));
+    let records = match lookup(name, QueryType::A) {
+        Err(ResponseCode::NameError) => lookup(name, QueryType::AAAA),
+        res => res,
+    }?;
+    records.iter().find_map(|record| record.address()).
+        ok_or(ResponseCode::NameError)
+}
+
+// Look up the records of a name in the hosts file, then in the cache, and
+// finally with the DNS servers of the network config
+pub fn lookup(
+    name: &str,
+    qtype: QueryType
+) -> Result<Vec<Record>, ResponseCode> {
+    let name = name.trim_end_matches('.').to_lowercase();
+    let records = hosts(&name, qtype);
+    if !records.is_empty() {
+        return Ok(records);
+    }
+    if let Some(records) = cached(&name, qtype) {
+        return Ok(records);
+    }
+    let mut res = Err(ResponseCode::NetworkError);
+    for server in dns_servers() {
+        res = query(&name, qtype, server);
+        if res != Err(ResponseCode::NetworkError) {
+            break;
+        }
+    }
+    let records = res?;
+    cache(&name, qtype, &records);
+    Ok(records)
+}
+
+// Send a query to a DNS server and return the records of the answer
+pub fn query(
+    name: &str,
+    qtype: QueryType,
+    server: IpAddress
+) -> Result<Vec<Record>, ResponseCode> {
     let port = 53;
-    let query = Message::query(name, QueryType::A, QueryClass::IN);
+    let query = Message::query(name, qtype, QueryClass::IN);
 
     let socket_path = "/dev/net/udp";
     let buf_len = if let Some(info) = syscall::info(socket_path) {
@@ -141,7 +510,7 @@ pub fn resolve(name: &str) -> Result<IpAddress, ResponseCode> {
 
     let flags = OpenFlag::Device as usize;
     if let Some(handle) = syscall::open(socket_path, flags) {
-        if syscall::connect(handle, addr, port).is_err() {
+        if syscall::connect(handle, server, port).is_err() {
             syscall::close(handle);
             return Err(ResponseCode::NetworkError);
         }
@@ -152,7 +521,7 @@ pub fn resolve(name: &str) -> Result<IpAddress, ResponseCode> {
         loop {
             let mut data = vec![0; buf_len];
             if let Some(bytes) = syscall::read(handle, &mut data) {
-                if bytes < 28 {
+                if bytes < HEADER_LEN {
                     break;
                 }
                 data.resize(bytes, 0);
@@ -163,15 +532,15 @@ pub fn resolve(name: &str) -> Result<IpAddress, ResponseCode> {
                     //usr::hex::print_hex(&message.datagram);
                     return match message.code() {
                         ResponseCode::NoError => {
-                            // TODO: Parse the datagram instead of extracting
-                            // the last 4 bytes
-                            let n = message.datagram.len();
-                            let data = &message.datagram[(n - 4)..];
-                            let ipv4 = Ipv4Address::from_bytes(data);
-                            if ipv4.is_unspecified() {
-                                Err(ResponseCode::NameError) // FIXME
+                            let records: Vec<_> = message.answers().
+                                ok_or(ResponseCode::UnknownError)?.
+                                into_iter().
+                                filter(|record| record.qtype() == qtype).
+                                collect();
+                            if records.is_empty() {
+                                Err(ResponseCode::NameError)
                             } else {
-                                Ok(IpAddress::from(ipv4))
+                                Ok(records)
                             }
                         }
                         code => Err(code),
@@ -187,15 +556,91 @@ pub fn resolve(name: &str) -> Result<IpAddress, ResponseCode> {
 }
 
 pub fn main(args: &[&str]) -> Result<(), ExitCode> {
-    // TODO: Add `--server <address>` option
-    if args.len() != 2 {
-        help();
-        return Err(ExitCode::UsageError);
-    }
-    let domain = args[1];
-    match resolve(domain) {
-        Ok(addr) => {
-            println!("{}", addr);
+    let mut qtype = None;
+    let mut server = None;
+    let mut name = None;
+    let mut i = 1;
+    let n = args.len();
+    while i < n {
+        match args[i] {
+            "-h" | "--help" => {
+                help();
+                return Ok(());
+            }
+            "-t" | "--type" => {
+                if i + 1 < n {
+                    i += 1;
+                    match QueryType::from_str(args[i]) {
+                        Ok(t) => qtype = Some(t),
+                        Err(_) => {
+                            error!("Invalid record type '{}'", args[i]);
+                            return Err(ExitCode::UsageError);
+                        }
+                    }
+                } else {
+                    error!("Missing record type");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            "-s" | "--server" => {
+                if i + 1 < n {
+                    i += 1;
+                    match IpAddress::from_str(args[i]) {
+                        Ok(addr) => server = Some(addr),
+                        Err(_) => {
+                            error!("Invalid server address '{}'", args[i]);
+                            return Err(ExitCode::UsageError);
+                        }
+                    }
+                } else {
+                    error!("Missing server address");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            arg if arg.starts_with('-') => {
+                error!("Invalid option '{}'", arg);
+                return Err(ExitCode::UsageError);
+            }
+            arg if name.is_none() => {
+                name = Some(arg.to_string());
+            }
+            _ => {
+                help();
+                return Err(ExitCode::UsageError);
+            }
+        }
+        i += 1;
+    }
+    let mut name = match name {
+        Some(name) => name,
+        None => {
+            help();
+            return Err(ExitCode::UsageError);
+        }
+    };
+
+    // An address is looked up by its pointer record
+    if let Ok(addr) = IpAddress::from_str(&name) {
+        name = reverse_name(addr);
+        qtype = qtype.or(Some(QueryType::PTR));
+    }
+
+    let find = |qtype| match server {
+        Some(server) => query(&name, qtype, server),
+        None => lookup(&name, qtype),
+    };
+    let res = match qtype {
+        Some(qtype) => find(qtype),
+        None => match find(QueryType::A) {
+            Err(ResponseCode::NameError) => find(QueryType::AAAA),
+            res => res,
+        },
+    };
+    match res {
+        Ok(records) => {
+            for record in records {
+                println!("{}", record.data);
+            }
             Ok(())
         }
         Err(e) => {
@@ -210,7 +655,103 @@ fn help() {
     let csi_title = Style::color("yellow");
     let csi_reset = Style::reset();
     println!(
-        "{}Usage:{} host {}<domain>{1}",
+        "{}Usage:{} host {}<options> <name>{1}",
         csi_title, csi_reset, csi_option
     );
+    println!();
+    println!("{}Options:{}", csi_title, csi_reset);
+    println!(
+        "  {0}-t{1}, {0}--type <type>{1}        Query records of \
+        {0}<type>{1} (A, AAAA, CNAME, MX, PTR, TXT)",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}-s{1}, {0}--server <address>{1}   Query the DNS server at \
+        {0}<address>{1}",
+        csi_option, csi_reset
+    );
+}
+
+#[test_case]
+fn test_message() {
+    let mut datagram = vec![
+        0x12, 0x34, 0x81, 0x80, // ID + Flags
+        0x00, 0x01, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, // Counts
+        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+        0x00, 0x0F, 0x00, 0x01, // QTYPE + QCLASS
+    ];
+    // MX 10 mail.example.com (with a pointer to the question)
+    datagram.extend_from_slice(&[
+        0xC0, 12, 0x00, 0x0F, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10, 0x00, 9,
+        0x00, 10, 4, b'm', b'a', b'i', b'l', 0xC0, 12,
+    ]);
+    // TXT "hello world" (split in two strings)
+    datagram.extend_from_slice(&[
+        0xC0, 12, 0x00, 0x10, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3C, 0x00, 13,
+        6, b'h', b'e', b'l', b'l', b'o', b' ', 5, b'w', b'o', b'r', b'l', b'd',
+    ]);
+    // A 10.0.2.2 for mail.example.com (with a pointer to the MX record)
+    datagram.extend_from_slice(&[
+        0xC0, 43, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3C, 0x00, 4,
+        10, 0, 2, 2,
+    ]);
+    let message = Message::from(&datagram);
+    assert_eq!(message.id(), 0x1234);
+    assert!(message.is_response());
+    assert_eq!(message.code(), ResponseCode::NoError);
+
+    let records = message.answers().unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].name, "example.com");
+    assert_eq!(records[0].ttl, 3600);
+    assert_eq!(
+        records[0].data,
+        RecordData::MX(10, "mail.example.com".to_string())
+    );
+    assert_eq!(records[1].data, RecordData::TXT("hello world".to_string()));
+    assert_eq!(records[2].name, "mail.example.com");
+    assert_eq!(records[2].address(), Some(IpAddress::v4(10, 0, 2, 2)));
+
+    // Truncated message
+    datagram.pop();
+    assert_eq!(Message::from(&datagram).answers(), None);
+}
+
+#[test_case]
+fn test_reverse_name() {
+    let addr = IpAddress::v4(10, 0, 2, 15);
+    assert_eq!(reverse_name(addr), "15.2.0.10.in-addr.arpa");
+    let addr = IpAddress::from_str("2001:db8::1").unwrap();
+    assert_eq!(
+        reverse_name(addr),
+        "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.\
+         0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+    );
+}
+
+#[test_case]
+fn test_parse_hosts() {
+    let hosts = "# Comment\n\
+                 127.0.0.1 localhost\n\
+                 ::1       localhost\n\
+                 10.0.2.2  gateway gw # Host\n";
+    let records = parse_hosts(hosts, "gw", QueryType::A);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].address(), Some(IpAddress::v4(10, 0, 2, 2)));
+    let records = parse_hosts(hosts, "localhost", QueryType::AAAA);
+    let addr = IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 1);
+    assert_eq!(records[0].address(), Some(addr));
+    let name = "2.2.0.10.in-addr.arpa";
+    let records = parse_hosts(hosts, name, QueryType::PTR);
+    assert_eq!(records[0].data, RecordData::PTR("gateway".to_string()));
+    assert!(parse_hosts(hosts, "example.com", QueryType::A).is_empty());
+    assert!(parse_hosts(hosts, "gateway", QueryType::MX).is_empty());
+}
+
+#[test_case]
+fn test_split_port() {
+    assert_eq!(split_port("10.0.2.2:69"), ("10.0.2.2", Some("69")));
+    assert_eq!(split_port("moros.cc"), ("moros.cc", None));
+    assert_eq!(split_port("[::1]:80"), ("::1", Some("80")));
+    assert_eq!(split_port("[fe80::1]"), ("fe80::1", None));
 }
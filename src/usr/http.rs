@@ -1,50 +1,17 @@
 use crate::api::console::Style;
+use crate::api::fs;
+use crate::api::http::{self, Request, Response, URL};
 use crate::api::process::ExitCode;
+use crate::api::tls;
 use crate::api::syscall;
-use crate::sys::console;
-use crate::sys::fs::OpenFlag;
 use crate::usr;
 
-use alloc::format;
-use alloc::string::{String, ToString};
-use alloc::vec;
-use core::str::{self, FromStr};
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::str::FromStr;
 use smoltcp::wire::IpAddress;
 
-#[derive(Debug)]
-struct URL {
-    pub host: String,
-    pub port: u16,
-    pub path: String,
-}
-
-enum ResponseState {
-    Headers,
-    Body,
-}
-
-impl URL {
-    pub fn parse(url: &str) -> Option<Self> {
-        if !url.starts_with("http://") {
-            return None;
-        }
-        let url = &url[7..];
-        let (server, path) = match url.find('/') {
-            Some(i) => url.split_at(i),
-            None => (url, "/"),
-        };
-        let (host, port) = match server.find(':') {
-            Some(i) => server.split_at(i),
-            None => (server, ":80"),
-        };
-        let port = &port[1..];
-        Some(Self {
-            host: host.into(),
-            port: port.parse().unwrap_or(80),
-            path: path.into(),
-        })
-    }
-}
+const MAX_REDIRECTS: usize = 5;
 
 pub fn main(args: &[&str]) -> Result<(), ExitCode> {
     let csi_verbose = Style::color("blue");
@@ -52,9 +19,15 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
 
     // Parse command line options
     let mut is_verbose = false;
+    let mut scheme = "http://";
     let mut host = "";
     let mut path = "";
     let mut timeout = 5.0;
+    let mut method = None;
+    let mut data_path = None;
+    let mut output_path = None;
+    let mut headers = Vec::new();
+    let mut max_redirects = MAX_REDIRECTS;
     let mut i = 1;
     let n = args.len();
     while i < n {
@@ -74,14 +47,74 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
                     return Err(ExitCode::UsageError);
                 }
             }
+            "-m" | "--method" => {
+                if i + 1 < n {
+                    i += 1;
+                    method = Some(args[i].to_uppercase());
+                } else {
+                    error!("Missing method");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            "-d" | "--data" => {
+                if i + 1 < n {
+                    i += 1;
+                    data_path = Some(args[i]);
+                } else {
+                    error!("Missing data file");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            "-o" | "--output" => {
+                if i + 1 < n {
+                    i += 1;
+                    output_path = Some(args[i]);
+                } else {
+                    error!("Missing output file");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            "-H" | "--header" => {
+                if i + 1 < n {
+                    i += 1;
+                    if let Some((key, val)) = args[i].split_once(':') {
+                        let key = key.trim().to_string();
+                        let val = val.trim().to_string();
+                        headers.push((key, val));
+                    } else {
+                        error!("Invalid header '{}'", args[i]);
+                        return Err(ExitCode::UsageError);
+                    }
+                } else {
+                    error!("Missing header");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            "-r" | "--redirects" => {
+                if i + 1 < n {
+                    i += 1;
+                    if let Ok(n) = args[i].parse() {
+                        max_redirects = n;
+                    } else {
+                        error!("Invalid redirects count '{}'", args[i]);
+                        return Err(ExitCode::UsageError);
+                    }
+                } else {
+                    error!("Missing redirects count");
+                    return Err(ExitCode::UsageError);
+                }
+            }
             _ => {
                 if args[i].starts_with('-') {
                     error!("Invalid option '{}'", args[i]);
                     return Err(ExitCode::UsageError);
                 } else if host.is_empty() {
-                    host = args[i].
-                        trim_start_matches("http://").
-                        trim_start_matches("https://");
+                    host = args[i];
+                    if let Some(rest) = host.strip_prefix("https://") {
+                        (scheme, host) = ("https://", rest);
+                    } else {
+                        host = host.trim_start_matches("http://");
+                    }
                 } else if path.is_empty() {
                     path = args[i];
                 } else {
@@ -104,134 +137,126 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
         }
     }
 
-    let url = "http://".to_string() + host + path;
-    let url = URL::parse(&url).expect("invalid URL format");
-    let port = url.port;
-    let addr = if url.host.ends_with(char::is_numeric) {
-        match IpAddress::from_str(&url.host) {
-            Ok(ip_addr) => ip_addr,
-            Err(_) => {
-                error!("Invalid address format");
-                return Err(ExitCode::UsageError);
-            }
+    let body = if let Some(data_path) = data_path {
+        if let Ok(buf) = fs::read_to_bytes(data_path) {
+            buf
+        } else {
+            error!("Could not read '{}'", data_path);
+            return Err(ExitCode::Failure);
         }
     } else {
-        match usr::host::resolve(&url.host) {
-            Ok(ip_addr) => ip_addr,
-            Err(e) => {
-                error!("Could not resolve host: {:?}", e);
-                return Err(ExitCode::Failure);
-            }
-        }
+        Vec::new()
     };
-
-    let socket_path = "/dev/net/tcp";
-    let buf_len = if let Some(info) = syscall::info(socket_path) {
-        info.size() as usize
-    } else {
-        error!("Could not open '{}'", socket_path);
-        return Err(ExitCode::Failure);
+    let method = match method {
+        Some(method) => method,
+        None if data_path.is_some() => "POST".to_string(),
+        None => "GET".to_string(),
     };
 
-    let mut code = None;
-    let flags = OpenFlag::Device as usize;
-    if let Some(handle) = syscall::open(socket_path, flags) {
-        if syscall::connect(handle, addr, port).is_err() {
-            error!("Could not connect to {}:{}", addr, port);
-            syscall::close(handle);
-            return Err(ExitCode::Failure);
-        }
-        let req = vec![
-            format!("GET {} HTTP/1.1\r\n", url.path),
-            format!("Host: {}\r\n", url.host),
-            format!("User-Agent: MOROS/{}\r\n", env!("CARGO_PKG_VERSION")),
-            format!("Connection: close\r\n"),
-            format!("\r\n"),
-        ];
+    let url = scheme.to_string() + host + path;
+    let mut url = URL::parse(&url).expect("invalid URL format");
+    let mut req = Request::new(&method, &url);
+    req.body = body;
+    for (key, val) in headers {
+        req.headers.insert(key, val);
+    }
+    let mut redirects = 0;
+    let res = loop {
         if is_verbose {
             print!("{}", csi_verbose);
-            for line in &req {
-                print!("> {}", line);
+            for line in req.head().lines() {
+                println!("> {}", line);
             }
             print!("{}", csi_reset);
         }
-        let req = req.join("");
-        syscall::write(handle, req.as_bytes());
-
-        let mut state = ResponseState::Headers;
-        loop {
-            if console::end_of_text() || console::end_of_transmission() {
-                eprintln!();
-                syscall::close(handle);
-                return Err(ExitCode::Failure);
-            }
-            let mut data = vec![0; buf_len];
-            if let Some(n) = syscall::read(handle, &mut data) {
-                if n == 0 {
-                    break;
-                }
-                data.resize(n, 0);
-                let mut i = 0;
-                while i < n {
-                    match state {
-                        ResponseState::Headers => {
-                            let mut j = i;
-                            while j < n {
-                                if data[j] == b'\n' {
-                                    break;
-                                }
-                                j += 1;
-                            }
-                            // TODO: check i == j
-                            let line = String::from_utf8_lossy(&data[i..j]);
-                            if i == 0 {
-                                code = line.split(" ").nth(1).map(|word|
-                                    word.to_string()
-                                );
-                            }
-                            if is_verbose {
-                                if i == 0 {
-                                    print!("{}", csi_verbose);
-                                }
-                                println!("< {}", line);
-                            }
-                            if line.trim().is_empty() {
-                                if is_verbose {
-                                    print!("{}", csi_reset);
-                                }
-                                state = ResponseState::Body;
-                            }
-                            i = j + 1;
-                        }
-                        ResponseState::Body => {
-                            // NOTE: The buffer may not be convertible to a
-                            // UTF-8 string so we write it to STDOUT directly
-                            // instead of using print.
-                            syscall::write(1, &data[i..n]);
-                            break;
-                        }
-                    }
-                }
-            } else {
-                error!("Could not read from {}:{}", addr, port);
-                syscall::close(handle);
-                return Err(ExitCode::Failure);
+        let res = send(&url, &req, timeout)?;
+        if is_verbose {
+            print!("{}", csi_verbose);
+            for line in res.head().lines() {
+                println!("< {}", line);
             }
+            print!("{}", csi_reset);
         }
-        syscall::close(handle);
-        if let Some(s) = code {
-            if let Ok(n) = s.parse::<usize>() {
-                if n < 400 {
-                    return Ok(());
-                }
+        if !res.is_redirect() || redirects == max_redirects {
+            break res;
+        }
+        let location = res.header("Location").and_then(|l| url.join(l));
+        if let Some(next_url) = location {
+            if res.code == 303 {
+                req.method = "GET".to_string();
+                req.body.clear();
             }
+            req.set_url(&next_url);
+            url = next_url;
+            redirects += 1;
+        } else {
+            break res;
+        }
+    };
+
+    if let Some(output_path) = output_path {
+        if fs::write(output_path, &res.body).is_err() {
+            error!("Could not write to '{}'", output_path);
+            return Err(ExitCode::Failure);
         }
-        Err(ExitCode::Failure)
+    } else {
+        // NOTE: The buffer may not be convertible to a UTF-8 string so we
+        // write it to STDOUT directly instead of using print.
+        syscall::write(1, &res.body);
+    }
+    if res.code < 400 {
+        Ok(())
     } else {
         Err(ExitCode::Failure)
     }
 }
 
+fn send(url: &URL, req: &Request, timeout: f64) -> Result<Response, ExitCode> {
+    let addr = if let Ok(addr) = IpAddress::from_str(&url.host) {
+        addr
+    } else {
+        match usr::host::resolve(&url.host) {
+            Ok(ip_addr) => ip_addr,
+            Err(e) => {
+                error!("Could not resolve host: {:?}", e);
+                return Err(ExitCode::Failure);
+            }
+        }
+    };
+    let port = url.port;
+    match http::send(addr, url, req, timeout) {
+        Ok(res) => Ok(res),
+        Err(http::Error::Interrupted) => {
+            eprintln!();
+            Err(ExitCode::Failure)
+        }
+        Err(http::Error::Connect) => {
+            error!("Could not connect to {}:{}", addr, port);
+            Err(ExitCode::Failure)
+        }
+        Err(http::Error::Timeout) => {
+            error!("Timed out waiting for {}:{}", addr, port);
+            Err(ExitCode::Failure)
+        }
+        Err(http::Error::Tls(tls::Error::Certificates)) => {
+            error!("Could not load certificates from '{}'", tls::CA_PATH);
+            Err(ExitCode::Failure)
+        }
+        Err(http::Error::Tls(_)) => {
+            error!("Could not establish TLS session with {}", url.host);
+            Err(ExitCode::Failure)
+        }
+        Err(http::Error::InvalidResponse) => {
+            error!("Could not parse response from {}:{}", addr, port);
+            Err(ExitCode::Failure)
+        }
+        Err(_) => {
+            error!("Could not exchange with {}:{}", addr, port);
+            Err(ExitCode::Failure)
+        }
+    }
+}
+
 fn help() -> Result<(), ExitCode> {
     let csi_option = Style::color("aqua");
     let csi_title = Style::color("yellow");
@@ -250,5 +275,29 @@ fn help() -> Result<(), ExitCode> {
         "  {0}-t{1}, {0}--timeout <seconds>{1}    Request timeout",
         csi_option, csi_reset
     );
+    println!(
+        "  {0}-m{1}, {0}--method <verb>{1}        Request method",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}-d{1}, {0}--data <file>{1}          Send {0}<file>{1} \
+        in the request body",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}-H{1}, {0}--header <key:value>{1}   Add header to the request",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}-r{1}, {0}--redirects <count>{1}    Follow up to {0}<count>{1} \
+        redirects (default: {2})",
+        csi_option, csi_reset, MAX_REDIRECTS
+    );
+    println!(
+        "  {0}-o{1}, {0}--output <file>{1}        Write response body \
+        to {0}<file>{1}",
+        csi_option, csi_reset
+    );
     Ok(())
 }
+
@@ -1,12 +1,18 @@
+use crate::api::base64::Base64;
 use crate::api::clock;
 use crate::api::clock::DATE_TIME_ZONE;
 use crate::api::console::Style;
 use crate::api::fs;
+use crate::api::fs::FileIO;
+use crate::api::http;
+use crate::api::process;
 use crate::api::process::ExitCode;
 use crate::api::syscall;
 use crate::api::time;
 use crate::sys;
 use crate::sys::console;
+use crate::sys::fs::{File, SeekFrom};
+use crate::usr;
 
 use alloc::collections::btree_map::BTreeMap;
 use alloc::collections::vec_deque::VecDeque;
@@ -15,21 +21,74 @@ use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt;
-use smoltcp::iface::SocketSet;
 use smoltcp::phy::Device;
 use smoltcp::socket::tcp;
-use smoltcp::time::Instant;
+use smoltcp::time::Duration;
 use smoltcp::wire::IpAddress;
 
 const MAX_CONNECTIONS: usize = 32;
-const POLL_DELAY_DIV: usize = 128;
 const INDEX: [&str; 4] = ["", "/index.html", "/index.htm", "/index.txt"];
+const CGI_STDIN: &str = "/tmp/httpd.stdin";
+const CGI_STDOUT: &str = "/tmp/httpd.stdout";
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+const LOG_DATE: &str = "%d/%b/%Y:%H:%M:%S %z";
+const LOG_MAX_SIZE: usize = 256 << 10;
+const MAX_REQUEST_LEN: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Deny,
+    Read,
+    Write,
+}
+
+// An access rule is applied to the requests for a path and the paths below
+// it, and it can be restricted to some users authenticated with the basic
+// HTTP authentication scheme.
+// Example: /tmp write alice bob
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    path: String,
+    access: Access,
+    users: Vec<String>,
+}
+
+impl Rule {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let path = fields.next()?;
+        if !path.starts_with('/') {
+            return None;
+        }
+        let path = match path.trim_end_matches('/') {
+            "" => "/".to_string(),
+            path => path.to_string(),
+        };
+        let access = match fields.next()? {
+            "deny" => Access::Deny,
+            "read" => Access::Read,
+            "write" => Access::Write,
+            _ => return None,
+        };
+        let users = fields.map(|user| user.to_string()).collect();
+        Some(Self { path, access, users })
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.path == "/"
+            || path == self.path
+            || path.starts_with(&format!("{}/", self.path))
+    }
+}
 
 #[derive(Clone)]
 struct Request {
     addr: IpAddress,
+    user: Option<String>,
     verb: String,
     path: String,
+    query: String,
+    version: String,
     body: Vec<u8>,
     headers: BTreeMap<String, String>,
 }
@@ -38,44 +97,74 @@ impl Request {
     pub fn new(addr: IpAddress) -> Self {
         Self {
             addr,
+            user: None,
             verb: String::new(),
             path: String::new(),
+            query: String::new(),
+            version: String::new(),
             body: Vec::new(),
             headers: BTreeMap::new(),
         }
     }
 
-    pub fn from(addr: IpAddress, buf: &[u8]) -> Option<Self> {
-        let msg = String::from_utf8_lossy(buf);
-        if !msg.is_empty() {
-            let mut req = Request::new(addr);
-            let mut is_header = true;
-            for (i, line) in msg.lines().enumerate() {
-                if i == 0 {
-                    // Request line
-                    let fields: Vec<_> = line.split(' ').collect();
-                    if fields.len() >= 2 {
-                        req.verb = fields[0].to_string();
-                        req.path = fields[1].to_string();
-                    }
-                } else if is_header {
-                    // Message header
-                    if let Some((key, val)) = line.split_once(':') {
-                        let k = key.trim().to_string();
-                        let v = val.trim().to_string();
-                        req.headers.insert(k, v);
-                    } else if line.is_empty() {
-                        is_header = false;
-                    }
-                } else if !is_header {
-                    // Message body
-                    let s = format!("{}\n", line);
-                    req.body.extend_from_slice(s.as_bytes());
-                }
+    // Parse the request at the beginning of the buffer and return it with
+    // its length once it has been completely received
+    pub fn from(addr: IpAddress, buf: &[u8]) -> Option<(Self, usize)> {
+        let (msg, len) = http::Request::parse_len(buf)?;
+        let mut req = Request::new(addr);
+        req.verb = msg.method;
+        req.path = msg.path;
+        req.query = msg.query;
+        req.version = msg.version;
+        req.headers = msg.headers;
+        req.body = msg.body;
+        Some((req, len))
+    }
+
+    pub fn header(&self, key: &str) -> Option<&String> {
+        self.headers.iter().find_map(|(k, v)|
+            if k.eq_ignore_ascii_case(key) { Some(v) } else { None }
+        )
+    }
+
+    // Return the user authenticated with the basic HTTP authentication
+    // scheme using the passwords of the system
+    pub fn basic_auth(&self) -> Option<String> {
+        let (scheme, credentials) = self.header("Authorization")?.
+            split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let credentials = credentials.trim().trim_end_matches('=');
+        let buf = Base64::decode(credentials.as_bytes()).ok()?;
+        let credentials = String::from_utf8(buf).ok()?;
+        let (user, password) = credentials.split_once(':')?;
+        let hash = usr::user::hashed_password(user)?;
+        usr::user::check(password, &hash).ok()?;
+        Some(user.to_string())
+    }
+}
+
+// The part of a file sent in chunks after the response headers
+#[derive(Clone)]
+struct Stream {
+    file: File,
+    len: usize,
+}
+
+impl Stream {
+    pub fn next_chunk(&mut self, max_len: usize) -> Option<Vec<u8>> {
+        if self.len == 0 {
+            return None;
+        }
+        let mut buf = vec![0; max_len.min(self.len)];
+        match self.file.read(&mut buf) {
+            Ok(n) if n > 0 => {
+                buf.truncate(n);
+                self.len -= n;
+                Some(buf)
             }
-            Some(req)
-        } else {
-            None
+            _ => None,
         }
     }
 }
@@ -86,9 +175,12 @@ struct Response {
     buf: Vec<u8>,
     mime: String,
     time: String,
+    started: f64,
+    latency: f64,
     code: usize,
     size: usize,
     body: Vec<u8>,
+    stream: Option<Stream>,
     headers: BTreeMap<String, String>,
     real_path: String,
 }
@@ -98,7 +190,7 @@ impl Response {
         let mut headers = BTreeMap::new();
         headers.insert(
             "Date".to_string(),
-            time::now_utc().format("%a, %d %b %Y %H:%M:%S GMT"),
+            time::now_utc().format(HTTP_DATE),
         );
         headers.insert(
             "Server".to_string(),
@@ -109,16 +201,23 @@ impl Response {
             buf: Vec::new(),
             mime: String::new(),
             time: time::now().format(DATE_TIME_ZONE),
+            started: clock::realtime(),
+            latency: 0.0,
             code: 0,
             size: 0,
             body: Vec::new(),
+            stream: None,
             headers,
             real_path: String::new(),
         }
     }
 
     pub fn end(&mut self) {
+        self.latency = clock::realtime() - self.started;
         self.size = self.body.len();
+        if let Some(stream) = &self.stream {
+            self.size += stream.len;
+        }
         self.headers.insert(
             "Content-Length".to_string(),
             self.size.to_string()
@@ -133,7 +232,7 @@ impl Response {
         );
         self.headers.insert(
             "Content-Type".to_string(),
-            if self.mime.starts_with("text/") {
+            if self.mime.starts_with("text/") && !self.mime.contains(';') {
                 format!("{}; charset=utf-8", self.mime)
             } else {
                 format!("{}", self.mime)
@@ -157,16 +256,35 @@ impl Response {
     }
 
     fn status(&self) -> String {
-        let msg = match self.code {
-            200 => "OK",
-            301 => "Moved Permanently",
-            400 => "Bad Request",
-            403 => "Forbidden",
-            404 => "Not Found",
-            500 => "Internal Server Error",
-            _ => "Unknown Error",
+        format!("HTTP/1.1 {} {}", self.code, http::status_text(self.code))
+    }
+
+    // Combined Log Format followed by the time taken to process the request
+    // in milliseconds
+    fn log(&self) -> String {
+        let req = &self.req;
+        let time = time::from_timestamp(self.started as i64).format(LOG_DATE);
+        let query = if req.query.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", req.query)
         };
-        format!("HTTP/1.1 {} {}", self.code, msg)
+        let header = |key: &str| req.header(key).map_or("-", String::as_str);
+        format!(
+            "{} - {} [{}] \"{} {}{} {}\" {} {} \"{}\" \"{}\" {:.0}\n",
+            req.addr,
+            req.user.as_deref().unwrap_or("-"),
+            time,
+            req.verb,
+            req.path,
+            query,
+            req.version,
+            self.code,
+            self.size,
+            header("Referer"),
+            header("User-Agent"),
+            self.latency * 1000.0
+        )
     }
 
     fn is_persistent(&self) -> bool {
@@ -187,9 +305,10 @@ impl fmt::Display for Response {
         let csi_reset = Style::reset();
         write!(
             f,
-            "{}{} - -{} [{}] {}\"{} {}\"{} {} {}",
+            "{}{} - {}{} [{}] {}\"{} {}\"{} {} {}",
             csi_cyan,
             self.req.addr,
+            self.req.user.as_deref().unwrap_or("-"),
             csi_pink,
             self.time,
             csi_blue,
@@ -202,6 +321,51 @@ impl fmt::Display for Response {
     }
 }
 
+// Return the rule with the longest path matching the requested path
+fn find_rule<'a>(rules: &'a [Rule], path: &str) -> Option<&'a Rule> {
+    rules.iter().filter(|rule| rule.matches(path)).max_by_key(|rule|
+        rule.path.len()
+    )
+}
+
+// Check the access rule of a request and return the status code of the
+// response when it is not allowed. Writing is allowed without rules.
+fn authorize(req: &mut Request, rules: &[Rule]) -> Result<(), usize> {
+    let rule = match find_rule(rules, &req.path) {
+        Some(rule) => rule,
+        None => return Ok(()),
+    };
+    if rule.access == Access::Deny {
+        return Err(403);
+    }
+    if !rule.users.is_empty() {
+        match req.basic_auth() {
+            Some(user) if rule.users.contains(&user) => {
+                req.user = Some(user);
+            }
+            _ => return Err(401),
+        }
+    }
+    let is_write = matches!(req.verb.as_str(), "PUT" | "DELETE");
+    if is_write && rule.access != Access::Write {
+        return Err(403);
+    }
+    Ok(())
+}
+
+fn deny(res: &mut Response) {
+    res.mime = "text/html".to_string();
+    if res.code == 401 {
+        res.headers.insert(
+            "WWW-Authenticate".to_string(),
+            "Basic realm=\"MOROS\"".to_string(),
+        );
+        res.body.extend_from_slice(b"<h1>Unauthorized</h1>\r\n");
+    } else {
+        res.body.extend_from_slice(b"<h1>Forbidden</h1>\r\n");
+    }
+}
+
 fn get(req: &Request, res: &mut Response) {
     if fs::is_dir(&res.real_path) && !req.path.ends_with('/') {
         res.code = 301;
@@ -218,19 +382,8 @@ fn get(req: &Request, res: &mut Response) {
             if fs::is_dir(&real_path) {
                 continue;
             }
-            if let Ok(buf) = fs::read_to_bytes(&real_path) {
-                res.code = 200;
-                res.mime = content_type(&real_path);
-                let tmp;
-                res.body.extend_from_slice(
-                    if res.mime.starts_with("text/") {
-                        tmp = String::from_utf8_lossy(&buf).to_string().
-                            replace("\n", "\r\n");
-                        tmp.as_bytes()
-                    } else {
-                        &buf
-                    },
-                );
+            if fs::is_file(&real_path) {
+                get_file(req, res, &real_path);
                 not_found = false;
                 break;
             }
@@ -261,6 +414,145 @@ fn get(req: &Request, res: &mut Response) {
     }
 }
 
+// The file is sent in chunks, either whole or the range of bytes requested,
+// unless it has not been modified since the version cached by the client.
+fn get_file(req: &Request, res: &mut Response, path: &str) {
+    let (mut file, info) = match (File::open(path), syscall::info(path)) {
+        (Some(file), Some(info)) => (file, info),
+        _ => {
+            res.code = 500;
+            return;
+        }
+    };
+    let size = file.size();
+    let etag = etag(size, info.time());
+    let modified = time::from_timestamp_utc(info.time() as i64);
+    res.mime = content_type(path);
+    res.headers.insert("ETag".to_string(), etag.clone());
+    res.headers.insert("Last-Modified".to_string(), modified.format(HTTP_DATE));
+    res.headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+
+    if is_not_modified(req, &etag, info.time()) {
+        res.code = 304;
+        return;
+    }
+
+    // The range is ignored if the file has changed since it was cached by
+    // the client in a previous request
+    let range = match req.header("Range") {
+        Some(range) if req.header("If-Range").map_or(true, |v| *v == etag) => {
+            parse_range(range, size)
+        }
+        _ => Ok(None),
+    };
+    let (start, len) = match range {
+        Ok(Some((start, end))) => {
+            res.code = 206;
+            res.headers.insert(
+                "Content-Range".to_string(),
+                format!("bytes {}-{}/{}", start, end, size)
+            );
+            (start, end - start + 1)
+        }
+        Ok(None) => {
+            res.code = 200;
+            (0, size)
+        }
+        Err(()) => {
+            res.code = 416;
+            res.headers.insert(
+                "Content-Range".to_string(),
+                format!("bytes */{}", size)
+            );
+            return;
+        }
+    };
+    if file.seek(SeekFrom::Start(start as u32)).is_err() {
+        res.code = 500;
+        return;
+    }
+    res.stream = Some(Stream { file, len });
+}
+
+// Make an entity tag from the modification time and the size of a file like
+// nginx, to avoid reading the whole file on each request
+fn etag(size: usize, modified: u64) -> String {
+    format!("\"{:x}-{:x}\"", modified, size)
+}
+
+// The `If-None-Match` header takes precedence over `If-Modified-Since`
+fn is_not_modified(req: &Request, etag: &str, modified: u64) -> bool {
+    if let Some(tags) = req.header("If-None-Match") {
+        return tags.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+    }
+    if let Some(date) = req.header("If-Modified-Since") {
+        if let Ok(date) = ::time::PrimitiveDateTime::parse(date, HTTP_DATE) {
+            return modified <= date.assume_utc().unix_timestamp() as u64;
+        }
+    }
+    false
+}
+
+// Parse a single range of bytes and return its first and last positions.
+// Multiple ranges or an invalid range are ignored, but a range starting
+// after the end of the file cannot be satisfied.
+fn parse_range(range: &str, size: usize) -> Result<Option<(usize, usize)>, ()> {
+    let range = match range.trim().strip_prefix("bytes=") {
+        Some(range) if !range.contains(',') => range,
+        _ => return Ok(None),
+    };
+    let (start, end) = match range.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return Ok(None),
+        ("", suffix) => match suffix.parse::<usize>() {
+            Ok(0) => return Err(()),
+            Ok(n) => (size.saturating_sub(n), size.saturating_sub(1)),
+            Err(_) => return Ok(None),
+        },
+        (start, "") => match start.parse() {
+            Ok(start) => (start, size.saturating_sub(1)),
+            Err(_) => return Ok(None),
+        },
+        (start, end) => match (start.parse(), end.parse::<usize>()) {
+            (Ok(start), Ok(end)) if start <= end => {
+                (start, end.min(size.saturating_sub(1)))
+            }
+            _ => return Ok(None),
+        },
+    };
+    if start >= size {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+// The log is rotated when it reaches its maximum size by moving it to a
+// file with the same name followed by `.1`
+fn write_log(path: &str, line: &str) -> Result<(), ()> {
+    if let Some(info) = syscall::info(path) {
+        if info.size() as usize + line.len() > LOG_MAX_SIZE {
+            let buf = fs::read_to_bytes(path)?;
+            fs::write(&format!("{}.1", path), &buf)?;
+            fs::delete(path)?;
+        }
+    }
+    let handle = if fs::exists(path) {
+        fs::append_file(path)
+    } else {
+        fs::create_file(path)
+    };
+    let handle = handle.ok_or(())?;
+    let res = syscall::write(handle, line.as_bytes());
+    syscall::close(handle);
+    res.map(|_| ()).ok_or(())
+}
+
 fn put(req: &Request, res: &mut Response) {
     if res.real_path.ends_with('/') {
         // Write directory
@@ -297,10 +589,122 @@ fn delete(_req: &Request, res: &mut Response) {
     res.mime = "text/plain".to_string();
 }
 
+// Run a Lisp script or a binary with the method, path, query, and headers of
+// the request given in its arguments, and the body of the request given to
+// its standard input. Its standard output is parsed as a CGI response.
+fn cgi(req: &Request, res: &mut Response) {
+    if !fs::is_file(&res.real_path) {
+        res.code = 404;
+        res.mime = "text/html".to_string();
+        res.body.extend_from_slice(b"<h1>Not Found</h1>\r\n");
+        return;
+    }
+    let headers: Vec<String> = req.headers.iter().map(|(key, val)|
+        format!("{}: {}", key, val)
+    ).collect();
+    let mut args = vec![req.verb.as_str(), req.path.as_str()];
+    args.push(req.query.as_str());
+    args.extend(headers.iter().map(String::as_str));
+
+    fs::delete(CGI_STDOUT).ok();
+    let is_redirected = fs::write(CGI_STDIN, &req.body).is_ok()
+        && fs::reopen(CGI_STDIN, 0, false).is_ok()
+        && fs::reopen(CGI_STDOUT, 1, false).is_ok();
+    let res_exec = if is_redirected {
+        exec(&res.real_path, &args)
+    } else {
+        Err(ExitCode::Failure)
+    };
+    for i in 0..2 {
+        fs::reopen("/dev/console", i, false).ok();
+    }
+    let output = fs::read_to_bytes(CGI_STDOUT).unwrap_or_default();
+    fs::delete(CGI_STDIN).ok();
+    fs::delete(CGI_STDOUT).ok();
+
+    if res_exec.is_err() {
+        res.code = 500;
+        res.mime = "text/html".to_string();
+        res.body.extend_from_slice(b"<h1>Internal Server Error</h1>\r\n");
+        return;
+    }
+    res.code = 200;
+    match parse_cgi_headers(&output) {
+        Some((headers, i)) => {
+            for (key, val) in headers {
+                match key.to_lowercase().as_str() {
+                    "status" => {
+                        let code = val.split(' ').next().unwrap_or("");
+                        res.code = code.parse().unwrap_or(500);
+                    }
+                    "content-type" => {
+                        res.mime = val;
+                    }
+                    "content-length" | "connection" => {}
+                    "location" => {
+                        if res.code == 200 {
+                            res.code = 302;
+                        }
+                        res.headers.insert(key, val);
+                    }
+                    _ => {
+                        res.headers.insert(key, val);
+                    }
+                }
+            }
+            res.body.extend_from_slice(&output[i..]);
+        }
+        None => {
+            res.mime = "text/plain".to_string();
+            res.body.extend_from_slice(&output);
+        }
+    }
+}
+
+fn exec(path: &str, args: &[&str]) -> Result<(), ExitCode> {
+    let mut argv = Vec::with_capacity(args.len() + 2);
+    if path.ends_with(".lsp") {
+        argv.push("lisp");
+        argv.push(path);
+        argv.extend(args);
+        usr::lisp::main(&argv)
+    } else {
+        argv.push(path);
+        argv.extend(args);
+        process::spawn(path, &argv)
+    }
+}
+
+// Return the headers at the beginning of the output of a CGI program and
+// the index of its body after the empty line following them, or nothing if
+// the output doesn't start with headers.
+fn parse_cgi_headers(output: &[u8]) -> Option<(Vec<(String, String)>, usize)> {
+    let mut headers = Vec::new();
+    let mut i = 0;
+    while i < output.len() {
+        let n = output[i..].iter().position(|&b| b == b'\n')?;
+        let line = String::from_utf8_lossy(&output[i..i + n]);
+        let line = line.trim_end_matches('\r');
+        i += n + 1;
+        if line.is_empty() {
+            return Some((headers, i));
+        }
+        let (key, val) = line.split_once(':')?;
+        if key.is_empty() || key.contains(' ') {
+            return None;
+        }
+        headers.push((key.to_string(), val.trim().to_string()));
+    }
+    None
+}
+
 pub fn main(args: &[&str]) -> Result<(), ExitCode> {
     let csi_color = Style::color("yellow");
     let csi_reset = Style::reset();
     let mut read_only = false;
+    let mut cgi_dir = None;
+    let mut access_path = None;
+    let mut log_path = None;
     let mut port = 80;
     let mut dir = sys::process::dir();
     let mut i = 1;
@@ -323,6 +727,33 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
                     return Err(ExitCode::UsageError);
                 }
             }
+            "-a" | "--access" => {
+                if i + 1 < n {
+                    i += 1;
+                    access_path = Some(args[i].to_string());
+                } else {
+                    error!("Missing access file");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            "-l" | "--log" => {
+                if i + 1 < n {
+                    i += 1;
+                    log_path = Some(args[i].to_string());
+                } else {
+                    error!("Missing log file");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            "-c" | "--cgi" => {
+                if i + 1 < n {
+                    i += 1;
+                    cgi_dir = Some(args[i].to_string());
+                } else {
+                    error!("Missing directory");
+                    return Err(ExitCode::UsageError);
+                }
+            }
             "-d" | "--dir" => {
                 if i + 1 < n {
                     i += 1;
@@ -339,128 +770,247 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
 
     // NOTE: This specific format is needed by `join_path`
     let dir = format!("/{}", fs::realpath(&dir).trim_matches('/'));
+    let mut rules = Vec::new();
+    if let Some(path) = access_path {
+        if let Ok(contents) = fs::read_to_string(&path) {
+            for line in contents.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if let Some(rule) = Rule::parse(line) {
+                    rules.push(rule);
+                } else {
+                    error!("Could not parse '{}'", line);
+                    return Err(ExitCode::Failure);
+                }
+            }
+        } else {
+            error!("Could not read '{}'", path);
+            return Err(ExitCode::Failure);
+        }
+    }
+    let cgi_dir = cgi_dir.map(|dir|
+        format!("{}/", fs::realpath(&dir).trim_end_matches('/'))
+    );
 
-    if let Some((ref mut iface, ref mut device)) = *sys::net::NET.lock() {
-        let mut sockets = SocketSet::new(vec![]);
-
-        let mtu = device.capabilities().max_transmission_unit;
-        let buf_len = mtu - 14 - 20 - 20; // ETH+TCP+IP headers
-        let mut connections = Vec::new();
+    // The server is listening on every interface, with sockets added to the
+    // set of each interface to be polled with the other sockets. The lock on
+    // the network is only held while receiving and sending data and not
+    // while handling the requests, so that CGI programs can use sockets.
+    let mut connections = Vec::new();
+    let mut net = sys::net::NET.lock();
+    let buf_len = match net.first() {
+        Some(ni) => {
+            let mtu = ni.device.capabilities().max_transmission_unit;
+            mtu - 14 - 20 - 20 // ETH+TCP+IP headers
+        }
+        None => {
+            error!("Could not find network interface");
+            return Err(ExitCode::Failure);
+        }
+    };
+    for (iface, ni) in net.iter_mut().enumerate() {
         for _ in 0..MAX_CONNECTIONS {
             let tcp_rx_buffer = tcp::SocketBuffer::new(vec![0; buf_len]);
             let tcp_tx_buffer = tcp::SocketBuffer::new(vec![0; buf_len]);
             let tcp_socket = tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer);
-            let tcp_handle = sockets.add(tcp_socket);
+            let tcp_handle = ni.sockets.add(tcp_socket);
 
+            let recv_buffer: Vec<u8> = Vec::new();
             let send_queue: VecDeque<Vec<u8>> = VecDeque::new();
             let keep_alive = true;
-            connections.push((tcp_handle, send_queue, keep_alive));
+            let stream: Option<Stream> = None;
+            connections.push((
+                iface, tcp_handle, recv_buffer, send_queue, keep_alive, stream
+            ));
         }
+    }
+    drop(net);
+
+    println!(
+        "{}HTTP Server listening on port {}{}",
+        csi_color, port, csi_reset
+    );
 
-        println!(
-            "{}HTTP Server listening on 0.0.0.0:{}{}",
-            csi_color, port, csi_reset
+    let handle = |mut req: Request| {
+        let access = authorize(&mut req, &rules);
+        let mut res = Response::new(req.clone());
+        res.real_path = join_path(&dir, &req.path);
+        if let Err(code) = access {
+            res.code = code;
+        }
+
+        let is_cgi = cgi_dir.as_ref().map_or(false, |dir|
+            res.real_path.starts_with(dir)
         );
+        match req.verb.as_str() {
+            _ if res.code > 0 => {
+                deny(&mut res)
+            }
+            _ if is_cgi => {
+                cgi(&req, &mut res)
+            }
+            "GET" => {
+                get(&req, &mut res)
+            }
+            "PUT" if !read_only => {
+                put(&req, &mut res)
+            }
+            "DELETE" if !read_only => {
+                delete(&req, &mut res)
+            }
+            _ => {
+                let s = b"<h1>Bad Request</h1>\r\n";
+                res.body.extend_from_slice(s);
+                res.code = 400;
+                res.mime = "text/html".to_string();
+            }
+        }
+        res.end();
+        println!("{}", res);
+        if let Some(path) = &log_path {
+            if write_log(path, &res.log()).is_err() {
+                error!("Could not write to '{}'", path);
+            }
+        }
+        res
+    };
 
-        loop {
-            if console::end_of_text() || console::end_of_transmission() {
-                println!();
-                return Ok(());
+    loop {
+        if console::end_of_text() || console::end_of_transmission() {
+            let mut net = sys::net::NET.lock();
+            for (iface, tcp_handle, _, _, _, _) in &connections {
+                net[*iface].sockets.remove(*tcp_handle);
             }
+            println!();
+            return Ok(());
+        }
 
-            let ms = (clock::realtime() * 1000000.0) as i64;
-            let time = Instant::from_micros(ms);
-            iface.poll(time, device, &mut sockets);
+        // Receive the requests
+        let mut requests = Vec::new();
+        let mut net = sys::net::NET.lock();
+        for ni in net.iter_mut() {
+            ni.poll();
+        }
+        for (i, connection) in connections.iter_mut().enumerate() {
+            let (iface, tcp_handle, recv_buffer, send_queue, _, stream) =
+                connection;
+            let ni = &mut net[*iface];
+            let socket = ni.sockets.get_mut::<tcp::Socket>(*tcp_handle);
 
-            for (tcp_handle, send_queue, keep_alive) in &mut connections {
-                let socket = sockets.get_mut::<tcp::Socket>(*tcp_handle);
+            if !socket.is_open() {
+                socket.listen(port).unwrap();
+                recv_buffer.clear();
+            }
+            let endpoint = match socket.remote_endpoint() {
+                Some(endpoint) => endpoint,
+                None => continue,
+            };
+            if socket.may_recv() {
+                // A request can be larger than the receive buffer of the
+                // socket so it is kept in the buffer of the connection until
+                // it has been completely received.
+                while socket.can_recv() {
+                    let res = socket.recv(|chunk| {
+                        recv_buffer.extend_from_slice(chunk);
+                        (chunk.len(), ())
+                    });
+                    if res.is_err() {
+                        break;
+                    }
+                }
+                if recv_buffer.len() > MAX_REQUEST_LEN {
+                    socket.abort();
+                    recv_buffer.clear();
+                    continue;
+                }
 
-                if !socket.is_open() {
-                    socket.listen(port).unwrap();
+                // The next request is handled after the previous response
+                let is_sent = send_queue.is_empty() && stream.is_none();
+                if !is_sent {
+                    continue;
                 }
-                let endpoint = match socket.remote_endpoint() {
-                    Some(endpoint) => endpoint,
-                    None => continue,
-                };
-                if socket.may_recv() {
-                    // The amount of octets queued in the receive buffer may be
-                    // larger than the contiguous slice returned by `recv` so
-                    // we need to loop over chunks of it until it is empty.
-                    let recv_queue = socket.recv_queue();
-                    let mut receiving = true;
-                    let mut buf = vec![];
-                    while receiving {
-                        let res = socket.recv(|chunk| {
-                            buf.extend_from_slice(chunk);
-                            if buf.len() < recv_queue {
-                                return (chunk.len(), None);
-                            }
-                            receiving = false;
-
-                            let addr = endpoint.addr;
-                            if let Some(req) = Request::from(addr, &buf) {
-                                let mut res = Response::new(req.clone());
-                                res.real_path = join_path(&dir, &req.path);
-
-                                match req.verb.as_str() {
-                                    "GET" => {
-                                        get(&req, &mut res)
-                                    }
-                                    "PUT" if !read_only => {
-                                        put(&req, &mut res)
-                                    }
-                                    "DELETE" if !read_only => {
-                                        delete(&req, &mut res)
-                                    }
-                                    _ => {
-                                        let s = b"<h1>Bad Request</h1>\r\n";
-                                        res.body.extend_from_slice(s);
-                                        res.code = 400;
-                                        res.mime = "text/html".to_string();
-                                    }
-                                }
-                                res.end();
-                                println!("{}", res);
-                                (chunk.len(), Some(res))
-                            } else {
-                                (0, None)
-                            }
-                        });
-                        if receiving {
-                            continue;
-                        }
-                        if let Ok(Some(res)) = res {
-                            *keep_alive = res.is_persistent();
-                            for chunk in res.buf.chunks(buf_len) {
-                                send_queue.push_back(chunk.to_vec());
+                let addr = endpoint.addr;
+                if let Some((req, n)) = Request::from(addr, recv_buffer) {
+                    recv_buffer.drain(..n);
+                    requests.push((i, req));
+                }
+            }
+        }
+        drop(net);
+
+        // Handle the requests without holding the lock on the network
+        let responses: Vec<(usize, Response)> = requests.into_iter().
+            map(|(i, req)| (i, handle(req))).collect();
+
+        // Send the responses
+        let mut net = sys::net::NET.lock();
+        for (i, res) in responses {
+            let (_, _, _, send_queue, keep_alive, stream) = &mut connections[i];
+            *keep_alive = res.is_persistent();
+            for chunk in res.buf.chunks(buf_len) {
+                send_queue.push_back(chunk.to_vec());
+            }
+            *stream = res.stream;
+        }
+        for (iface, tcp_handle, recv_buffer, send_queue, keep_alive, stream) in
+            &mut connections
+        {
+            let ni = &mut net[*iface];
+            let socket = ni.sockets.get_mut::<tcp::Socket>(*tcp_handle);
+            // The connection may have been closed during the handling of its
+            // request
+            if socket.remote_endpoint().is_none() {
+                recv_buffer.clear();
+                send_queue.clear();
+                *stream = None;
+                continue;
+            }
+            if socket.may_recv() {
+                if socket.can_send() {
+                    // Read the next chunk of the file being sent
+                    if send_queue.is_empty() {
+                        if let Some(s) = stream.as_mut() {
+                            match s.next_chunk(buf_len) {
+                                Some(chunk) => send_queue.push_back(chunk),
+                                None => *stream = None,
                             }
                         }
                     }
-                    if socket.can_send() {
-                        if let Some(chunk) = send_queue.pop_front() {
-                            let sent = socket.send_slice(&chunk).
-                                expect("Could not send chunk");
-                            debug_assert!(sent == chunk.len());
+                    // The part of the chunk that doesn't fit in the send
+                    // buffer of the socket is put back in the queue
+                    if let Some(chunk) = send_queue.pop_front() {
+                        let sent = socket.send_slice(&chunk).unwrap_or(0);
+                        if sent < chunk.len() {
+                            send_queue.push_front(chunk[sent..].to_vec());
                         }
                     }
-                    if send_queue.is_empty() && !*keep_alive {
-                        socket.close();
-                    }
-                } else if socket.may_send() {
-                    socket.close();
-                    send_queue.clear();
                 }
-            }
-            if let Some(delay) = iface.poll_delay(time, &sockets) {
-                let d = delay.total_micros() / POLL_DELAY_DIV as u64;
-                if d > 0 {
-                    syscall::sleep((d as f64) / 1000000.0);
+                let is_sent = send_queue.is_empty() && stream.is_none();
+                if is_sent && !*keep_alive {
+                    socket.close();
                 }
+            } else if socket.may_send() {
+                socket.close();
+                send_queue.clear();
+                *stream = None;
             }
         }
-    } else {
-        error!("Could not find network interface");
-        Err(ExitCode::Failure)
+        let mut delay = None;
+        for ni in net.iter_mut() {
+            ni.poll();
+            delay = match (delay, ni.poll_delay()) {
+                (Some(a), Some(b)) => Some(b.min(a)),
+                (a, b) => a.or(b),
+            };
+        }
+        drop(net);
+
+        // Wake up on incoming packets but not later than 100 ms to check
+        // if the user wants to stop the server
+        let max = Duration::from_millis(100);
+        sys::net::wait(delay.unwrap_or(max).min(max));
     }
 }
 
@@ -501,10 +1051,23 @@ fn usage() {
     );
     println!();
     println!("{}Options:{}", csi_title, csi_reset);
+    println!(
+        "  {0}-a{1}, {0}--access <path>{1}    \
+        Load access rules from {0}<path>{1}",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}-c{1}, {0}--cgi <path>{1}       Run programs in {0}<path>{1}",
+        csi_option, csi_reset
+    );
     println!(
         "  {0}-d{1}, {0}--dir <path>{1}       Set directory to {0}<path>{1}",
         csi_option, csi_reset
     );
+    println!(
+        "  {0}-l{1}, {0}--log <path>{1}       Write access log to {0}<path>{1}",
+        csi_option, csi_reset
+    );
     println!(
         "  {0}-p{1}, {0}--port <number>{1}    Listen to port {0}<number>{1}",
         csi_option, csi_reset
@@ -524,3 +1087,85 @@ fn test_join_path() {
     assert_eq!(join_path("/", "/bar"), "/bar");
     assert_eq!(join_path("/", "/"), "/");
 }
+
+#[test_case]
+fn test_parse_cgi_headers() {
+    let output = b"Status: 404\r\nContent-Type: text/html\r\n\r\n<h1>";
+    let headers = vec![
+        ("Status".to_string(), "404".to_string()),
+        ("Content-Type".to_string(), "text/html".to_string()),
+    ];
+    assert_eq!(parse_cgi_headers(output), Some((headers, 40)));
+
+    let output = b"Content-Type: text/plain\n\nHello";
+    assert_eq!(parse_cgi_headers(output).map(|(_, i)| i), Some(26));
+
+    assert_eq!(parse_cgi_headers(b"Hello, World!\n"), None);
+    assert_eq!(parse_cgi_headers(b"Hello: World!\n"), None);
+}
+
+#[test_case]
+fn test_access_rules() {
+    let rules = [
+        Rule::parse("/ read").unwrap(),
+        Rule::parse("/tmp/ write alice bob").unwrap(),
+        Rule::parse("/ini deny").unwrap(),
+    ];
+    assert_eq!(rules[1].path, "/tmp");
+    assert_eq!(rules[1].users, vec!["alice", "bob"]);
+    assert_eq!(find_rule(&rules, "/index.html"), Some(&rules[0]));
+    assert_eq!(find_rule(&rules, "/tmp"), Some(&rules[1]));
+    assert_eq!(find_rule(&rules, "/tmp/foo.txt"), Some(&rules[1]));
+    assert_eq!(find_rule(&rules, "/tmpfoo.txt"), Some(&rules[0]));
+    assert_eq!(find_rule(&rules, "/ini/boot.sh"), Some(&rules[2]));
+    assert_eq!(find_rule(&rules[1..], "/index.html"), None);
+
+    assert_eq!(Rule::parse("/tmp"), None);
+    assert_eq!(Rule::parse("tmp read"), None);
+    assert_eq!(Rule::parse("/tmp execute"), None);
+
+    let addr = IpAddress::v4(10, 0, 2, 2);
+    let req = |line: &str| {
+        let buf = format!("{}\r\n\r\n", line);
+        Request::from(addr, buf.as_bytes()).unwrap().0
+    };
+    assert_eq!(authorize(&mut req("PUT /index.html"), &rules), Err(403));
+    assert_eq!(authorize(&mut req("GET /index.html"), &rules), Ok(()));
+    assert_eq!(authorize(&mut req("GET /ini/boot.sh"), &rules), Err(403));
+    assert_eq!(authorize(&mut req("GET /tmp/"), &rules), Err(401));
+}
+
+#[test_case]
+fn test_parse_range() {
+    assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some((0, 99))));
+    assert_eq!(parse_range("bytes=500-", 1000), Ok(Some((500, 999))));
+    assert_eq!(parse_range("bytes=-100", 1000), Ok(Some((900, 999))));
+    assert_eq!(parse_range("bytes=900-2000", 1000), Ok(Some((900, 999))));
+    assert_eq!(parse_range("bytes=-2000", 1000), Ok(Some((0, 999))));
+    assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+    assert_eq!(parse_range("bytes=0-0,-1", 1000), Ok(None));
+    assert_eq!(parse_range("bytes=99-0", 1000), Ok(None));
+    assert_eq!(parse_range("lines=0-99", 1000), Ok(None));
+}
+
+#[test_case]
+fn test_etag() {
+    assert_eq!(etag(866, 1635529858), "\"617c3482-362\"");
+}
+
+#[test_case]
+fn test_log() {
+    let addr = IpAddress::v4(10, 0, 2, 2);
+    let buf = b"GET /index.html?lang=en HTTP/1.1\r\nUser-Agent: MOROS\r\n\r\n";
+    let (mut req, _) = Request::from(addr, buf).unwrap();
+    req.user = Some("alice".to_string());
+    let mut res = Response::new(req);
+    res.code = 200;
+    res.body.extend_from_slice(b"Hello");
+    res.end();
+    let log = res.log();
+    assert!(log.starts_with("10.0.2.2 - alice ["));
+    assert!(log.contains("] \"GET /index.html?lang=en HTTP/1.1\" 200 5 "));
+    assert!(log.contains(" \"-\" \"MOROS\" "));
+    assert!(log.ends_with('\n'));
+}
@@ -47,6 +47,18 @@ pub fn copy_files(verbose: bool) {
     create_dev("/dev/ata/0/1", "ata-0-1", verbose);
     create_dev("/dev/ata/1/0", "ata-1-0", verbose);
     create_dev("/dev/ata/1/1", "ata-1-1", verbose);
+    for drive in sys::ahci::list() {
+        let path = format!("/dev/ahci/{}", drive.port);
+        let name = format!("ahci-{}", drive.port);
+        create_dir("/dev/ahci", verbose);
+        create_dev(&path, &name, verbose);
+    }
+    for drive in sys::virtio::blk::list() {
+        let path = format!("/dev/virtio/{}", drive.id);
+        let name = format!("virtio-{}", drive.id);
+        create_dir("/dev/virtio", verbose);
+        create_dev(&path, &name, verbose);
+    }
     create_dev("/dev/clk/uptime", "uptime", verbose);
     create_dev("/dev/clk/realtime", "realtime", verbose);
     create_dev("/dev/rtc", "rtc", verbose);
@@ -55,10 +67,12 @@ pub fn copy_files(verbose: bool) {
     create_dev("/dev/console", "console", verbose);
     create_dev("/dev/net/tcp", "tcp", verbose);
     create_dev("/dev/net/udp", "udp", verbose);
+    create_dev("/dev/net/icmp", "icmp", verbose);
     create_dev("/dev/vga/font", "font", verbose);
 
     copy_file!("/ini/banner.txt", verbose);
     copy_file!("/ini/boot.sh", verbose);
+    copy_file!("/ini/hosts", verbose);
     copy_file!("/ini/lisp.lsp", verbose);
     copy_file!("/ini/shell.sh", verbose);
     copy_file!("/ini/version.txt", verbose);
@@ -121,6 +135,8 @@ pub fn copy_files(verbose: bool) {
     copy_file!("/var/www/index.html", verbose);
     copy_file!("/var/www/moros.css", verbose);
     copy_file!("/var/www/moros.png", verbose);
+    create_dir("/var/www/cgi", verbose);
+    copy_file!("/var/www/cgi/status.lsp", verbose);
 
     create_dir("/var/pkg", verbose);
 }
@@ -231,6 +231,14 @@ pub fn default_env() -> Rc<RefCell<Env>> {
         "socket/accept".to_string(),
         Exp::Primitive(primitive::lisp_socket_accept),
     );
+    data.insert(
+        "http/get".to_string(),
+        Exp::Primitive(primitive::lisp_http_get),
+    );
+    data.insert(
+        "http/post".to_string(),
+        Exp::Primitive(primitive::lisp_http_post),
+    );
     data.insert(
         "host".to_string(),
         Exp::Primitive(primitive::lisp_host),
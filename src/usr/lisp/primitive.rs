@@ -4,6 +4,7 @@ use super::{float, number, string};
 use super::{Err, Exp, Number};
 
 use crate::api;
+use crate::api::http;
 use crate::api::regex::Regex;
 use crate::api::syscall;
 use crate::sys::fs::OpenFlag;
@@ -581,6 +582,50 @@ pub fn lisp_socket_accept(args: &[Exp]) -> Result<Exp, Err> {
     }
 }
 
+pub fn lisp_http_get(args: &[Exp]) -> Result<Exp, Err> {
+    ensure_length_eq!(args, 1);
+    http_request("GET", &string(&args[0])?, Vec::new())
+}
+
+pub fn lisp_http_post(args: &[Exp]) -> Result<Exp, Err> {
+    ensure_length_eq!(args, 2);
+    let body = string(&args[1])?.into_bytes();
+    http_request("POST", &string(&args[0])?, body)
+}
+
+// Return a dict with the code, the headers, and the body of the response
+fn http_request(method: &str, url: &str, body: Vec<u8>) -> Result<Exp, Err> {
+    let url = match http::URL::parse(url) {
+        Some(url) => url,
+        None => return expected!("valid URL"),
+    };
+    let addr = match IpAddress::from_str(&url.host) {
+        Ok(addr) => addr,
+        Err(()) => match host::resolve(&url.host) {
+            Ok(addr) => addr,
+            Err(_) => return could_not!("resolve {}", url.host),
+        },
+    };
+    let mut req = http::Request::new(method, &url);
+    req.body = body;
+    match http::send(addr, &url, &req, 5.0) {
+        Ok(res) => {
+            let headers = res.headers.into_iter().map(|(k, v)|
+                (k, Exp::Str(v))
+            ).collect();
+            let mut dict = BTreeMap::new();
+            dict.insert("code".to_string(), Exp::Num(Number::from(res.code)));
+            dict.insert("headers".to_string(), Exp::Dict(headers));
+            dict.insert(
+                "body".to_string(),
+                Exp::Str(String::from_utf8_lossy(&res.body).to_string())
+            );
+            Ok(Exp::Dict(dict))
+        }
+        Err(_) => could_not!("request {}:{}", addr, url.port),
+    }
+}
+
 pub fn lisp_dict(args: &[Exp]) -> Result<Exp, Err> {
     let mut dict = BTreeMap::new();
     for chunk in args.chunks(2) {
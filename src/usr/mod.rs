@@ -28,12 +28,15 @@ pub mod memory;
 pub mod net;
 pub mod pci;
 pub mod pi;
+pub mod ping;
 pub mod pow;
 pub mod r#move;
 pub mod read;
 pub mod shell;
+pub mod shelld;
 pub mod socket;
 pub mod tcp;
+pub mod tftp;
 pub mod time;
 pub mod user;
 pub mod vga;
@@ -7,18 +7,22 @@ use crate::sys;
 use crate::sys::console;
 use crate::sys::net;
 use crate::sys::net::EthernetDeviceIO;
+use crate::sys::net::{arp, firewall, pcap};
+use crate::usr;
 use alloc::format;
 
-use alloc::borrow::ToOwned;
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec;
+use alloc::vec::Vec;
 use core::str::FromStr;
-use smoltcp::iface::SocketSet;
-use smoltcp::phy::Device;
-use smoltcp::socket::tcp;
-use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetFrame, IpCidr, Ipv4Address, PrettyPrinter};
+use smoltcp::iface::Route;
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::raw;
+use smoltcp::wire::{EthernetAddress, HardwareAddress};
+use smoltcp::wire::{IpAddress, IpCidr, IpProtocol, IpVersion, Ipv4Address};
+use smoltcp::wire::{Icmpv6Packet, Icmpv6Repr, NdiscPrefixInfoFlags, NdiscRepr};
+use smoltcp::wire::{Ipv6Address, Ipv6Cidr, Ipv6Packet, Ipv6Repr};
 
 pub fn main(args: &[&str]) -> Result<(), ExitCode> {
     match *args.get(1).unwrap_or(&"") {
@@ -27,25 +31,82 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
             return Ok(());
         }
         "c" | "config" => {
-            if args.len() < 3 {
-                print_config("mac");
-                print_config("ip");
-                print_config("gw");
-                print_config("dns");
-            } else if args[2] == "-h" || args[2] == "--help" {
+            // The first interface is used when none is given
+            let (iface, args) = match args.get(2) {
+                Some(arg) => match net::find_interface(arg) {
+                    Some(i) => (Some(i), &args[3..]),
+                    None => (None, &args[2..]),
+                },
+                None => (None, &args[2..]),
+            };
+            let i = iface.unwrap_or(0);
+            if args.is_empty() {
+                match iface {
+                    Some(i) => print_interface(i),
+                    None => {
+                        for i in 0..interfaces().len() {
+                            if i > 0 {
+                                println!();
+                            }
+                            print_interface(i);
+                        }
+                    }
+                }
+            } else if args[0] == "-h" || args[0] == "--help" {
                 help_config();
                 return Ok(());
-            } else if args.len() < 4 {
-                print_config(args[2]);
+            } else if args[0] == "-s" || args[0] == "--save" {
+                return save_config();
+            } else if args[0] == "-l" || args[0] == "--load" {
+                return load_config();
+            } else if args.len() < 2 {
+                print_config(i, args[0]);
             } else {
-                set_config(args[2], args[3]);
+                set_config(i, args[0], args[1]);
             }
         }
+        "a" | "arp" => {
+            return arp(&args[2..]);
+        }
+        "r" | "route" => {
+            return route(&args[2..]);
+        }
+        "capture" => {
+            return capture(&args[2..]);
+        }
+        "f" | "firewall" => {
+            return firewall(&args[2..]);
+        }
         "s" | "stat" => {
-            stat();
+            match args.get(2) {
+                Some(name) => match net::find_interface(name) {
+                    Some(i) => stat(i),
+                    None => {
+                        error!("Could not find interface '{}'", name);
+                        return Err(ExitCode::Failure);
+                    }
+                },
+                None => {
+                    for i in 0..interfaces().len() {
+                        if i > 0 {
+                            println!();
+                        }
+                        stat(i);
+                    }
+                }
+            }
         }
         "m" | "monitor" => {
-            monitor();
+            let name = args.get(2).unwrap_or(&"");
+            let i = if name.is_empty() {
+                0
+            } else if let Some(i) = net::find_interface(name) {
+                i
+            } else {
+                error!("Could not find interface '{}'", name);
+                return Err(ExitCode::Failure);
+            };
+            monitor(i);
         }
         _ => {
             error!("Invalid command");
@@ -65,8 +126,12 @@ fn help() {
     );
     println!();
     println!("{}Commands:{}", csi_title, csi_reset);
+    println!("  {}arp{}      Manage neighbor cache", csi_option, csi_reset);
+    println!("  {}capture{}  Capture packets", csi_option, csi_reset);
     println!("  {}config{}   Configure network", csi_option, csi_reset);
+    println!("  {}firewall{} Manage firewall rules", csi_option, csi_reset);
     println!("  {}monitor{}  Monitor network", csi_option, csi_reset);
+    println!("  {}route{}    Manage routing table", csi_option, csi_reset);
     println!(
         "  {}stat{}     Display network status",
         csi_option, csi_reset
@@ -78,40 +143,278 @@ fn help_config() {
     let csi_title = Style::color("yellow");
     let csi_reset = Style::reset();
     println!(
-        "{}Usage:{} net config {}<attribute> <value>{}",
+        "{}Usage:{} net config {}[<iface>] <attribute> <value>{}",
         csi_title, csi_reset, csi_option, csi_reset
     );
     println!();
     println!("{}Attributes:{}", csi_title, csi_reset);
-    println!("  {}mac{}  MAC Address", csi_option, csi_reset);
-    println!("  {}ip{}   IP Address", csi_option, csi_reset);
-    println!("  {}gw{}   Gateway Address", csi_option, csi_reset);
-    println!("  {}dns{}  Domain Name Servers", csi_option, csi_reset);
+    println!("  {}mode{}  Static or DHCP", csi_option, csi_reset);
+    println!("  {}lease{} DHCP Server and Expiry", csi_option, csi_reset);
+    println!("  {}mac{}   MAC Address", csi_option, csi_reset);
+    println!("  {}ip{}    IP Address", csi_option, csi_reset);
+    println!("  {}ip6{}   IPv6 Addresses (or auto)", csi_option, csi_reset);
+    println!("  {}gw{}    Gateway Address", csi_option, csi_reset);
+    println!("  {}gw6{}   IPv6 Gateway Address", csi_option, csi_reset);
+    println!("  {}dns{}   Domain Name Servers", csi_option, csi_reset);
+    println!();
+    println!("{}Options:{}", csi_title, csi_reset);
+    println!(
+        "  {0}-s{1}, {0}--save{1}  Save configuration to '{2}'",
+        csi_option, csi_reset, NET_FILE
+    );
+    println!(
+        "  {0}-l{1}, {0}--load{1}  Load configuration from '{2}'",
+        csi_option, csi_reset, NET_FILE
+    );
+}
+
+fn help_route() {
+    let csi_option = Style::color("aqua");
+    let csi_title = Style::color("yellow");
+    let csi_reset = Style::reset();
+    println!(
+        "{}Usage:{} net route {}<command>{}",
+        csi_title, csi_reset, csi_option, csi_reset
+    );
+    println!();
+    println!("{}Commands:{}", csi_title, csi_reset);
+    println!(
+        "  {0}list{1}                           List routes",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}add <cidr> <gw> [<iface>]{1}      Add route",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}del <cidr> [<iface>]{1}           Delete route",
+        csi_option, csi_reset
+    );
+}
+
+fn help_arp() {
+    let csi_option = Style::color("aqua");
+    let csi_title = Style::color("yellow");
+    let csi_reset = Style::reset();
+    println!(
+        "{}Usage:{} net arp {}<command>{}",
+        csi_title, csi_reset, csi_option, csi_reset
+    );
+    println!();
+    println!("{}Commands:{}", csi_title, csi_reset);
+    println!(
+        "  {0}list{1}                           List entries",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}add <ip> <mac> [<iface>]{1}       Add static entry",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}del <ip>{1}                       Delete entry",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}flush{1}                          Delete all dynamic entries",
+        csi_option, csi_reset
+    );
+}
+
+fn help_capture() {
+    let csi_option = Style::color("aqua");
+    let csi_title = Style::color("yellow");
+    let csi_reset = Style::reset();
+    println!(
+        "{}Usage:{} net capture {}<options> <file> [<iface>]{}",
+        csi_title, csi_reset, csi_option, csi_reset
+    );
+    println!();
+    println!("{}Options:{}", csi_title, csi_reset);
+    println!(
+        "  {0}-p{1}, {0}--protocol <name>{1}  \
+        Capture only arp, icmp, tcp, or udp",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}-P{1}, {0}--port <n>{1}         \
+        Capture only TCP or UDP port {0}<n>{1}",
+        csi_option, csi_reset
+    );
+}
+
+fn help_firewall() {
+    let csi_option = Style::color("aqua");
+    let csi_title = Style::color("yellow");
+    let csi_reset = Style::reset();
+    println!(
+        "{}Usage:{} net firewall {}<command>{}",
+        csi_title, csi_reset, csi_option, csi_reset
+    );
+    println!();
+    println!("{}Commands:{}", csi_title, csi_reset);
+    println!(
+        "  {0}list{1}                           List rules",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}add <rule>{1}                     Add rule",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}del <n>{1}                        Delete rule",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}flush{1}                          Delete all rules",
+        csi_option, csi_reset
+    );
+    println!();
+    println!("{}Rules:{}", csi_title, csi_reset);
+    println!(
+        "  {0}allow|deny [<protocol>] [from <cidr>] [port <n>]{1}",
+        csi_option, csi_reset
+    );
+    println!();
+    println!("{}Options:{}", csi_title, csi_reset);
+    println!(
+        "  {0}-s{1}, {0}--save{1}  Save rules to '{2}'",
+        csi_option, csi_reset, FIREWALL_FILE
+    );
+    println!(
+        "  {0}-l{1}, {0}--load{1}  Load rules from '{2}'",
+        csi_option, csi_reset, FIREWALL_FILE
+    );
 }
 
-fn print_config(attribute: &str) {
+// Return the names of the interfaces
+fn interfaces() -> Vec<String> {
+    sys::net::NET.lock().iter().map(|ni| ni.name.clone()).collect()
+}
+
+fn print_attribute(attribute: &str, value: &str) {
     let csi_color = Style::color("aqua");
     let csi_reset = Style::reset();
-    if let Some(value) = get_config(attribute) {
-        let width = 4 - attribute.len();
-        println!(
-            "{}{}:{}{:width$}{}",
-            csi_color,
-            attribute,
-            csi_reset,
-            "",
-            value,
-            width = width
-        );
+    let width = 6 - attribute.len().min(5);
+    println!(
+        "{}{}:{}{:width$}{}",
+        csi_color,
+        attribute,
+        csi_reset,
+        "",
+        value,
+        width = width
+    );
+}
+
+fn print_config(i: usize, attribute: &str) {
+    if let Some(value) = get_config(i, attribute) {
+        print_attribute(attribute, &value);
+    }
+}
+
+fn print_interface(i: usize) {
+    if let Some(name) = interfaces().get(i) {
+        print_attribute("iface", name);
     }
+    print_config(i, "mode");
+    print_config(i, "lease");
+    print_config(i, "mac");
+    print_config(i, "ip");
+    print_config(i, "ip6");
+    print_config(i, "gw");
+    print_config(i, "gw6");
+    print_config(i, "dns");
 }
 
 const DNS_FILE: &str = "/ini/dns";
+const NET_FILE: &str = "/ini/net";
+const FIREWALL_FILE: &str = "/ini/firewall";
+
+// The configuration is saved with one attribute per line, prefixed by the
+// name of the interface, and it is loaded by setting each attribute in
+// order, so a DHCP mode should come first to allow the following lines to
+// override what has been given by the server.
+fn save_config() -> Result<(), ExitCode> {
+    let mut lines = Vec::new();
+    for (i, name) in interfaces().iter().enumerate() {
+        // The loopback interface is configured at boot
+        if name == "lo" {
+            continue;
+        }
+        let mut attributes = vec!["mode"];
+        if sys::net::dhcp::interface() != Some(i) {
+            attributes.extend_from_slice(&["ip", "gw"]);
+        }
+        attributes.extend_from_slice(&["ip6", "gw6", "debug"]);
+        for attribute in attributes {
+            let value = match attribute {
+                // The link-local address is configured at boot
+                "ip6" => ip_config(i, IpVersion::Ipv6).and_then(|addrs|
+                    addrs.split(',').find(|addr|
+                        !addr.starts_with("fe80:")
+                    ).map(|addr| addr.to_string())
+                ),
+                _ => get_config(i, attribute),
+            };
+            if let Some(value) = value {
+                lines.push(format!("{} {} {}\n", name, attribute, value));
+            }
+        }
+    }
+    if let Ok(servers) = fs::read_to_string(DNS_FILE) {
+        lines.push(format!("dns {}\n", servers.trim()));
+    }
+    if fs::write(NET_FILE, lines.join("").as_bytes()).is_ok() {
+        Ok(())
+    } else {
+        error!("Could not write to '{}'", NET_FILE);
+        Err(ExitCode::Failure)
+    }
+}
+
+// Each line can be prefixed by the name of an interface, or the attribute
+// will be set on the first one.
+fn load_config() -> Result<(), ExitCode> {
+    // Nothing to do if the network has never been configured
+    if !fs::exists(NET_FILE) {
+        return Ok(());
+    }
+    if let Ok(contents) = fs::read_to_string(NET_FILE) {
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<_> = line.split_whitespace().collect();
+            match words.len() {
+                2 => set_config(0, words[0], words[1]),
+                3 => match net::find_interface(words[0]) {
+                    Some(i) => set_config(i, words[1], words[2]),
+                    None => error!("Could not find interface '{}'", words[0]),
+                },
+                _ => error!("Could not parse '{}'", line),
+            }
+        }
+        Ok(())
+    } else {
+        error!("Could not read '{}'", NET_FILE);
+        Err(ExitCode::Failure)
+    }
+}
 
-fn dns_config() -> Option<String> {
+pub fn dns_config() -> Option<String> {
+    // The servers given by DHCP take precedence over the saved ones
+    if let Some(lease) = sys::net::dhcp::lease() {
+        if !lease.dns_servers.is_empty() {
+            let servers: Vec<_> = lease.dns_servers.iter().map(|server|
+                server.to_string()
+            ).collect();
+            return Some(servers.join(","));
+        }
+    }
     if let Ok(value) = fs::read_to_string(DNS_FILE) {
         let servers = value.trim();
-        if servers.split(',').all(|s| Ipv4Address::from_str(s).is_ok()) {
+        if servers.split(',').all(|s| IpAddress::from_str(s).is_ok()) {
             Some(servers.to_string())
         } else {
             error!("Could not parse '{}'", servers);
@@ -123,11 +426,15 @@ fn dns_config() -> Option<String> {
     }
 }
 
-fn gw_config() -> Option<String> {
+fn gw_config(i: usize, version: IpVersion) -> Option<String> {
     let mut res = None;
-    if let Some((ref mut iface, _)) = *sys::net::NET.lock() {
-        iface.routes_mut().update(|storage| {
-            if let Some(route) = storage.iter().next() {
+    if let Some(ni) = sys::net::NET.lock().get_mut(i) {
+        ni.iface.routes_mut().update(|storage| {
+            let mut routes = storage.iter().filter(|route|
+                route.via_router.version() == version &&
+                route.cidr.prefix_len() == 0
+            );
+            if let Some(route) = routes.next() {
                 res = Some(route.via_router.to_string());
             }
         });
@@ -137,12 +444,18 @@ fn gw_config() -> Option<String> {
     res
 }
 
-fn ip_config() -> Option<String> {
-    if let Some((ref mut iface, _)) = *sys::net::NET.lock() {
-        if let Some(ip_cidr) = iface.ip_addrs().iter().next() {
-            return Some(format!(
-                "{}/{}", ip_cidr.address(), ip_cidr.prefix_len()
-            ));
+fn ip_config(i: usize, version: IpVersion) -> Option<String> {
+    if let Some(ni) = sys::net::NET.lock().get(i) {
+        let addrs: Vec<_> = ni.iface.ip_addrs().iter().filter(|ip_cidr|
+            match ip_cidr {
+                IpCidr::Ipv4(_) => version == IpVersion::Ipv4,
+                IpCidr::Ipv6(_) => version == IpVersion::Ipv6,
+            }
+        ).map(|ip_cidr|
+            format!("{}/{}", ip_cidr.address(), ip_cidr.prefix_len())
+        ).collect();
+        if !addrs.is_empty() {
+            return Some(addrs.join(","));
         }
     } else {
         error!("Network error");
@@ -150,21 +463,58 @@ fn ip_config() -> Option<String> {
     None
 }
 
-fn mac_config() -> Option<String> {
-    if let Some((ref mut iface, _)) = *sys::net::NET.lock() {
-        return Some(iface.hardware_addr().to_string());
+fn mode_config(i: usize) -> Option<String> {
+    if sys::net::dhcp::interface() == Some(i) {
+        Some("dhcp".to_string())
+    } else {
+        Some("static".to_string())
+    }
+}
+
+fn lease_config(i: usize) -> Option<String> {
+    if sys::net::dhcp::interface() != Some(i) {
+        return None;
+    }
+    let lease = sys::net::dhcp::lease()?;
+    let expiry = match lease.expires_at {
+        Some(time) => {
+            let seconds = (time - clock::uptime()).max(0.0);
+            format!("expires in {:.0}s", seconds)
+        }
+        None => "never expires".to_string(),
+    };
+    Some(format!("{} ({})", lease.server, expiry))
+}
+
+fn debug_config(i: usize) -> Option<String> {
+    if let Some(ni) = sys::net::NET.lock().get(i) {
+        return Some(ni.device.config().is_debug_enabled().to_string());
     } else {
         error!("Network error");
     }
     None
 }
 
-pub fn get_config(attribute: &str) -> Option<String> {
+fn mac_config(i: usize) -> Option<String> {
+    if let Some(ni) = sys::net::NET.lock().get(i) {
+        return Some(ni.iface.hardware_addr().to_string());
+    } else {
+        error!("Network error");
+    }
+    None
+}
+
+pub fn get_config(i: usize, attribute: &str) -> Option<String> {
     match attribute {
+        "debug" => debug_config(i),
         "dns" => dns_config(),
-        "gw" => gw_config(),
-        "ip" => ip_config(),
-        "mac" => mac_config(),
+        "gw" => gw_config(i, IpVersion::Ipv4),
+        "gw6" => gw_config(i, IpVersion::Ipv6),
+        "ip" => ip_config(i, IpVersion::Ipv4),
+        "ip6" => ip_config(i, IpVersion::Ipv6),
+        "mac" => mac_config(i),
+        "lease" => lease_config(i),
+        "mode" => mode_config(i),
         _ => {
             error!("Invalid config attribute");
             None
@@ -172,26 +522,48 @@ pub fn get_config(attribute: &str) -> Option<String> {
     }
 }
 
-pub fn set_config(attribute: &str, value: &str) {
+pub fn set_config(i: usize, attribute: &str, value: &str) {
     match attribute {
         "debug" => {
-            if let Some((_, ref mut device)) = *sys::net::NET.lock() {
+            if let Some(ni) = sys::net::NET.lock().get(i) {
                 match value {
-                    "1" | "true" => device.config().enable_debug(),
-                    "0" | "false" => device.config().disable_debug(),
+                    "1" | "true" => ni.device.config().enable_debug(),
+                    "0" | "false" => ni.device.config().disable_debug(),
                     _ => error!("Invalid config value"),
                 }
             } else {
                 error!("Network error");
             }
         }
+        "mode" => {
+            match value {
+                "dhcp" => {
+                    if let Some(name) = interfaces().get(i) {
+                        usr::dhcp::main(&["dhcp", name]).ok();
+                    }
+                }
+                "static" => {
+                    if sys::net::dhcp::interface() == Some(i) {
+                        sys::net::dhcp::stop();
+                    }
+                }
+                _ => error!("Invalid config value"),
+            }
+        }
         "ip" => {
-            if let Ok(addr) = IpCidr::from_str(value) {
-                if let Some((ref mut iface, _)) = *sys::net::NET.lock() {
-                    iface.update_ip_addrs(|addrs| {
-                        addrs.clear();
-                        addrs.push(addr).unwrap();
-                        log!("NET IP {}", value);
+            if let Ok(addr @ IpCidr::Ipv4(_)) = IpCidr::from_str(value) {
+                // The address would be replaced when the lease is renewed
+                if sys::net::dhcp::interface() == Some(i) {
+                    sys::net::dhcp::stop();
+                }
+                if let Some(ni) = sys::net::NET.lock().get_mut(i) {
+                    ni.iface.update_ip_addrs(|addrs| {
+                        addrs.retain(|a| !matches!(a, IpCidr::Ipv4(_)));
+                        if addrs.push(addr).is_ok() {
+                            log!("NET IP {}", value);
+                        } else {
+                            error!("Could not add address");
+                        }
                     });
                 } else {
                     error!("Network error");
@@ -201,12 +573,58 @@ pub fn set_config(attribute: &str, value: &str) {
             }
         }
         "gw" => {
-            if let Some((ref mut iface, _)) = *sys::net::NET.lock() {
+            if let Some(ni) = sys::net::NET.lock().get_mut(i) {
+                let routes = ni.iface.routes_mut();
                 if value == "0.0.0.0" {
-                    iface.routes_mut().remove_default_ipv4_route();
+                    routes.remove_default_ipv4_route();
                 } else if let Ok(ip) = Ipv4Address::from_str(value) {
-                    iface.routes_mut().add_default_ipv4_route(ip).unwrap();
-                    log!("NET GW {}", value);
+                    if routes.add_default_ipv4_route(ip).is_ok() {
+                        log!("NET GW {}", value);
+                    } else {
+                        error!("Could not add route");
+                    }
+                } else {
+                    error!("Could not parse address");
+                }
+            } else {
+                error!("Network error");
+            }
+        }
+        "ip6" => {
+            if value == "auto" {
+                slaac(i);
+            } else if let Ok(IpCidr::Ipv6(addr)) = IpCidr::from_str(value) {
+                if let Some(ni) = sys::net::NET.lock().get_mut(i) {
+                    ni.iface.update_ip_addrs(|addrs| {
+                        // Keep the IPv4 and the link-local IPv6 addresses
+                        addrs.retain(|a| match a {
+                            IpCidr::Ipv6(a) => a.address().is_link_local(),
+                            _ => true,
+                        });
+                        if addrs.push(IpCidr::Ipv6(addr)).is_ok() {
+                            log!("NET IP6 {}", value);
+                        } else {
+                            error!("Could not add address");
+                        }
+                    });
+                } else {
+                    error!("Network error");
+                }
+            } else {
+                error!("Could not parse address");
+            }
+        }
+        "gw6" => {
+            if let Some(ni) = sys::net::NET.lock().get_mut(i) {
+                let routes = ni.iface.routes_mut();
+                if value == "::" {
+                    routes.remove_default_ipv6_route();
+                } else if let Ok(ip) = Ipv6Address::from_str(value) {
+                    if routes.add_default_ipv6_route(ip).is_ok() {
+                        log!("NET GW6 {}", value);
+                    } else {
+                        error!("Could not add route");
+                    }
                 } else {
                     error!("Could not parse address");
                 }
@@ -216,7 +634,7 @@ pub fn set_config(attribute: &str, value: &str) {
         }
         "dns" => {
             let servers = value.trim();
-            if servers.split(',').all(|s| Ipv4Address::from_str(s).is_ok()) {
+            if servers.split(',').all(|s| IpAddress::from_str(s).is_ok()) {
                 let s = format!("{}\n", servers);
                 if fs::write(DNS_FILE, s.as_bytes()).is_ok() {
                     log!("NET DNS {}", servers);
@@ -233,65 +651,614 @@ pub fn set_config(attribute: &str, value: &str) {
     }
 }
 
-pub fn stat() {
-    if let Some((_, ref mut device)) = *sys::net::NET.lock() {
-        let stats = device.stats();
-        let csi_color = Style::color("aqua");
-        let csi_reset = Style::reset();
-        println!(
-            "{}rx:{} {} packets ({} bytes)",
-            csi_color,
-            csi_reset,
-            stats.rx_packets_count(),
-            stats.rx_bytes_count()
+fn route(args: &[&str]) -> Result<(), ExitCode> {
+    match *args.first().unwrap_or(&"list") {
+        "-h" | "--help" => {
+            help_route();
+            Ok(())
+        }
+        "l" | "list" => {
+            list_routes();
+            Ok(())
+        }
+        "a" | "add" if args.len() == 3 || args.len() == 4 => {
+            let cidr = parse_cidr(args[1])?;
+            let gw = match IpAddress::from_str(args[2]) {
+                Ok(gw) if gw.version() == cidr.address().version() => gw,
+                _ => {
+                    error!("Could not parse gateway");
+                    return Err(ExitCode::UsageError);
+                }
+            };
+            let mut net = sys::net::NET.lock();
+            let i = match args.get(3) {
+                Some(name) => net.iter().position(|ni| ni.name == *name),
+                None => sys::net::route(&mut net, &gw),
+            };
+            let ni = match i {
+                Some(i) => &mut net[i],
+                None => {
+                    error!("Could not find interface");
+                    return Err(ExitCode::Failure);
+                }
+            };
+            let route = Route {
+                cidr,
+                via_router: gw,
+                preferred_until: None,
+                expires_at: None,
+            };
+            let mut res = Ok(());
+            ni.iface.routes_mut().update(|storage| {
+                storage.retain(|r| r.cidr != cidr);
+                if storage.push(route).is_err() {
+                    error!("Could not add route");
+                    res = Err(ExitCode::Failure);
+                }
+            });
+            res
+        }
+        "d" | "del" if args.len() == 2 || args.len() == 3 => {
+            let cidr = parse_cidr(args[1])?;
+            let name = args.get(2);
+            let mut found = false;
+            for ni in sys::net::NET.lock().iter_mut() {
+                if name.map_or(false, |name| ni.name != *name) {
+                    continue;
+                }
+                ni.iface.routes_mut().update(|storage| {
+                    let n = storage.len();
+                    storage.retain(|r| r.cidr != cidr);
+                    found |= storage.len() < n;
+                });
+            }
+            if found {
+                Ok(())
+            } else {
+                error!("Could not find route");
+                Err(ExitCode::Failure)
+            }
+        }
+        _ => {
+            help_route();
+            Err(ExitCode::UsageError)
+        }
+    }
+}
+
+fn arp(args: &[&str]) -> Result<(), ExitCode> {
+    match *args.first().unwrap_or(&"list") {
+        "-h" | "--help" => {
+            help_arp();
+            Ok(())
+        }
+        "l" | "list" => {
+            list_neighbors();
+            Ok(())
+        }
+        "a" | "add" if args.len() == 3 || args.len() == 4 => {
+            let addr = match Ipv4Address::from_str(args[1]) {
+                Ok(addr) if addr.is_unicast() => addr,
+                _ => {
+                    error!("Could not parse address");
+                    return Err(ExitCode::UsageError);
+                }
+            };
+            let mac = match EthernetAddress::from_str(args[2]) {
+                Ok(mac) if mac.is_unicast() => mac,
+                _ => {
+                    error!("Could not parse MAC address");
+                    return Err(ExitCode::UsageError);
+                }
+            };
+            let mut net = sys::net::NET.lock();
+            let i = match args.get(3) {
+                Some(name) => net.iter().position(|ni| ni.name == *name),
+                None => sys::net::route(&mut net, &addr.into()),
+            };
+            match i {
+                Some(i) => {
+                    arp::add(&mut net[i], addr, mac);
+                    Ok(())
+                }
+                None => {
+                    error!("Could not find interface");
+                    Err(ExitCode::Failure)
+                }
+            }
+        }
+        "d" | "del" if args.len() == 2 => {
+            let addr = match Ipv4Address::from_str(args[1]) {
+                Ok(addr) => addr,
+                Err(_) => {
+                    error!("Could not parse address");
+                    return Err(ExitCode::UsageError);
+                }
+            };
+            let mut found = false;
+            for ni in sys::net::NET.lock().iter_mut() {
+                found |= arp::delete(ni, addr).is_ok();
+            }
+            if found {
+                Ok(())
+            } else {
+                error!("Could not find entry");
+                Err(ExitCode::Failure)
+            }
+        }
+        "flush" => {
+            for ni in sys::net::NET.lock().iter_mut() {
+                arp::flush(ni);
+            }
+            Ok(())
+        }
+        _ => {
+            help_arp();
+            Err(ExitCode::UsageError)
+        }
+    }
+}
+
+fn list_neighbors() {
+    let csi_color = Style::color("aqua");
+    let csi_reset = Style::reset();
+    println!(
+        "{}{:16} {:18} {:6} {}{}",
+        csi_color, "address", "mac", "iface", "age", csi_reset
+    );
+    for ni in sys::net::NET.lock().iter() {
+        for n in &ni.neighbors {
+            let mac = n.mac.to_string().to_uppercase();
+            let age = if n.is_static {
+                "static".to_string()
+            } else {
+                format!("{:.0}s", n.age())
+            };
+            println!(
+                "{:16} {:18} {:6} {}", n.addr.to_string(), mac, ni.name, age
+            );
+        }
+    }
+}
+
+fn firewall(args: &[&str]) -> Result<(), ExitCode> {
+    match *args.first().unwrap_or(&"list") {
+        "-h" | "--help" => {
+            help_firewall();
+            Ok(())
+        }
+        "-s" | "--save" => {
+            save_firewall()
+        }
+        "-l" | "--load" => {
+            load_firewall()
+        }
+        "l" | "list" => {
+            let csi_color = Style::color("aqua");
+            let csi_reset = Style::reset();
+            for (i, rule) in firewall::rules().iter().enumerate() {
+                println!(
+                    "{}{:>3}:{} {} ({} hits)",
+                    csi_color, i + 1, csi_reset, rule, rule.hits
+                );
+            }
+            Ok(())
+        }
+        "a" | "add" if args.len() > 1 => {
+            let s = args[1..].join(" ");
+            if let Some(rule) = firewall::Rule::parse(&s) {
+                firewall::add(rule);
+                Ok(())
+            } else {
+                error!("Could not parse rule");
+                Err(ExitCode::UsageError)
+            }
+        }
+        "d" | "del" if args.len() == 2 => {
+            // The rules are numbered from 1 in the list
+            match args[1].parse::<usize>() {
+                Ok(n) if n > 0 && firewall::delete(n - 1).is_ok() => Ok(()),
+                _ => {
+                    error!("Could not find rule");
+                    Err(ExitCode::Failure)
+                }
+            }
+        }
+        "flush" => {
+            firewall::flush();
+            Ok(())
+        }
+        _ => {
+            help_firewall();
+            Err(ExitCode::UsageError)
+        }
+    }
+}
+
+fn save_firewall() -> Result<(), ExitCode> {
+    let lines: Vec<_> = firewall::rules().iter().map(|rule|
+        format!("{}\n", rule)
+    ).collect();
+    if fs::write(FIREWALL_FILE, lines.join("").as_bytes()).is_ok() {
+        Ok(())
+    } else {
+        error!("Could not write to '{}'", FIREWALL_FILE);
+        Err(ExitCode::Failure)
+    }
+}
+
+// The rules of the file replace the current ones
+fn load_firewall() -> Result<(), ExitCode> {
+    if !fs::exists(FIREWALL_FILE) {
+        return Ok(());
+    }
+    if let Ok(contents) = fs::read_to_string(FIREWALL_FILE) {
+        firewall::flush();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(rule) = firewall::Rule::parse(line) {
+                firewall::add(rule);
+            } else {
+                error!("Could not parse '{}'", line);
+            }
+        }
+        Ok(())
+    } else {
+        error!("Could not read '{}'", FIREWALL_FILE);
+        Err(ExitCode::Failure)
+    }
+}
+
+fn parse_cidr(s: &str) -> Result<IpCidr, ExitCode> {
+    match IpCidr::from_str(s) {
+        Ok(cidr) => Ok(network(&cidr)),
+        Err(_) => {
+            error!("Could not parse network");
+            Err(ExitCode::UsageError)
+        }
+    }
+}
+
+// The routing table is made of the networks directly connected to each
+// interface followed by the routes to other networks through a gateway.
+fn list_routes() {
+    let csi_color = Style::color("aqua");
+    let csi_reset = Style::reset();
+    println!(
+        "{}{:24} {:6} {}{}",
+        csi_color, "destination", "iface", "gateway", csi_reset
+    );
+    for ni in sys::net::NET.lock().iter_mut() {
+        let mut routes = Vec::new();
+        for cidr in ni.iface.ip_addrs() {
+            routes.push((network(cidr), None));
+        }
+        ni.iface.routes_mut().update(|storage| {
+            for route in storage.iter() {
+                routes.push((route.cidr, Some(route.via_router)));
+            }
+        });
+        for (cidr, gw) in routes {
+            let gw = gw.map(|gw| gw.to_string()).unwrap_or("-".to_string());
+            println!("{:24} {:6} {}", cidr.to_string(), ni.name, gw);
+        }
+    }
+}
+
+// Return the network of an address by clearing the bits after its prefix
+fn network(cidr: &IpCidr) -> IpCidr {
+    match cidr {
+        IpCidr::Ipv4(cidr) => IpCidr::Ipv4(cidr.network()),
+        IpCidr::Ipv6(cidr) => {
+            let n = cidr.prefix_len() as usize;
+            let mut bytes = [0; 16];
+            for (i, b) in cidr.address().as_bytes().iter().enumerate() {
+                let bits = n.saturating_sub(i * 8).min(8);
+                bytes[i] = b & (0xFF00u16 >> bits) as u8;
+            }
+            let addr = Ipv6Address::from_bytes(&bytes);
+            IpCidr::Ipv6(Ipv6Cidr::new(addr, cidr.prefix_len()))
+        }
+    }
+}
+
+// Stateless Address Autoconfiguration (RFC 4862)
+//
+// A router solicitation is sent to all the routers on the link and the
+// prefix of the first router advertisement received is combined with the
+// interface identifier of the link-local address to get a global address.
+fn slaac(i: usize) {
+    let (mac, handle) = if let Some(ni) = net::NET.lock().get_mut(i) {
+        let HardwareAddress::Ethernet(mac) = ni.iface.hardware_addr();
+        let link_local = net::ipv6_eui64_addr(
+            Ipv6Address::new(0xFE80, 0, 0, 0, 0, 0, 0, 0), mac
         );
-        println!(
-            "{}tx:{} {} packets ({} bytes)",
-            csi_color,
-            csi_reset,
-            stats.tx_packets_count(),
-            stats.tx_bytes_count()
+
+        let raw_rx_buffer = raw::PacketBuffer::new(
+            vec![raw::PacketMetadata::EMPTY; 4], vec![0; 2048]
+        );
+        let raw_tx_buffer = raw::PacketBuffer::new(
+            vec![raw::PacketMetadata::EMPTY; 1], vec![0; 256]
+        );
+        let mut raw_socket = raw::Socket::new(
+            IpVersion::Ipv6, IpProtocol::Icmpv6, raw_rx_buffer, raw_tx_buffer
         );
+
+        let solicitation = router_solicitation(link_local, mac.into());
+        if raw_socket.send_slice(&solicitation).is_err() {
+            error!("Could not send router solicitation");
+            return;
+        }
+        (mac, ni.sockets.add(raw_socket))
     } else {
         error!("Network error");
+        return;
+    };
+
+    let timeout = 5.0;
+    let started = clock::realtime();
+    let mut buf = vec![0; 2048];
+    loop {
+        if clock::realtime() - started > timeout {
+            error!("Timeout reached");
+            break;
+        }
+        if console::end_of_text() || console::end_of_transmission() {
+            eprintln!();
+            break;
+        }
+
+        // The lock is released between each poll to let the kernel use the
+        // other sockets of the interface.
+        let delay = if let Some(ni) = net::NET.lock().get_mut(i) {
+            ni.poll();
+            let socket = ni.sockets.get_mut::<raw::Socket>(handle);
+            let mut is_done = false;
+            while let Ok(n) = socket.recv_slice(&mut buf) {
+                let advert = router_advert(&buf[..n]);
+                let (router, prefix, is_default) = match advert {
+                    Some(advert) => advert,
+                    None => continue,
+                };
+                if let Some(prefix) = prefix {
+                    let addr = net::ipv6_eui64_addr(prefix, mac);
+                    let cidr = IpCidr::Ipv6(Ipv6Cidr::new(addr, 64));
+                    ni.iface.update_ip_addrs(|addrs| {
+                        addrs.retain(|a| match a {
+                            IpCidr::Ipv6(a) => a.address().is_link_local(),
+                            _ => true,
+                        });
+                        if addrs.push(cidr).is_ok() {
+                            log!("NET IP6 {}", cidr);
+                        }
+                    });
+                }
+                if is_default {
+                    let routes = ni.iface.routes_mut();
+                    if routes.add_default_ipv6_route(router).is_ok() {
+                        log!("NET GW6 {}", router);
+                    }
+                }
+                is_done = true;
+                break;
+            }
+            if is_done {
+                break;
+            }
+            ni.poll_delay()
+        } else {
+            error!("Network error");
+            return;
+        };
+
+        if let Some(delay) = delay {
+            let d = (delay.total_micros() as f64) / 1000000.0;
+            syscall::sleep(d.min(0.1)); // Don't sleep longer than 0.1s
+        }
+    }
+    if let Some(ni) = net::NET.lock().get_mut(i) {
+        ni.sockets.remove(handle);
     }
 }
 
-fn monitor() {
-    if let Some((ref mut iface, ref mut device)) = *net::NET.lock() {
-        device.config().enable_debug();
+fn router_solicitation(src: Ipv6Address, mac: HardwareAddress) -> Vec<u8> {
+    let dst = Ipv6Address::LINK_LOCAL_ALL_ROUTERS;
+    let icmp_repr = Icmpv6Repr::Ndisc(NdiscRepr::RouterSolicit {
+        lladdr: Some(mac.into()),
+    });
+    let ip_repr = Ipv6Repr {
+        src_addr: src,
+        dst_addr: dst,
+        next_header: IpProtocol::Icmpv6,
+        payload_len: icmp_repr.buffer_len(),
+        hop_limit: 255,
+    };
+    let mut buf = vec![0; ip_repr.buffer_len() + icmp_repr.buffer_len()];
+    let mut ip_packet = Ipv6Packet::new_unchecked(&mut buf);
+    ip_repr.emit(&mut ip_packet);
+    let mut icmp_packet = Icmpv6Packet::new_unchecked(ip_packet.payload_mut());
+    icmp_repr.emit(
+        &src.into(),
+        &dst.into(),
+        &mut icmp_packet,
+        &ChecksumCapabilities::default()
+    );
+    buf
+}
+
+// Return the address of the router, the prefix to use for autoconfiguration,
+// and whether or not the router can be used as a default gateway.
+fn router_advert(
+    buf: &[u8]
+) -> Option<(Ipv6Address, Option<Ipv6Address>, bool)> {
+    let ip_packet = Ipv6Packet::new_checked(buf).ok()?;
+    let ip_repr = Ipv6Repr::parse(&ip_packet).ok()?;
+    let icmp_packet = Icmpv6Packet::new_checked(ip_packet.payload()).ok()?;
+    let icmp_repr = Icmpv6Repr::parse(
+        &ip_repr.src_addr.into(),
+        &ip_repr.dst_addr.into(),
+        &icmp_packet,
+        &ChecksumCapabilities::default()
+    ).ok()?;
+    if let Icmpv6Repr::Ndisc(NdiscRepr::RouterAdvert {
+        router_lifetime, prefix_info, ..
+    }) = icmp_repr {
+        let prefix = prefix_info.filter(|info|
+            info.prefix_len == 64 &&
+            info.flags.contains(NdiscPrefixInfoFlags::ADDRCONF)
+        ).map(|info| info.prefix);
+        let is_default = router_lifetime.total_millis() > 0;
+        Some((ip_repr.src_addr, prefix, is_default))
+    } else {
+        None
+    }
+}
 
-        let mtu = device.capabilities().max_transmission_unit;
-        let tcp_rx_buffer = tcp::SocketBuffer::new(vec![0; mtu]);
-        let tcp_tx_buffer = tcp::SocketBuffer::new(vec![0; mtu]);
-        let tcp_socket = tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer);
-        let mut sockets = SocketSet::new(vec![]);
-        let tcp_handle = sockets.add(tcp_socket);
+pub fn stat(i: usize) {
+    if let Some(ni) = sys::net::NET.lock().get(i) {
+        let stats = ni.device.stats();
+        print_attribute("iface", &ni.name);
+        print_attribute("rx", &format!(
+            "{} packets ({} bytes)",
+            stats.rx_packets_count(),
+            stats.rx_bytes_count()
+        ));
+        print_attribute("tx", &format!(
+            "{} packets ({} bytes)",
+            stats.tx_packets_count(),
+            stats.tx_bytes_count()
+        ));
+    } else {
+        error!("Network error");
+    }
+}
 
-        loop {
-            if console::end_of_text() || console::end_of_transmission() {
-                println!();
-                return;
+// Save the frames of an interface to a pcap file until interrupted
+fn capture(args: &[&str]) -> Result<(), ExitCode> {
+    let mut path = None;
+    let mut name = None;
+    let mut filter = pcap::Filter::default();
+    let mut i = 0;
+    let n = args.len();
+    while i < n {
+        match args[i] {
+            "-h" | "--help" => {
+                help_capture();
+                return Ok(());
+            }
+            "-p" | "--protocol" => {
+                i += 1;
+                let arg = args.get(i).unwrap_or(&"");
+                filter.protocol = net::Protocol::from(arg);
+                if filter.protocol.is_none() {
+                    error!("Could not parse protocol");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            "-P" | "--port" => {
+                i += 1;
+                filter.port = args.get(i).and_then(|s| s.parse().ok());
+                if filter.port.is_none() {
+                    error!("Could not parse port");
+                    return Err(ExitCode::UsageError);
+                }
             }
-            syscall::sleep(0.1);
+            arg if path.is_none() => path = Some(arg),
+            arg if name.is_none() => name = Some(arg),
+            _ => {
+                help_capture();
+                return Err(ExitCode::UsageError);
+            }
+        }
+        i += 1;
+    }
+    let path = match path {
+        Some(path) => path,
+        None => {
+            help_capture();
+            return Err(ExitCode::UsageError);
+        }
+    };
+    let config = match name.map_or(Some(0), net::find_interface) {
+        Some(i) => sys::net::NET.lock()[i].device.config(),
+        None => {
+            error!("Could not find interface '{}'", name.unwrap_or(""));
+            return Err(ExitCode::Failure);
+        }
+    };
+    let handle = match fs::create_file(path) {
+        Some(handle) => handle,
+        None => {
+            error!("Could not open '{}'", path);
+            return Err(ExitCode::Failure);
+        }
+    };
 
-            let ms = (clock::realtime() * 1000000.0) as i64;
-            let time = Instant::from_micros(ms);
-            iface.poll(time, device, &mut sockets);
-            let socket = sockets.get_mut::<tcp::Socket>(tcp_handle);
-            if socket.may_recv() {
-                socket.recv(|buffer| {
-                    let recvd_len = buffer.len();
-                    let data = buffer.to_owned();
-                    let pp = PrettyPrinter::<EthernetFrame<&[u8]>>::new(
-                        "", &buffer
-                    );
-                    debug!("{}", pp);
-                    (recvd_len, data)
-                }).unwrap();
+    let mut res = Ok(());
+    let mut count = 0;
+    let mut buf = pcap::header();
+    pcap::start(filter);
+    config.enable_capture();
+    loop {
+        let is_canceled = console::end_of_text()
+                       || console::end_of_transmission();
+        for record in pcap::records() {
+            buf.extend_from_slice(&record.to_bytes());
+            count += 1;
+        }
+        if !buf.is_empty() {
+            if syscall::write(handle, &buf).is_none() {
+                error!("Could not write to '{}'", path);
+                res = Err(ExitCode::Failure);
+                break;
             }
+            buf.clear();
+        }
+        if is_canceled {
+            println!();
+            break;
         }
+        syscall::sleep(0.1);
+    }
+    config.disable_capture();
+    let dropped = pcap::stop();
+    syscall::close(handle);
+    println!("{} packets captured", count);
+    if dropped > 0 {
+        println!("{} packets dropped", dropped);
+    }
+    res
+}
+
+fn monitor(i: usize) {
+    // The frames are printed by the device when its debug mode is enabled
+    if let Some(ni) = net::NET.lock().get_mut(i) {
+        ni.device.config().enable_debug();
     } else {
         error!("Network error");
+        return;
     }
+    loop {
+        if console::end_of_text() || console::end_of_transmission() {
+            println!();
+            return;
+        }
+        syscall::sleep(0.1);
+        if let Some(ni) = net::NET.lock().get_mut(i) {
+            ni.poll();
+        }
+    }
+}
+
+#[test_case]
+fn test_network() {
+    let cidr = IpCidr::from_str("10.0.2.15/24").unwrap();
+    assert_eq!(network(&cidr), IpCidr::from_str("10.0.2.0/24").unwrap());
+    let cidr = IpCidr::from_str("fe80::5054:ff:fe12:3456/64").unwrap();
+    assert_eq!(network(&cidr), IpCidr::from_str("fe80::/64").unwrap());
+    let cidr = IpCidr::from_str("2001:db8:abcd::1/36").unwrap();
+    assert_eq!(network(&cidr), IpCidr::from_str("2001:db8:a000::/36").unwrap());
 }
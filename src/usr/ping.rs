@@ -0,0 +1,271 @@
+use crate::api::clock;
+use crate::api::console::Style;
+use crate::api::fs::IO;
+use crate::api::process::ExitCode;
+use crate::api::syscall;
+use crate::sys::console;
+use crate::sys::fs::OpenFlag;
+use crate::usr;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::str::FromStr;
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::wire::{IpAddress, Icmpv4Packet, Icmpv4Repr};
+use smoltcp::wire::{Icmpv6Packet, Icmpv6Repr};
+
+// Size of the data sent with each request, starting with a timestamp
+const DATA_SIZE: usize = 56;
+
+pub fn main(args: &[&str]) -> Result<(), ExitCode> {
+    let mut count = None;
+    let mut interval = 1.0;
+    let mut host = None;
+    let mut i = 1;
+    let n = args.len();
+    while i < n {
+        match args[i] {
+            "-h" | "--help" => {
+                help();
+                return Ok(());
+            }
+            "-c" | "--count" => {
+                if i + 1 < n {
+                    i += 1;
+                    count = match args[i].parse::<usize>() {
+                        Ok(count) => Some(count),
+                        Err(_) => {
+                            error!("Could not parse count");
+                            return Err(ExitCode::UsageError);
+                        }
+                    };
+                } else {
+                    error!("Missing count");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            "-i" | "--interval" => {
+                if i + 1 < n {
+                    i += 1;
+                    interval = match args[i].parse::<f64>() {
+                        Ok(interval) => interval,
+                        Err(_) => {
+                            error!("Could not parse interval");
+                            return Err(ExitCode::UsageError);
+                        }
+                    };
+                } else {
+                    error!("Missing interval");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            arg if host.is_none() => host = Some(arg),
+            _ => {
+                help();
+                return Err(ExitCode::UsageError);
+            }
+        }
+        i += 1;
+    }
+    let host = match host {
+        Some(host) => host,
+        None => {
+            help();
+            return Err(ExitCode::UsageError);
+        }
+    };
+
+    let addr = if let Ok(addr) = IpAddress::from_str(host) {
+        addr
+    } else {
+        match usr::host::resolve(host) {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!("Could not resolve host: {:?}", e);
+                return Err(ExitCode::Failure);
+            }
+        }
+    };
+
+    let socket_path = "/dev/net/icmp";
+    let buf_len = if let Some(info) = syscall::info(socket_path) {
+        info.size() as usize
+    } else {
+        error!("Could not open '{}'", socket_path);
+        return Err(ExitCode::Failure);
+    };
+
+    let flags = OpenFlag::Device as usize;
+    let handle = match syscall::open(socket_path, flags) {
+        Some(handle) => handle,
+        None => {
+            error!("Could not open '{}'", socket_path);
+            return Err(ExitCode::Failure);
+        }
+    };
+    if syscall::connect(handle, addr, 0).is_err() {
+        error!("Could not connect to {}", addr);
+        syscall::close(handle);
+        return Err(ExitCode::Failure);
+    }
+
+    println!("PING {} ({}): {} data bytes", host, addr, DATA_SIZE);
+    let timeout = interval.max(1.0);
+    let mut rtts = Vec::new();
+    let mut sent = 0;
+    let mut seq_no = 0;
+    'requests: while count.map_or(true, |count| seq_no < count) {
+        seq_no += 1;
+        let started = clock::uptime();
+        let request = echo_request(addr, seq_no as u16);
+        if syscall::write(handle, &request).is_none() {
+            error!("Could not send request to {}", addr);
+            break;
+        }
+        sent += 1;
+
+        loop {
+            if is_canceled() {
+                println!();
+                break 'requests;
+            }
+            if syscall::poll(&[(handle, IO::Read)]).is_some() {
+                let mut buf = vec![0; buf_len];
+                if let Some(n) = syscall::read(handle, &mut buf) {
+                    let reply = echo_reply(addr, &buf[0..n], seq_no as u16);
+                    if let Some(time) = reply {
+                        let rtt = (clock::uptime() - time) * 1000.0;
+                        println!(
+                            "{} bytes from {}: icmp_seq={} time={:.3} ms",
+                            n, addr, seq_no, rtt
+                        );
+                        rtts.push(rtt);
+                        break;
+                    }
+                }
+            }
+            if clock::uptime() - started > timeout {
+                println!("Request timeout for icmp_seq={}", seq_no);
+                break;
+            }
+            syscall::sleep(0.001);
+        }
+
+        if count.map_or(false, |count| seq_no >= count) {
+            break;
+        }
+        while clock::uptime() - started < interval {
+            if is_canceled() {
+                println!();
+                break 'requests;
+            }
+            syscall::sleep(0.01);
+        }
+    }
+    syscall::close(handle);
+
+    let received = rtts.len();
+    let loss = if sent > 0 {
+        100.0 * ((sent - received) as f64) / (sent as f64)
+    } else {
+        0.0
+    };
+    println!();
+    println!("--- {} ping statistics ---", host);
+    println!(
+        "{} packets transmitted, {} packets received, {:.1}% packet loss",
+        sent, received, loss
+    );
+    if received > 0 {
+        let min = rtts.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = rtts.iter().cloned().fold(0.0, f64::max);
+        let avg = rtts.iter().sum::<f64>() / (received as f64);
+        println!(
+            "round-trip min/avg/max = {:.3}/{:.3}/{:.3} ms",
+            min, avg, max
+        );
+        Ok(())
+    } else {
+        Err(ExitCode::Failure)
+    }
+}
+
+fn is_canceled() -> bool {
+    console::end_of_text() || console::end_of_transmission()
+}
+
+// The identifier and the checksum will be set by the kernel
+fn echo_request(addr: IpAddress, seq_no: u16) -> Vec<u8> {
+    let mut data = vec![0; DATA_SIZE];
+    data[0..8].copy_from_slice(&clock::uptime().to_be_bytes());
+    for (i, b) in data.iter_mut().enumerate().skip(8) {
+        *b = i as u8;
+    }
+    let ident = 0;
+    let caps = ChecksumCapabilities::ignored();
+    match addr {
+        IpAddress::Ipv4(_) => {
+            let repr = Icmpv4Repr::EchoRequest { ident, seq_no, data: &data };
+            let mut buf = vec![0; repr.buffer_len()];
+            repr.emit(&mut Icmpv4Packet::new_unchecked(&mut buf), &caps);
+            buf
+        }
+        IpAddress::Ipv6(_) => {
+            let repr = Icmpv6Repr::EchoRequest { ident, seq_no, data: &data };
+            let mut buf = vec![0; repr.buffer_len()];
+            let mut packet = Icmpv6Packet::new_unchecked(&mut buf);
+            repr.emit(&addr, &addr, &mut packet, &caps);
+            buf
+        }
+    }
+}
+
+// Return the time at which the request was sent if the reply is valid
+fn echo_reply(addr: IpAddress, buf: &[u8], expected: u16) -> Option<f64> {
+    // The checksum has already been verified by the kernel
+    let caps = ChecksumCapabilities::ignored();
+    let (seq_no, data) = match addr {
+        IpAddress::Ipv4(_) => {
+            let packet = Icmpv4Packet::new_checked(buf).ok()?;
+            match Icmpv4Repr::parse(&packet, &caps).ok()? {
+                Icmpv4Repr::EchoReply { seq_no, data, .. } => (seq_no, data),
+                _ => return None,
+            }
+        }
+        IpAddress::Ipv6(_) => {
+            let packet = Icmpv6Packet::new_checked(buf).ok()?;
+            match Icmpv6Repr::parse(&addr, &addr, &packet, &caps).ok()? {
+                Icmpv6Repr::EchoReply { seq_no, data, .. } => (seq_no, data),
+                _ => return None,
+            }
+        }
+    };
+    if seq_no == expected && data.len() >= 8 {
+        Some(f64::from_be_bytes(data[0..8].try_into().ok()?))
+    } else {
+        None
+    }
+}
+
+fn help() {
+    let csi_option = Style::color("aqua");
+    let csi_title = Style::color("yellow");
+    let csi_reset = Style::reset();
+    println!(
+        "{}Usage:{} ping {}<options> <host>{1}",
+        csi_title, csi_reset, csi_option
+    );
+    println!();
+    println!("{}Options:{}", csi_title, csi_reset);
+    println!(
+        "  {0}-c{1}, {0}--count <n>{1}       \
+        Stop after sending {0}<n>{1} requests",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}-i{1}, {0}--interval <s>{1}    \
+        Wait {0}<s>{1} seconds between requests",
+        csi_option, csi_reset
+    );
+}
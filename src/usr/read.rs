@@ -56,7 +56,8 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
                     }
                 }
                 "daytime" => {
-                    if host.contains(':') {
+                    // IPv6 addresses are enclosed in brackets: `[::1]:13`
+                    if host.contains(':') && !host.ends_with(']') {
                         usr::tcp::main(&["tcp", host])
                     } else {
                         usr::tcp::main(&["tcp", &format!("{}:13", host)])
@@ -14,12 +14,13 @@ use alloc::vec::Vec;
 use core::sync::atomic::{fence, Ordering};
 
 // TODO: Scan /bin
-const AUTOCOMPLETE_COMMANDS: [&str; 40] = [
+const AUTOCOMPLETE_COMMANDS: [&str; 43] = [
     "2048", "calc", "chess", "copy", "date", "decode", "delete", "dhcp",
     "diff", "disk", "edit", "elf", "encode", "env", "goto", "hash", "help",
     "hex", "host", "http", "httpd", "install", "keyboard", "life", "lisp",
-    "list", "memory", "move", "net", "pci", "quit", "read", "shell", "socket",
-    "tcp", "time", "user", "vga", "view", "write",
+    "list", "memory", "move", "net", "pci", "ping", "quit", "read", "shell",
+    "shelld", "socket", "tcp", "tftp", "time", "user", "vga", "view",
+    "write",
 ];
 
 struct Config {
@@ -554,12 +555,15 @@ fn dispatch(args: &[&str], config: &mut Config) -> Result<(), ExitCode> {
         "net"      => usr::net::main(args),
         "pci"      => usr::pci::main(args),
         "pi"       => usr::pi::main(args),
+        "ping"     => usr::ping::main(args),
         "quit"     => Err(ExitCode::ShellExit),
         "read"     => usr::read::main(args),
         "set"      => cmd_set(args, config),
         "shell"    => usr::shell::main(args),
+        "shelld"   => usr::shelld::main(args),
         "socket"   => usr::socket::main(args),
         "tcp"      => usr::tcp::main(args),
+        "tftp"     => usr::tftp::main(args),
         "time"     => usr::time::main(args),
         "unalias"  => cmd_unalias(args, config),
         "unset"    => cmd_unset(args, config),
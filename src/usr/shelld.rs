@@ -0,0 +1,198 @@
+use crate::api::clock::DATE_TIME_ZONE;
+use crate::api::console::Style;
+use crate::api::fs;
+use crate::api::process::ExitCode;
+use crate::api::syscall;
+use crate::api::time;
+use crate::sys;
+use crate::sys::console;
+use crate::sys::fs::OpenFlag;
+use crate::sys::net::socket::{SocketError, SocketOption};
+use crate::usr;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use smoltcp::wire::IpAddress;
+
+const MAX_ATTEMPTS: usize = 3;
+const LOGIN_TIMEOUT: f64 = 60.0;
+
+pub fn main(args: &[&str]) -> Result<(), ExitCode> {
+    let mut port = 2323;
+    let mut i = 1;
+    let n = args.len();
+    while i < n {
+        match args[i] {
+            "-h" | "--help" => {
+                usage();
+                return Ok(());
+            }
+            "-p" | "--port" => {
+                if i + 1 < n {
+                    i += 1;
+                    port = args[i].parse().unwrap_or(port);
+                } else {
+                    error!("Missing port number");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            _ => {
+                error!("Invalid option '{}'", args[i]);
+                return Err(ExitCode::UsageError);
+            }
+        }
+        i += 1;
+    }
+
+    if !fs::exists(usr::user::USERS) {
+        error!("Could not read '{}'", usr::user::USERS);
+        return Err(ExitCode::Failure);
+    }
+
+    let csi_color = Style::color("yellow");
+    let csi_reset = Style::reset();
+    println!(
+        "{}Shell server listening on 0.0.0.0:{}{}",
+        csi_color, port, csi_reset
+    );
+    loop {
+        let flags = OpenFlag::Device as usize;
+        let handle = match syscall::open("/dev/net/tcp", flags) {
+            Some(handle) => handle,
+            None => {
+                error!("Could not open socket");
+                return Err(ExitCode::Failure);
+            }
+        };
+        if syscall::listen(handle, port).is_err() {
+            error!("Could not listen to 0.0.0.0:{}", port);
+            syscall::close(handle);
+            return Err(ExitCode::Failure);
+        }
+
+        // Wake up every second to check if the server has been interrupted
+        syscall::sockopt(handle, SocketOption::ReadTimeout, 1.0).ok();
+        let addr = loop {
+            if console::end_of_text() || console::end_of_transmission() {
+                println!();
+                syscall::close(handle);
+                return Ok(());
+            }
+            match syscall::accept(handle) {
+                Ok(addr) => break Some(addr),
+                Err(SocketError::Timeout) => continue,
+                Err(_) => break None,
+            }
+        };
+        if let Some(addr) = addr {
+            session(handle, addr);
+        }
+        syscall::close(handle);
+    }
+}
+
+fn session(handle: usize, addr: IpAddress) {
+    syscall::sockopt(handle, SocketOption::Terminal, 1.0).ok();
+    syscall::sockopt(handle, SocketOption::ReadTimeout, LOGIN_TIMEOUT).ok();
+    let user = match login(handle) {
+        Some(user) => user,
+        None => {
+            log(addr, "login failed");
+            return;
+        }
+    };
+    log(addr, &format!("login {}", user));
+
+    // A session will last until the user quits the shell or the connection
+    // is closed by the client
+    syscall::sockopt(handle, SocketOption::ReadTimeout, 0.0).ok();
+
+    let prev_user = sys::process::user();
+    let prev_dir = sys::process::dir();
+    let prev_home = sys::process::env("HOME");
+    let home = format!("/usr/{}", user);
+    sys::process::set_user(&user);
+    sys::process::set_dir(&home);
+    sys::process::set_env("USER", &user);
+    sys::process::set_env("HOME", &home);
+
+    let is_redirected = (0..3).all(|i| syscall::dup(handle, i).is_some());
+    if is_redirected {
+        usr::shell::main(&["shell"]).ok();
+    }
+    for i in 0..3 {
+        fs::reopen("/dev/console", i, false).ok();
+    }
+
+    if let Some(user) = prev_user {
+        sys::process::set_user(&user);
+        sys::process::set_env("USER", &user);
+    }
+    if let Some(home) = prev_home {
+        sys::process::set_env("HOME", &home);
+    }
+    sys::process::set_dir(&prev_dir);
+    log(addr, &format!("logout {}", user));
+}
+
+// Authenticate a user with the passwords of the system like the console
+// login, and return its name
+fn login(handle: usize) -> Option<String> {
+    for _ in 0..MAX_ATTEMPTS {
+        write(handle, "\nUsername: ")?;
+        let username = read_line(handle)?;
+        write(handle, "Password: \x1b[12l")?; // Disable echo
+        let password = read_line(handle)?;
+        write(handle, "\x1b[12h\n")?; // Enable echo
+        if let Some(hash) = usr::user::hashed_password(&username) {
+            if usr::user::check(&password, &hash).is_ok() {
+                return Some(username);
+            }
+        }
+        syscall::sleep(1.0);
+        write(handle, "Login incorrect\n")?;
+    }
+    None
+}
+
+fn read_line(handle: usize) -> Option<String> {
+    let mut buf = vec![0; 256];
+    let n = syscall::read(handle, &mut buf)?;
+    if n == 0 {
+        return None; // Connection closed
+    }
+    buf.truncate(n);
+    let line = String::from_utf8_lossy(&buf);
+    if line.contains(console::ETX_KEY) || line.contains(console::EOT_KEY) {
+        return None;
+    }
+    Some(line.trim().to_string())
+}
+
+fn write(handle: usize, s: &str) -> Option<()> {
+    syscall::write(handle, s.as_bytes()).map(|_| ())
+}
+
+fn log(addr: IpAddress, msg: &str) {
+    let csi_color = Style::color("yellow");
+    let csi_reset = Style::reset();
+    let time = time::now().format(DATE_TIME_ZONE);
+    println!("{}[{}]{} {} {}", csi_color, time, csi_reset, addr, msg);
+}
+
+fn usage() {
+    let csi_option = Style::color("aqua");
+    let csi_title = Style::color("yellow");
+    let csi_reset = Style::reset();
+    println!(
+        "{}Usage:{} shelld {}<options>{1}",
+        csi_title, csi_reset, csi_option
+    );
+    println!();
+    println!("{}Options:{}", csi_title, csi_reset);
+    println!(
+        "  {0}-p{1}, {0}--port <number>{1}    Listen to port {0}<number>{1}",
+        csi_option, csi_reset
+    );
+}
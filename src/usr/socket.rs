@@ -43,9 +43,9 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
         help();
         return Err(ExitCode::UsageError);
     }
-    let (host, port) = match args[1].split_once(':') {
-        Some((h, p)) => (h, p),
-        None => ("0.0.0.0", args[1]),
+    let (host, port) = match usr::host::split_port(args[1]) {
+        (host, Some(port)) => (host, port),
+        (port, None) => ("0.0.0.0", port),
     };
     let port: u16 = match port.parse() {
         Ok(n) => n,
@@ -54,8 +54,8 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
             return Err(ExitCode::UsageError);
         }
     };
-    let addr = if host.ends_with(char::is_numeric) {
-        IpAddress::from_str(host).expect("invalid address format")
+    let addr = if let Ok(addr) = IpAddress::from_str(host) {
+        addr
     } else {
         match usr::host::resolve(host) {
             Ok(ip_addr) => ip_addr,
@@ -27,9 +27,9 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
         return Err(ExitCode::UsageError);
     }
 
-    let (host, port) = match args[1].split_once(':') {
-        Some((h, p)) => (h, p),
-        None => {
+    let (host, port) = match usr::host::split_port(args[1]) {
+        (host, Some(port)) => (host, port),
+        (_, None) => {
             help();
             return Err(ExitCode::UsageError);
         }
@@ -41,8 +41,8 @@ pub fn main(args: &[&str]) -> Result<(), ExitCode> {
             return Err(ExitCode::UsageError);
         }
     };
-    let addr = if host.ends_with(char::is_numeric) {
-        IpAddress::from_str(host).expect("invalid address format")
+    let addr = if let Ok(addr) = IpAddress::from_str(host) {
+        addr
     } else {
         match usr::host::resolve(host) {
             Ok(ip_addr) => ip_addr,
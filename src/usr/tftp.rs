@@ -0,0 +1,692 @@
+use crate::api::console::Style;
+use crate::api::fs;
+use crate::api::process::ExitCode;
+use crate::api::syscall;
+use crate::sys::console;
+use crate::sys::fs::OpenFlag;
+use crate::sys::net::socket::{SocketError, SocketOption};
+use crate::usr;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::str::FromStr;
+use smoltcp::wire::{IpAddress, IpEndpoint};
+
+const PORT: u16 = 69;
+const BLKSIZE: usize = 512;
+const MIN_BLKSIZE: usize = 8;
+const MAX_BLKSIZE: usize = 65464;
+const TIMEOUT: f64 = 1.0;
+const MAX_RETRIES: usize = 5;
+
+const RRQ: u16 = 1;
+const WRQ: u16 = 2;
+const DATA: u16 = 3;
+const ACK: u16 = 4;
+const ERROR: u16 = 5;
+const OACK: u16 = 6;
+
+// Error codes
+const NOT_DEFINED: u16 = 0;
+const FILE_NOT_FOUND: u16 = 1;
+const ACCESS_VIOLATION: u16 = 2;
+const ILLEGAL_OPERATION: u16 = 4;
+const UNKNOWN_TID: u16 = 5;
+const FILE_EXISTS: u16 = 6;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Packet {
+    Read { file: String, blksize: Option<usize> },
+    Write { file: String, blksize: Option<usize> },
+    Data { block: u16, data: Vec<u8> },
+    Ack { block: u16 },
+    Error { code: u16, msg: String },
+    OptionAck { blksize: Option<usize> },
+}
+
+impl Packet {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 2 {
+            return None;
+        }
+        let opcode = u16::from_be_bytes([buf[0], buf[1]]);
+        let fields = || buf[2..].split(|&b| b == 0).map(|field|
+            String::from_utf8_lossy(field).to_string()
+        ).collect::<Vec<_>>();
+        match opcode {
+            RRQ | WRQ => {
+                let fields = fields();
+                let file = fields.first()?.to_string();
+                let blksize = parse_blksize(fields.get(2..)?);
+                if opcode == RRQ {
+                    Some(Packet::Read { file, blksize })
+                } else {
+                    Some(Packet::Write { file, blksize })
+                }
+            }
+            DATA => {
+                let block = u16::from_be_bytes([*buf.get(2)?, *buf.get(3)?]);
+                let data = buf[4..].to_vec();
+                Some(Packet::Data { block, data })
+            }
+            ACK => {
+                let block = u16::from_be_bytes([*buf.get(2)?, *buf.get(3)?]);
+                Some(Packet::Ack { block })
+            }
+            ERROR => {
+                let code = u16::from_be_bytes([*buf.get(2)?, *buf.get(3)?]);
+                let msg = buf[4..].split(|&b| b == 0).next().unwrap_or(&[]);
+                let msg = String::from_utf8_lossy(msg).to_string();
+                Some(Packet::Error { code, msg })
+            }
+            OACK => {
+                let blksize = parse_blksize(&fields());
+                Some(Packet::OptionAck { blksize })
+            }
+            _ => None,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Packet::Read { file, blksize } => {
+                buf.extend_from_slice(&RRQ.to_be_bytes());
+                push_request(&mut buf, file, *blksize);
+            }
+            Packet::Write { file, blksize } => {
+                buf.extend_from_slice(&WRQ.to_be_bytes());
+                push_request(&mut buf, file, *blksize);
+            }
+            Packet::Data { block, data } => {
+                buf.extend_from_slice(&DATA.to_be_bytes());
+                buf.extend_from_slice(&block.to_be_bytes());
+                buf.extend_from_slice(data);
+            }
+            Packet::Ack { block } => {
+                buf.extend_from_slice(&ACK.to_be_bytes());
+                buf.extend_from_slice(&block.to_be_bytes());
+            }
+            Packet::Error { code, msg } => {
+                buf.extend_from_slice(&ERROR.to_be_bytes());
+                buf.extend_from_slice(&code.to_be_bytes());
+                buf.extend_from_slice(msg.as_bytes());
+                buf.push(0);
+            }
+            Packet::OptionAck { blksize } => {
+                buf.extend_from_slice(&OACK.to_be_bytes());
+                push_blksize(&mut buf, *blksize);
+            }
+        }
+        buf
+    }
+}
+
+fn push_request(buf: &mut Vec<u8>, file: &str, blksize: Option<usize>) {
+    buf.extend_from_slice(file.as_bytes());
+    buf.push(0);
+    buf.extend_from_slice(b"octet\0");
+    push_blksize(buf, blksize);
+}
+
+fn push_blksize(buf: &mut Vec<u8>, blksize: Option<usize>) {
+    if let Some(n) = blksize {
+        buf.extend_from_slice(format!("blksize\0{}\0", n).as_bytes());
+    }
+}
+
+// The options of a request are pairs of null-terminated names and values,
+// and the block size is the only one supported (RFC 2348)
+fn parse_blksize(fields: &[String]) -> Option<usize> {
+    fields.chunks(2).find_map(|option| match option {
+        [name, value] if name.eq_ignore_ascii_case("blksize") => {
+            value.parse().ok().filter(is_valid_blksize)
+        }
+        _ => None,
+    })
+}
+
+fn is_valid_blksize(n: &usize) -> bool {
+    (MIN_BLKSIZE..=MAX_BLKSIZE).contains(n)
+}
+
+#[derive(Debug)]
+enum Error {
+    Timeout,
+    Interrupted,
+    Socket,
+    Protocol,
+    Message(String),
+}
+
+// The peer of a transfer is identified by its address and its port (TID),
+// known after receiving its first packet.
+struct Socket {
+    handle: usize,
+    buf: Vec<u8>,
+    peer: Option<IpEndpoint>,
+}
+
+impl Socket {
+    fn new() -> Option<Self> {
+        let path = "/dev/net/udp";
+        let len = syscall::info(path)?.size() as usize;
+        if len < MIN_BLKSIZE + 4 {
+            return None; // The network is not up
+        }
+        let handle = syscall::open(path, OpenFlag::Device as usize)?;
+        syscall::sockopt(handle, SocketOption::ReadTimeout, TIMEOUT).ok();
+        let buf = vec![0; len];
+        let peer = None;
+        Some(Self { handle, buf, peer })
+    }
+
+    fn connect(endpoint: IpEndpoint) -> Result<Self, Error> {
+        let socket = Self::new().ok_or(Error::Socket)?;
+        let (addr, port) = (endpoint.addr, endpoint.port);
+        if syscall::connect(socket.handle, addr, port).is_err() {
+            return Err(Error::Socket);
+        }
+        Ok(socket)
+    }
+
+    // Largest block size fitting in a datagram
+    fn max_blksize(&self) -> usize {
+        self.buf.len() - 4
+    }
+
+    fn send(&self, packet: &Packet) -> Result<(), Error> {
+        match syscall::send(self.handle, &packet.to_bytes()) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::Socket),
+        }
+    }
+
+    // Receive the next datagram from any sender, which becomes the remote
+    // endpoint of the socket, with its packet if it can be parsed
+    fn recv_from(&mut self) -> Result<(IpEndpoint, Option<Packet>), Error> {
+        if console::end_of_text() || console::end_of_transmission() {
+            return Err(Error::Interrupted);
+        }
+        let res = syscall::accept_endpoint(self.handle).and_then(|endpoint|
+            syscall::recv(self.handle, &mut self.buf).map(|n| (endpoint, n))
+        );
+        match res {
+            Ok((endpoint, n)) => {
+                Ok((endpoint, Packet::parse(&self.buf[0..n])))
+            }
+            Err(SocketError::Timeout) => Err(Error::Timeout),
+            Err(_) => Err(Error::Socket),
+        }
+    }
+
+    // Receive the next packet from the peer, answering the datagrams sent
+    // from another port with an error as they belong to another transfer
+    fn recv(&mut self) -> Result<Packet, Error> {
+        loop {
+            let (endpoint, packet) = self.recv_from()?;
+            match self.peer {
+                Some(peer) if peer != endpoint => {
+                    let code = UNKNOWN_TID;
+                    let msg = "Unknown transfer ID".to_string();
+                    self.send(&Packet::Error { code, msg }).ok();
+                    let (addr, port) = (peer.addr, peer.port);
+                    if syscall::connect(self.handle, addr, port).is_err() {
+                        return Err(Error::Socket);
+                    }
+                }
+                _ => return packet.ok_or(Error::Protocol),
+            }
+        }
+    }
+
+    // Wait for the first packet of the peer, resending the last packet when
+    // nothing has been received in time
+    fn accept(&mut self, last: Option<&Packet>) -> Result<Packet, Error> {
+        for _ in 0..MAX_RETRIES {
+            match self.recv_from() {
+                Ok((endpoint, packet)) => {
+                    self.peer = Some(endpoint);
+                    return packet.ok_or(Error::Protocol);
+                }
+                Err(Error::Timeout) => {
+                    if let Some(packet) = last {
+                        self.send(packet)?;
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    // Send a packet until the expected reply is received
+    fn exchange(
+        &mut self,
+        packet: &Packet,
+        is_reply: impl Fn(&Packet) -> bool
+    ) -> Result<Packet, Error> {
+        self.send(packet)?;
+        let mut retries = 0;
+        loop {
+            match self.recv() {
+                Ok(Packet::Error { msg, .. }) => {
+                    return Err(Error::Message(msg));
+                }
+                Ok(reply) if is_reply(&reply) => return Ok(reply),
+                Ok(_) | Err(Error::Protocol) => {} // Ignore unexpected packets
+                Err(Error::Timeout) if retries < MAX_RETRIES => {
+                    retries += 1;
+                    self.send(packet)?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn send_file(&mut self, buf: &[u8], blksize: usize) -> Result<(), Error> {
+        let mut block: u16 = 1;
+        let mut chunks = buf.chunks(blksize);
+        loop {
+            let data = chunks.next().unwrap_or(&[]).to_vec();
+            let is_last = data.len() < blksize;
+            let packet = Packet::Data { block, data };
+            self.exchange(&packet, |reply| {
+                *reply == Packet::Ack { block }
+            })?;
+            if is_last {
+                return Ok(());
+            }
+            block = block.wrapping_add(1);
+        }
+    }
+
+    // Receive a file starting with the given packet or by acknowledging
+    // the block before the first one
+    fn recv_file(
+        &mut self,
+        first: Option<Packet>,
+        blksize: usize
+    ) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        let mut block: u16 = 1;
+        let mut packet = match first {
+            Some(packet) => packet,
+            None => self.exchange(&Packet::Ack { block: 0 }, |reply| {
+                matches!(reply, Packet::Data { block: 1, .. })
+            })?,
+        };
+        loop {
+            let n = match packet {
+                Packet::Data { data, .. } => {
+                    buf.extend_from_slice(&data);
+                    data.len()
+                }
+                _ => return Err(Error::Protocol),
+            };
+            let ack = Packet::Ack { block };
+            if n < blksize {
+                self.send(&ack)?;
+                return Ok(buf);
+            }
+            block = block.wrapping_add(1);
+            packet = self.exchange(&ack, |reply| {
+                matches!(reply, Packet::Data { block: b, .. } if *b == block)
+            })?;
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        syscall::close(self.handle);
+    }
+}
+
+pub fn main(args: &[&str]) -> Result<(), ExitCode> {
+    let mut port = None;
+    let mut blksize = None;
+    let mut params = Vec::new();
+    let mut i = 1;
+    let n = args.len();
+    while i < n {
+        match args[i] {
+            "-h" | "--help" => {
+                help();
+                return Ok(());
+            }
+            "-p" | "--port" => {
+                if i + 1 < n {
+                    i += 1;
+                    port = args[i].parse().ok();
+                } else {
+                    error!("Missing port number");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            "-b" | "--blksize" => {
+                if i + 1 < n {
+                    i += 1;
+                    blksize = args[i].parse().ok().filter(is_valid_blksize);
+                    if blksize.is_none() {
+                        error!("Invalid block size '{}'", args[i]);
+                        return Err(ExitCode::UsageError);
+                    }
+                } else {
+                    error!("Missing block size");
+                    return Err(ExitCode::UsageError);
+                }
+            }
+            arg if arg.starts_with('-') => {
+                error!("Invalid option '{}'", arg);
+                return Err(ExitCode::UsageError);
+            }
+            arg => {
+                params.push(arg);
+            }
+        }
+        i += 1;
+    }
+
+    let res = match params.as_slice() {
+        ["get", host, remote] => {
+            let local = remote.rsplit('/').next().unwrap_or(remote);
+            get(host, port, remote, local, blksize)
+        }
+        ["get", host, remote, local] => get(host, port, remote, local, blksize),
+        ["put", host, local] => {
+            let remote = local.rsplit('/').next().unwrap_or(local);
+            put(host, port, local, remote, blksize)
+        }
+        ["put", host, local, remote] => put(host, port, local, remote, blksize),
+        ["serve", dir] => serve(dir, port.unwrap_or(PORT)),
+        _ => {
+            help();
+            return Err(ExitCode::UsageError);
+        }
+    };
+    res.map_err(|err| {
+        match err {
+            Error::Timeout => error!("Timed out"),
+            Error::Interrupted => eprintln!(),
+            Error::Socket => error!("Could not use socket"),
+            Error::Protocol => error!("Invalid packet received"),
+            Error::Message(msg) => error!("{}", msg),
+        }
+        ExitCode::Failure
+    })
+}
+
+fn connect(host: &str, port: Option<u16>) -> Result<Socket, Error> {
+    let (host, port) = match usr::host::split_port(host) {
+        (host, Some(port)) => (host, port.parse().ok()),
+        (host, None) => (host, port),
+    };
+    let addr = match IpAddress::from_str(host) {
+        Ok(addr) => addr,
+        Err(_) => match usr::host::resolve(host) {
+            Ok(addr) => addr,
+            Err(e) => {
+                let msg = format!("Could not resolve host: {:?}", e);
+                return Err(Error::Message(msg));
+            }
+        },
+    };
+    Socket::connect(IpEndpoint::new(addr, port.unwrap_or(PORT)))
+}
+
+fn get(
+    host: &str,
+    port: Option<u16>,
+    remote: &str,
+    local: &str,
+    blksize: Option<usize>
+) -> Result<(), Error> {
+    let mut socket = connect(host, port)?;
+    let max_blksize = socket.max_blksize();
+    let blksize = blksize.map(|n| n.min(max_blksize));
+    let req = Packet::Read { file: remote.to_string(), blksize };
+    socket.send(&req)?;
+
+    // The server replies from a new port
+    let buf = match socket.accept(Some(&req))? {
+        Packet::OptionAck { blksize: Some(n) } if n <= max_blksize => {
+            socket.recv_file(None, n)?
+        }
+        Packet::OptionAck { .. } => {
+            let code = NOT_DEFINED;
+            let msg = "Invalid block size".to_string();
+            socket.send(&Packet::Error { code, msg }).ok();
+            return Err(Error::Protocol);
+        }
+        packet @ Packet::Data { block: 1, .. } => {
+            socket.recv_file(Some(packet), BLKSIZE)?
+        }
+        Packet::Error { msg, .. } => return Err(Error::Message(msg)),
+        _ => return Err(Error::Protocol),
+    };
+    if fs::write(local, &buf).is_err() {
+        return Err(Error::Message(format!("Could not write '{}'", local)));
+    }
+    Ok(())
+}
+
+fn put(
+    host: &str,
+    port: Option<u16>,
+    local: &str,
+    remote: &str,
+    blksize: Option<usize>
+) -> Result<(), Error> {
+    let buf = match fs::read_to_bytes(local) {
+        Ok(buf) => buf,
+        Err(_) => {
+            return Err(Error::Message(format!("Could not read '{}'", local)));
+        }
+    };
+    let mut socket = connect(host, port)?;
+    let max_blksize = socket.max_blksize();
+    let blksize = blksize.map(|n| n.min(max_blksize));
+    let file = remote.to_string();
+    let req = Packet::Write { file, blksize };
+    socket.send(&req)?;
+
+    // The server replies from a new port
+    let blksize = match socket.accept(Some(&req))? {
+        Packet::OptionAck { blksize: Some(n) } if n <= max_blksize => n,
+        Packet::Ack { block: 0 } => BLKSIZE,
+        Packet::Error { msg, .. } => return Err(Error::Message(msg)),
+        _ => return Err(Error::Protocol),
+    };
+    socket.send_file(&buf, blksize)
+}
+
+fn serve(dir: &str, port: u16) -> Result<(), Error> {
+    let csi_color = Style::color("yellow");
+    let csi_reset = Style::reset();
+    let mut socket = Socket::new().ok_or(Error::Socket)?;
+    if syscall::listen(socket.handle, port).is_err() {
+        return Err(Error::Socket);
+    }
+    println!(
+        "{}TFTP server listening on 0.0.0.0:{}{}",
+        csi_color, port, csi_reset
+    );
+    loop {
+        let (endpoint, req) = match socket.recv_from() {
+            Ok((endpoint, Some(req))) => (endpoint, req),
+            Ok((_, None)) | Err(Error::Timeout) => continue,
+            Err(Error::Interrupted) => {
+                println!();
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+        let (verb, file) = match &req {
+            Packet::Read { file, .. } => ("GET", file.clone()),
+            Packet::Write { file, .. } => ("PUT", file.clone()),
+            _ => continue, // Ignore packets that are not requests
+        };
+
+        // Each transfer has its own socket with a new port (TID)
+        let res = Socket::connect(endpoint).and_then(|mut socket| {
+            socket.peer = Some(endpoint);
+            transfer(&mut socket, dir, req)
+        });
+        let addr = endpoint.addr;
+        let res = match res {
+            Ok(()) => "OK".to_string(),
+            Err(Error::Interrupted) => return Ok(()),
+            Err(Error::Message(msg)) => msg,
+            Err(err) => format!("{:?}", err),
+        };
+        println!(
+            "{}{}{} {} {} {}", csi_color, addr, csi_reset, verb, file, res
+        );
+    }
+}
+
+fn transfer(socket: &mut Socket, dir: &str, req: Packet) -> Result<(), Error> {
+    match req {
+        Packet::Read { file, blksize } => {
+            let path = match join_path(dir, &file) {
+                Some(path) => path,
+                None => {
+                    return reject(socket, ACCESS_VIOLATION, "Access violation");
+                }
+            };
+            let buf = match fs::read_to_bytes(&path) {
+                Ok(buf) => buf,
+                Err(_) => {
+                    return reject(socket, FILE_NOT_FOUND, "File not found");
+                }
+            };
+            let blksize = match blksize {
+                Some(n) => {
+                    let n = n.min(socket.max_blksize());
+                    let oack = Packet::OptionAck { blksize: Some(n) };
+                    socket.exchange(&oack, |reply| {
+                        *reply == Packet::Ack { block: 0 }
+                    })?;
+                    n
+                }
+                None => BLKSIZE,
+            };
+            socket.send_file(&buf, blksize)
+        }
+        Packet::Write { file, blksize } => {
+            let path = match join_path(dir, &file) {
+                Some(path) => path,
+                None => {
+                    return reject(socket, ACCESS_VIOLATION, "Access violation");
+                }
+            };
+            if fs::exists(&path) {
+                return reject(socket, FILE_EXISTS, "File already exists");
+            }
+            let (reply, blksize) = match blksize {
+                Some(n) => {
+                    let n = n.min(socket.max_blksize());
+                    (Packet::OptionAck { blksize: Some(n) }, n)
+                }
+                None => (Packet::Ack { block: 0 }, BLKSIZE),
+            };
+            let first = socket.exchange(&reply, |packet| {
+                matches!(packet, Packet::Data { block: 1, .. })
+            })?;
+            let buf = socket.recv_file(Some(first), blksize)?;
+            if fs::write(&path, &buf).is_err() {
+                return reject(socket, ACCESS_VIOLATION, "Could not write file");
+            }
+            Ok(())
+        }
+        _ => reject(socket, ILLEGAL_OPERATION, "Illegal TFTP operation"),
+    }
+}
+
+fn reject(socket: &mut Socket, code: u16, msg: &str) -> Result<(), Error> {
+    let msg = msg.to_string();
+    socket.send(&Packet::Error { code, msg: msg.clone() })?;
+    Err(Error::Message(msg))
+}
+
+// Return the path of a file inside the served directory
+fn join_path(dir: &str, file: &str) -> Option<String> {
+    if file.split('/').any(|part| part == "..") {
+        return None;
+    }
+    let dir = dir.trim_end_matches('/');
+    let file = file.trim_start_matches('/');
+    Some(format!("{}/{}", dir, file))
+}
+
+fn help() {
+    let csi_option = Style::color("aqua");
+    let csi_title = Style::color("yellow");
+    let csi_reset = Style::reset();
+    println!(
+        "{}Usage:{} tftp {}<command> <options>{1}",
+        csi_title, csi_reset, csi_option
+    );
+    println!();
+    println!("{}Commands:{}", csi_title, csi_reset);
+    println!(
+        "  {0}get <host> <remote> [<local>]{1}   Download a file",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}put <host> <local> [<remote>]{1}   Upload a file",
+        csi_option, csi_reset
+    );
+    println!(
+        "  {0}serve <dir>{1}                     Serve files of {0}<dir>{1}",
+        csi_option, csi_reset
+    );
+    println!();
+    println!("{}Options:{}", csi_title, csi_reset);
+    println!(
+        "  {0}-p{1}, {0}--port <number>{1}       Use port {0}<number>{1} \
+        (default: {2})",
+        csi_option, csi_reset, PORT
+    );
+    println!(
+        "  {0}-b{1}, {0}--blksize <number>{1}    Negotiate block size \
+        (default: {2})",
+        csi_option, csi_reset, BLKSIZE
+    );
+}
+
+#[test_case]
+fn test_packet() {
+    let req = Packet::Read { file: "boot.sh".to_string(), blksize: Some(1024) };
+    let buf = req.to_bytes();
+    let res = b"\x00\x01boot.sh\x00octet\x00blksize\x001024\x00";
+    assert_eq!(buf, res.to_vec());
+    assert_eq!(Packet::parse(&buf), Some(req));
+
+    let buf = b"\x00\x02a.txt\x00netascii\x00";
+    let req = Packet::Write { file: "a.txt".to_string(), blksize: None };
+    assert_eq!(Packet::parse(buf), Some(req));
+
+    let data = Packet::Data { block: 258, data: b"Hello".to_vec() };
+    assert_eq!(data.to_bytes(), b"\x00\x03\x01\x02Hello".to_vec());
+    assert_eq!(Packet::parse(&data.to_bytes()), Some(data));
+
+    let oack = Packet::OptionAck { blksize: Some(1428) };
+    assert_eq!(oack.to_bytes(), b"\x00\x06blksize\x001428\x00".to_vec());
+    assert_eq!(Packet::parse(&oack.to_bytes()), Some(oack));
+
+    let buf = b"\x00\x05\x00\x01File not found\x00";
+    let err = Packet::Error { code: 1, msg: "File not found".to_string() };
+    assert_eq!(Packet::parse(buf), Some(err));
+    assert_eq!(Packet::parse(b"\x00\x09"), None);
+}
+
+#[test_case]
+fn test_join_path() {
+    assert_eq!(join_path("/srv/", "/boot.sh"), Some("/srv/boot.sh".into()));
+    assert_eq!(join_path("/srv", "a/b"), Some("/srv/a/b".into()));
+    assert_eq!(join_path("/srv", "../ini/users.csv"), None);
+}
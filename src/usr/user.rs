@@ -15,7 +15,7 @@ use core::convert::TryInto;
 use core::str;
 use sha2::Sha256;
 
-const USERS: &str = "/ini/users.csv";
+pub const USERS: &str = "/ini/users.csv";
 const DISABLE_EMPTY_PASSWORD: bool = false;
 
 pub fn main(args: &[&str]) -> Result<(), ExitCode> {
@@ -136,7 +136,7 @@ fn create(username: &str) -> Result<(), ExitCode> {
     Ok(())
 }
 
-fn check(password: &str, hashed_password: &str) -> Result<(), ()> {
+pub fn check(password: &str, hashed_password: &str) -> Result<(), ()> {
     let fields: Vec<_> = hashed_password.split('$').collect();
     if fields.len() != 4 || fields[0] != "1" {
         return Err(());
@@ -209,7 +209,7 @@ fn read_hashed_passwords() -> BTreeMap<String, String> {
     hashed_passwords
 }
 
-fn hashed_password(username: &str) -> Option<String> {
+pub fn hashed_password(username: &str) -> Option<String> {
     read_hashed_passwords().get(username).map(|hash| hash.into())
 }
 